@@ -1,10 +1,14 @@
 use crate::geometry::Mesh;
 use crate::material::{ShaderDragon, PathPattern};
-use crate::material::ShaderLit;
-use crate::material::ShaderUnlit;
-use crate::world::{Node, NodeRef, Renderer};
+use crate::material::{ShaderLit, ShaderUnlit};
+use crate::material::{BlendMode, Material, SamplerSettings, TextureKind};
+use crate::world::{
+    BloomSettings, CameraInput, CameraMode, CameraRig, Clock, Director, EmitterRef,
+    DebugSettings, EmitterSettings, EnvironmentSource, FogSettings, Node, NodeRef, ParticleBlend,
+    ParticleEmitter, PROFILE_HISTORY, ProfileReport, Projection, Renderer, ShotTargets, Sky, Sun,
+    ToneMapping, Tonemapper, Trail, TrailRef, TrailSettings,
+};
 use glam::{Quat, Vec3, Vec4};
-use splines::{Interpolation, Key, Spline};
 use std::f32::consts::PI;
 use std::rc::Rc;
 use std::sync::Arc;
@@ -15,37 +19,110 @@ use web_time::Instant;
 use winit::application::ApplicationHandler;
 use winit::event::ElementState;
 use winit::event::{StartCause, WindowEvent};
-use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy};
+use winit::event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy};
 use winit::keyboard::KeyCode;
 use winit::keyboard::PhysicalKey;
 use winit::window::{Window, WindowId};
-use winit::event::MouseScrollDelta;
 
 const LIGHT_RADIUS: f32 = 100.0;
-const LIGHT_INTENSITY: f32 = 150.0;
+const LIGHT_RANGE: f32 = 400.0;
+const LIGHT_INTENSITY: f32 = 12000.0;
+/// Bright enough for the light cubes to bloom
+const LIGHT_GLOW: f32 = 12.0;
 const WINDOW_WIDTH: u32 = 1024;
 const WINDOW_HEIGHT: u32 = 768;
+const GROUND_HEIGHT: f32 = -80.0;
+const GROUND_SIZE: f32 = 400.0;
+/// Points sampled along the path for each trail
+const TRAIL_POINTS: usize = 64;
+/// Cubes the benchmark grid starts out with
+const BENCHMARK_COUNT: usize = 4096;
+const BENCHMARK_SPACING: f32 = 5.0;
 
 pub struct App {
     window: Option<Arc<Window>>,
-    start_time_stamp: Instant,
+    clock: Clock,
     renderer: Option<Renderer>,
     lights: Vec<(NodeRef, NodeRef, u128)>,
     event_loop: Option<EventLoopProxy<Renderer>>,
     dragon_shader: Option<Rc<ShaderDragon>>,
+    dragon_head: f32,
+    dragon_tail: f32,
+    dragon_radius: f32,
+    // position and heading of the head at the last fixed step
+    dragon_pose: Option<(Vec3, Vec3)>,
+    // follows the tip of the dragon's tail along the path
+    tail_emitter: Option<NodeRef>,
     selected_pattern: PathPattern,
+    camera_rig: CameraRig,
+    camera_input: CameraInput,
+    director: Director,
+    dolly_zoom: bool,
+    sun: Sun,
+    // azimuth and elevation the sun shines from
+    sun_angles: (f32, f32),
+    tone_mapping: ToneMapping,
+    bloom: BloomSettings,
+    sky: Sky,
+    fog: FogSettings,
+    debug: DebugSettings,
+    environment_intensity: f32,
+    // equirectangular map or cube map directory typed in to be loaded
+    environment_path: String,
+    environment_error: Option<String>,
+    // entities whose material can be edited, by name
+    entities: Vec<(String, NodeRef)>,
+    selected_entity: usize,
+    emitters: Vec<(String, EmitterRef)>,
+    selected_emitter: usize,
+    // trails behind points of the dragon's model, sampled back along the path
+    trails: Vec<(String, TrailRef, Vec3)>,
+    selected_trail: usize,
+    // grid of cubes for measuring the cost of many entities, filled and
+    // emptied from the ui
+    benchmark: NodeRef,
+    benchmark_count: usize,
+    benchmark_assets: Option<(Rc<Mesh>, Rc<ShaderLit>)>,
 }
 
 impl App {
     pub fn new(event_loop: &EventLoop<Renderer>) -> Self {
         Self {
             window: None,
-            start_time_stamp: Instant::now(),
+            clock: Clock::new(),
             renderer: None,
             lights: Vec::new(),
             event_loop: Some(event_loop.create_proxy()),
             dragon_shader: None,
+            dragon_head: 0.0,
+            dragon_tail: 0.0,
+            dragon_radius: 0.0,
+            dragon_pose: None,
+            tail_emitter: None,
             selected_pattern: PathPattern::Random,
+            camera_rig: CameraRig::new(),
+            camera_input: CameraInput::new(),
+            director: Director::new(),
+            dolly_zoom: false,
+            sun: Sun::default(),
+            sun_angles: (0.6, 1.1),
+            tone_mapping: ToneMapping::default(),
+            bloom: BloomSettings::default(),
+            sky: Sky::default(),
+            fog: FogSettings::default(),
+            debug: DebugSettings::default(),
+            environment_intensity: 1.0,
+            environment_path: String::new(),
+            environment_error: None,
+            entities: Vec::new(),
+            selected_entity: 0,
+            emitters: Vec::new(),
+            selected_emitter: 0,
+            trails: Vec::new(),
+            selected_trail: 0,
+            benchmark: Node::new(),
+            benchmark_count: BENCHMARK_COUNT,
+            benchmark_assets: None,
         }
     }
 }
@@ -67,8 +144,43 @@ impl App {
             &renderer.device,
         ));
         log::info!("loaded mesh in {:?}", app_init_timestamp.elapsed());
+        // the dragon flies towards +x, so its head is the furthest vertex along x
+        let (tail, head) = dragon_mesh
+            .vertices
+            .iter()
+            .map(|v| v.position[0])
+            .fold((0.0, 0.0), |(min, max), x| (f32::min(min, x), f32::max(max, x)));
+        self.dragon_head = head;
+        self.dragon_tail = tail;
+        self.dragon_radius = (head - tail) * 0.5;
         let dragon = Node::new_entity(dragon_mesh.clone(), shader.clone());
-        renderer.add(dragon);
+        dragon.borrow_mut().material = Material::new(Vec4::ONE, 0.3, 0.4);
+        let textures = &mut renderer.textures;
+        let scales = textures.load_bytes(
+            &renderer.device,
+            &renderer.queue,
+            "dragon-scales",
+            include_bytes!("assets/dragon-scales.png"),
+            TextureKind::Color,
+            SamplerSettings::default(),
+        );
+        let scales_normal = textures.load_bytes(
+            &renderer.device,
+            &renderer.queue,
+            "dragon-scales-normal",
+            include_bytes!("assets/dragon-scales-normal.png"),
+            TextureKind::Data,
+            SamplerSettings::default(),
+        );
+        match (scales, scales_normal) {
+            (Ok(base_color), Ok(normal)) => {
+                let set = textures.create_set(&renderer.device, Some(&base_color), Some(&normal));
+                dragon.borrow_mut().textures = Some(set);
+            }
+            (Err(e), _) | (_, Err(e)) => log::error!("Failed to load dragon scales, error {e:?}"),
+        }
+        renderer.add(dragon.clone());
+        self.entities.push(("Dragon".to_string(), dragon));
         let lights = vec![
             (
                 wgpu::Color {
@@ -77,7 +189,7 @@ impl App {
                     b: 1.0,
                     a: 1.0,
                 },
-                LIGHT_RADIUS,
+                LIGHT_RANGE,
                 LIGHT_INTENSITY,
                 6000,
             ),
@@ -88,7 +200,7 @@ impl App {
                     b: 0.5,
                     a: 0.8,
                 },
-                LIGHT_RADIUS,
+                LIGHT_RANGE,
                 LIGHT_INTENSITY,
                 1000,
             ),
@@ -99,7 +211,7 @@ impl App {
                     b: 0.7,
                     a: 0.8,
                 },
-                LIGHT_RADIUS,
+                LIGHT_RANGE,
                 LIGHT_INTENSITY,
                 4200,
             ),
@@ -110,7 +222,7 @@ impl App {
                     b: 1.0,
                     a: 0.8,
                 },
-                LIGHT_RADIUS,
+                LIGHT_RANGE,
                 LIGHT_INTENSITY,
                 8400,
             ),
@@ -120,86 +232,262 @@ impl App {
         self.lights = lights
             .into_iter()
             .map(|(color, radius, intensity, time_offset)| {
-                let light = Node::new_light(color, radius * intensity);
+                let light = Node::new_light(color, radius, intensity);
                 renderer.add(light.clone());
-                let cube = Node::new_entity(cube_mesh.clone(), shader_lit.clone());
+                // the light itself, nothing shades it
+                let cube = Node::new_entity(cube_mesh.clone(), shader_unlit.clone());
+                let glow = Vec3::new(color.r as f32, color.g as f32, color.b as f32);
+                cube.borrow_mut().material = Material::emissive(glow, LIGHT_GLOW);
                 cube.borrow_mut().translate(0.0, -2.0, 0.0);
+                // the cube sits right next to its light and would shadow everything
+                cube.borrow_mut().cast_shadow = false;
                 light.borrow_mut().add_child(cube.clone());
                 (light, cube, time_offset)
             })
             .collect();
-        const DEBUG_SPLINE: bool = false;
-        if DEBUG_SPLINE {
-            // infinity symbol oo, span from -3 -> 3
-            let points: Vec<Vec3> = vec![
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(2.0, 1.0, 0.0),
-                Vec3::new(3.0, 0.0, 0.0),
-                Vec3::new(2.0, -1.0, 0.0),
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(-2.0, 1.0, 0.0),
-                Vec3::new(-3.0, 0.0, 0.0),
-                Vec3::new(-2.0, -1.0, 0.0),
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(2.0, 0.0, 1.0),
-                Vec3::new(3.0, 0.0, 0.0),
-                Vec3::new(2.0, 0.0, -1.0),
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(-2.0, 0.0, 1.0),
-                Vec3::new(-3.0, 0.0, 0.0),
-                Vec3::new(-2.0, 0.0, -1.0),
-            ];
-            let n = points.len();
-            let i0 = 1;
-            let points = points
-                .into_iter()
-                .cycle()
-                .skip(n - 1)
-                .take(n + 3)
-                .enumerate()
-                .map(|(i, v)| ((i as f32 - i0 as f32) / n as f32, v))
-                .map(|(k, v)| Key::new(k, v, Interpolation::CatmullRom));
-            let spline = Spline::from_iter(points);
-            const CURVE_SCALE: f32 = 20.0;
-            let n = 100;
-            let normalize = |i, n| (i % n) as f32 / n as f32;
-            for i in 0..n {
-                let t1 = normalize(i, n);
-                let t2 = normalize(i + 1, n);
-                let p1 = spline.clamped_sample(t1).unwrap_or_default() * CURVE_SCALE;
-                let p2 = spline.clamped_sample(t2).unwrap_or_default() * CURVE_SCALE;
-                let rotation = Quat::from_rotation_arc(Vec3::X, (p2 - p1).normalize());
-                let r = (t1 * 256.0) as u32;
-                let g = r;
-                let b = r;
-                let col = 0xff + (b << 8) + (g << 16) + (r << 24);
-                let cube_mesh = Rc::new(Mesh::new_cube(col, &renderer.device));
-                let cube = Node::new_entity(cube_mesh.clone(), shader_unlit.clone());
-                cube.borrow_mut().translate(p1.x, p1.y, p1.z);
-                cube.borrow_mut().rotate_quat(rotation);
-                cube.borrow_mut().scale(0.2, 1.0, 1.0);
-                renderer.add(cube.clone());
+        let ground_mesh = Rc::new(Mesh::new_plane(GROUND_SIZE, 0x505058ff, &renderer.device));
+        let ground = Node::new_entity(ground_mesh, shader_lit.clone());
+        ground.borrow_mut().translate(0.0, 0.0, GROUND_HEIGHT);
+        ground.borrow_mut().material = Material::new(Vec4::ONE, 0.0, 0.9);
+        renderer.add(ground.clone());
+        self.entities.push(("Ground".to_string(), ground));
+        for (i, (_, cube, _)) in self.lights.iter().enumerate() {
+            self.entities.push((format!("Light {}", i + 1), cube.clone()));
+        }
+        let column_mesh = Rc::new(Mesh::new_cube(0xffffffff, &renderer.device));
+        let columns = [
+            ("Blue glass", -60.0, BlendMode::Alpha, Material {
+                base_color: Vec4::new(0.4, 0.7, 1.0, 0.3),
+                ..Material::new(Vec4::ONE, 0.0, 0.05)
+            }),
+            ("Amber glass", 0.0, BlendMode::Alpha, Material {
+                base_color: Vec4::new(1.0, 0.6, 0.2, 0.5),
+                ..Material::new(Vec4::ONE, 0.0, 0.2)
+            }),
+            ("Glow", 60.0, BlendMode::Additive, Material {
+                base_color: Vec4::new(0.0, 0.0, 0.0, 0.3),
+                ..Material::emissive(Vec3::new(0.3, 1.0, 0.5), 4.0)
+            }),
+        ];
+        for (name, x, blend, material) in columns {
+            let column = Node::new_entity(column_mesh.clone(), shader_lit.clone());
+            {
+                let mut column = column.borrow_mut();
+                column.translate(x, 80.0, GROUND_HEIGHT + 30.0);
+                column.scale(5.0, 5.0, 30.0);
+                column.material = material;
+                column.blend = blend;
+                // shadow maps have no translucency, they would cast solid shadows
+                column.cast_shadow = false;
             }
+            renderer.add(column.clone());
+            self.entities.push((name.to_string(), column));
+        }
+        renderer.add(self.benchmark.clone());
+        self.benchmark_assets = Some((column_mesh, shader_lit.clone()));
+        let trail = ParticleEmitter::new(
+            EmitterSettings {
+                rate: 200.0,
+                lifetime: 1.5,
+                speed: 8.0,
+                direction: -Vec3::X,
+                spread: 25.0,
+                spawn_radius: 2.0,
+                drag: 1.5,
+                gravity: Vec3::new(0.0, 0.0, -3.0),
+                start_color: Vec4::new(1.0, 0.55, 0.2, 1.0),
+                end_color: Vec4::new(0.8, 0.1, 0.05, 0.0),
+                intensity: 4.0,
+                start_size: 3.0,
+                end_size: 0.5,
+                ..Default::default()
+            },
+            512,
+        );
+        let tail_emitter = Node::new_emitter(trail.clone());
+        renderer.add(tail_emitter.clone());
+        self.tail_emitter = Some(tail_emitter);
+        self.emitters.push(("Tail trail".to_string(), trail));
+        let sparks = ParticleEmitter::new(
+            EmitterSettings {
+                rate: 60.0,
+                lifetime: 1.0,
+                speed: 25.0,
+                spread: 180.0,
+                drag: 2.0,
+                gravity: Vec3::new(0.0, 0.0, -20.0),
+                start_color: Vec4::new(0.5, 0.8, 1.0, 1.0),
+                end_color: Vec4::new(0.2, 0.4, 1.0, 0.0),
+                intensity: 6.0,
+                start_size: 1.5,
+                end_size: 0.3,
+                ..Default::default()
+            },
+            128,
+        );
+        if let Some((light, _, _)) = self.lights.get(1) {
+            light.borrow_mut().add_child(Node::new_emitter(sparks.clone()));
+        }
+        self.emitters.push(("Light sparks".to_string(), sparks));
+        let clouds = ParticleEmitter::new(
+            EmitterSettings {
+                rate: 8.0,
+                lifetime: 20.0,
+                speed: 1.0,
+                spread: 180.0,
+                spawn_radius: 150.0,
+                drag: 0.0,
+                gravity: Vec3::new(0.0, 0.0, 0.1),
+                start_color: Vec4::new(0.7, 0.72, 0.75, 0.35),
+                end_color: Vec4::new(0.7, 0.72, 0.75, 0.0),
+                start_size: 60.0,
+                end_size: 90.0,
+                fade_in: 0.2,
+                blend: ParticleBlend::Alpha,
+                ..Default::default()
+            },
+            256,
+        );
+        let cloud_bank = Node::new_emitter(clouds.clone());
+        cloud_bank.borrow_mut().translate(0.0, 0.0, GROUND_HEIGHT + 30.0);
+        renderer.add(cloud_bank);
+        self.emitters.push(("Clouds".to_string(), clouds));
+        let head_trail = Trail::new(
+            TrailSettings {
+                duration: 2.5,
+                width: 6.0,
+                end_width: 0.3,
+                color: Vec3::new(0.4, 0.8, 1.0),
+                intensity: 3.0,
+                streaks: 0.6,
+                ..Default::default()
+            },
+            TRAIL_POINTS,
+        );
+        let tail_trail = Trail::new(
+            TrailSettings {
+                duration: 1.0,
+                width: 5.0,
+                color: Vec3::new(1.0, 0.5, 0.2),
+                intensity: 3.0,
+                ..Default::default()
+            },
+            TRAIL_POINTS,
+        );
+        // above the head, the body would hide a trail along its middle
+        let head_point = Vec3::new(self.dragon_head, 8.0, 0.0);
+        let tail_point = Vec3::new(self.dragon_tail, 0.0, 0.0);
+        let trails = [("Head", head_trail, head_point), ("Tail", tail_trail, tail_point)];
+        for (name, trail, point) in trails {
+            renderer.add(Node::new_trail(trail.clone()));
+            self.trails.push((name.to_string(), trail, point));
         }
         log::info!("app initialized in {:?}", app_init_timestamp.elapsed());
     }
-    pub fn update(&mut self, time: f32) {
+    /// Move what is drawn to the simulated `time`, `delta_time` later than
+    /// the last frame, both in milliseconds.
+    pub fn update(&mut self, time: f64, delta_time: f64) {
         for (light, cube, time_offset) in self.lights.iter_mut() {
-            let time = time + *time_offset as f32;
-            let rx = PI * 2.0 * (0.00042 * time as f64).sin() as f32;
-            let ry = PI * 2.0 * (0.00011 * time as f64).sin() as f32;
-            let rz = PI * 2.0 * (0.00027 * time as f64).sin() as f32;
+            let time = time + *time_offset as f64;
+            let rx = PI * 2.0 * (0.00042 * time).sin() as f32;
+            let ry = PI * 2.0 * (0.00011 * time).sin() as f32;
+            let rz = PI * 2.0 * (0.00027 * time).sin() as f32;
             cube.borrow_mut().rotate(rx, ry, rz);
-            let x = (0.00058 * time as f64).sin() as f32;
-            let y = (0.00076 * time as f64).sin() as f32;
-            let z = (0.00042 * time as f64).sin() as f32;
+            let x = (0.00058 * time).sin() as f32;
+            let y = (0.00076 * time).sin() as f32;
+            let z = (0.00042 * time).sin() as f32;
             let v = Vec4::new(x, y, z, 1.0).normalize() * LIGHT_RADIUS;
             light.borrow_mut().translate(v.x, v.y, v.z);
         }
         let Some(renderer) = self.renderer.as_mut() else {
             return;
         };
-        renderer.time = time;
+        renderer.time = match self.dragon_shader.as_ref() {
+            Some(shader) => shader.wrap_time(time),
+            None => time as f32,
+        };
+        renderer.delta_time += (delta_time / 1000.0) as f32;
+        if let Some(shader) = self.dragon_shader.as_ref() {
+            for (_, trail, point) in &self.trails {
+                let mut trail = trail.borrow_mut();
+                let spacing = trail.settings.duration * 1000.0 / (trail.capacity() - 1) as f32;
+                let points = (0..trail.capacity())
+                    .map(|i| shader.sample_point(*point, renderer.time - i as f32 * spacing))
+                    .collect::<Vec<_>>();
+                trail.set_points(points);
+            }
+            if let Some(emitter) = self.tail_emitter.as_ref() {
+                let (tail, heading) = shader.sample_path(self.dragon_tail, renderer.time);
+                let mut emitter = emitter.borrow_mut();
+                emitter.translate(tail.x, tail.y, tail.z);
+                emitter.rotate_quat(Quat::from_rotation_arc(Vec3::X, heading));
+            }
+        }
+    }
+
+    /// Sample where the dragon's head is at the fixed step `time`, for the
+    /// camera to follow.
+    fn step(&mut self, time: f64) {
+        if let Some(shader) = self.dragon_shader.as_ref() {
+            self.dragon_pose = Some(shader.sample_path(self.dragon_head, shader.wrap_time(time)));
+        }
+    }
+
+    /// Move the camera towards the dragon by `dt` seconds of wall clock
+    /// time, so it keeps following while the simulation is slowed down or
+    /// paused.
+    fn update_camera(&mut self, dt: f32) {
+        let Some(renderer) = self.renderer.as_mut() else {
+            return;
+        };
+        if renderer.camera.free_fly {
+            renderer.camera.fly.update(dt);
+        }
+        let Some((head, heading)) = self.dragon_pose else {
+            return;
+        };
+        if self.director.enabled {
+            let targets = ShotTargets {
+                head,
+                dragon_radius: self.dragon_radius,
+                lights: self
+                    .lights
+                    .iter()
+                    .map(|(light, _, _)| light.borrow().translation)
+                    .collect(),
+            };
+            let aspect_ratio = renderer.config.width as f32 / renderer.config.height as f32;
+            self.director.update(&mut renderer.camera, &targets, aspect_ratio, dt);
+        } else {
+            self.camera_rig.update(&mut renderer.camera, head, heading, dt);
+        }
+    }
+
+    /// Fill the benchmark grid with `count` cubes just above the ground,
+    /// replacing the ones there were. 0 empties it.
+    fn build_benchmark(&mut self, count: usize) {
+        let mut group = self.benchmark.borrow_mut();
+        group.children.clear();
+        let Some((mesh, shader)) = &self.benchmark_assets else {
+            return;
+        };
+        let side = (count as f32).sqrt().ceil().max(1.0) as usize;
+        let half = (side - 1) as f32 * 0.5;
+        for i in 0..count {
+            let (row, column) = ((i / side) as f32, (i % side) as f32);
+            let cube = Node::new_entity(mesh.clone(), shader.clone());
+            {
+                let mut cube = cube.borrow_mut();
+                let (x, y) = ((column - half) * BENCHMARK_SPACING, (row - half) * BENCHMARK_SPACING);
+                cube.translate(x, y, GROUND_HEIGHT + 10.0);
+                let color = Vec4::new(column / side as f32, row / side as f32, 0.6, 1.0);
+                cube.material = Material::new(color, 0.0, 0.5);
+                // thousands of casters would measure the shadow passes instead
+                cube.cast_shadow = false;
+            }
+            group.add_child(cube);
+        }
+        log::info!("benchmark grid of {count} cubes");
     }
 
     fn regenerate_dragon_path(&mut self) {
@@ -269,8 +557,13 @@ impl ApplicationHandler<Renderer> for App {
     }
     fn new_events(&mut self, _event_loop: &ActiveEventLoop, cause: StartCause) {
         if cause == StartCause::Poll {
-            let time = self.start_time_stamp.elapsed().as_millis() as f32;
-            self.update(time);
+            // drawn in between fixed steps, so frames that are not a whole
+            // step apart still move on smoothly
+            if self.clock.tick() > 0 {
+                self.step(self.clock.time());
+            }
+            self.update(self.clock.interpolated_time(), self.clock.delta_time());
+            self.update_camera((self.clock.frame_time() / 1000.0) as f32);
             let Some(window) = self.window.as_ref() else {
                 return;
             };
@@ -305,6 +598,12 @@ impl ApplicationHandler<Renderer> for App {
         };
         // Let egui handle the event first and check if it wants to consume it
         let egui_consumed = renderer.handle_input(&event);
+        self.camera_input.handle(
+            &event,
+            egui_consumed,
+            &mut renderer.camera,
+            &mut self.camera_rig,
+        );
         // Only process events if egui didn't consume them
         match event {
             WindowEvent::RedrawRequested => {
@@ -312,12 +611,49 @@ impl ApplicationHandler<Renderer> for App {
                 let camera_distance = renderer.camera.distance;
                 let camera_azimuth = renderer.camera.azimuth;
                 let camera_elevation = renderer.camera.elevation;
+                let mut camera_mode = None;
+                let mut director_enabled = self.director.enabled;
+                let mut free_fly = renderer.camera.free_fly;
+                let fly = &mut renderer.camera.fly;
+                let mut fly_settings = (fly.speed, fly.acceleration, fly.roll_lock);
+                let camera = &renderer.camera;
+                let mut lens = (camera.projection, camera.fov, camera.near, camera.far);
+                let mut anti_aliasing = renderer.anti_aliasing;
+                let anti_aliasing_modes = renderer.anti_aliasing_modes.clone();
+                let (azimuth, elevation) = self.sun_angles;
+                let towards_sun = Vec3::new(
+                    elevation.cos() * azimuth.sin(),
+                    elevation.cos() * azimuth.cos(),
+                    elevation.sin(),
+                );
+                self.sun.direction = -towards_sun;
+                renderer.sun = self.sun;
+                renderer.tone_mapping = self.tone_mapping;
+                renderer.bloom = self.bloom;
+                renderer.sky = self.sky;
+                renderer.fog = self.fog;
+                renderer.debug = self.debug;
+                renderer.environment.intensity = self.environment_intensity;
+                let environment_source = renderer.environment.source().clone();
+                let particles_supported = renderer.particles.is_some();
+                let profile = renderer.profiler.report();
+                let mut profiling = renderer.profiler.enabled;
+                let gpu_culling_supported = renderer.gpu_culling_supported();
+                let mut cull_on_gpu = renderer.cull_on_gpu;
+                #[cfg(not(target_arch = "wasm32"))]
+                let mut load_environment = false;
+                let mut use_sky = false;
+                let mut benchmark = None;
                 renderer.draw(|ctx, regenerate_path| {
                     egui::Window::new("Debug Controls")
                         .default_pos([10.0, 10.0])
+                        .vscroll(true)
                         .show(ctx, |ui| {
                             ui.heading("Camera Controls");
-                            ui.label("Scroll: Zoom in/out");
+                            ui.label("Scroll / Pinch: Zoom in/out");
+                            ui.label("Left drag: Orbit");
+                            ui.label("Right / Middle drag: Pan");
+                            ui.label("Double click: Reset view");
                             ui.separator();
                             ui.heading("Dragon Path");
                             ui.label(format!("Current Pattern: {:?}", self.selected_pattern));
@@ -342,17 +678,400 @@ impl ApplicationHandler<Renderer> for App {
                                 }
                             });
                             ui.separator();
+                            ui.heading("Time");
+                            ui.label(format!("Time: {:.1}s", self.clock.time() / 1000.0));
+                            ui.checkbox(&mut self.clock.paused, "Paused (P)");
+                            ui.add(egui::Slider::new(&mut self.clock.scale, 0.0..=4.0).text("Speed"));
+                            ui.separator();
+                            ui.heading("Director");
+                            ui.checkbox(&mut director_enabled, "Cinematic shots");
+                            ui.checkbox(&mut self.director.auto, "Auto-director");
+                            if let Some(shot) = self.director.current_shot() {
+                                ui.label(format!("Shot: {}", shot.name));
+                            }
+                            if ui.button("Next shot").clicked() {
+                                self.director.skip();
+                            }
+                            ui.separator();
                             ui.heading("Camera Settings");
+                            let mut mode = self.camera_rig.mode;
+                            egui::ComboBox::from_label("Mode")
+                                .selected_text(format!("{:?}", mode))
+                                .show_ui(ui, |ui| {
+                                    for option in CameraMode::ALL {
+                                        ui.selectable_value(&mut mode, option, format!("{:?}", option));
+                                    }
+                                });
+                            if mode != self.camera_rig.mode {
+                                camera_mode = Some(mode);
+                            }
+                            ui.add(egui::Slider::new(&mut self.camera_rig.stiffness, 0.5..=20.0).text("Stiffness"));
+                            ui.checkbox(&mut free_fly, "Free fly (F)");
+                            if free_fly {
+                                ui.label("WASD: Move, Q/E: Down/Up, Shift: Boost");
+                                ui.label("Left drag: Look, Z/C: Roll");
+                                let (speed, acceleration, roll_lock) = &mut fly_settings;
+                                ui.add(egui::Slider::new(speed, 1.0..=500.0).logarithmic(true).text("Fly speed"));
+                                ui.add(egui::Slider::new(acceleration, 1.0..=30.0).text("Acceleration"));
+                                ui.checkbox(roll_lock, "Roll lock");
+                            }
                             ui.label(format!("Distance: {:.1}", camera_distance));
                             ui.label(format!("Azimuth: {:.2}", camera_azimuth));
                             ui.label(format!("Elevation: {:.2}", camera_elevation));
+                            egui::CollapsingHeader::new("Lens").show(ui, |ui| {
+                                let (projection, fov, near, far) = &mut lens;
+                                ui.horizontal(|ui| {
+                                    ui.radio_value(projection, Projection::Perspective, "Perspective");
+                                    ui.radio_value(projection, Projection::Orthographic, "Orthographic");
+                                });
+                                ui.add(egui::Slider::new(fov, 0.1..=2.5).text("FOV (rad)"));
+                                // the other modes and the director place the camera themselves
+                                let orbiting = self.camera_rig.mode.is_orbit() && !director_enabled && !free_fly;
+                                ui.add_enabled(orbiting, egui::Checkbox::new(&mut self.dolly_zoom, "Dolly zoom"))
+                                    .on_disabled_hover_text("Only in the orbit modes");
+                                ui.add(egui::Slider::new(near, 0.01..=10.0).logarithmic(true).text("Near"));
+                                ui.add(egui::Slider::new(far, 100.0..=10000.0).logarithmic(true).text("Far"));
+                            });
+                            egui::CollapsingHeader::new("Sun & Shadows").show(ui, |ui| {
+                                ui.checkbox(&mut self.sun.enabled, "Sun");
+                                ui.checkbox(&mut self.sun.cast_shadow, "Sun casts shadows");
+                                ui.add(egui::Slider::new(&mut self.sun.intensity, 0.0..=10.0).text("Intensity"));
+                                let (azimuth, elevation) = &mut self.sun_angles;
+                                ui.add(egui::Slider::new(azimuth, -PI..=PI).text("Azimuth"));
+                                ui.add(egui::Slider::new(elevation, 0.05..=1.55).text("Elevation"));
+                                for (i, (light, _, _)) in self.lights.iter().enumerate() {
+                                    let mut light = light.borrow_mut();
+                                    ui.checkbox(&mut light.cast_shadow, format!("Light {} casts shadows", i + 1));
+                                }
+                            });
+                            egui::CollapsingHeader::new("Tone Mapping").show(ui, |ui| {
+                                let tone_mapping = &mut self.tone_mapping;
+                                egui::ComboBox::from_label("Tonemapper")
+                                    .selected_text(format!("{:?}", tone_mapping.tonemapper))
+                                    .show_ui(ui, |ui| {
+                                        for tonemapper in Tonemapper::ALL {
+                                            ui.selectable_value(
+                                                &mut tone_mapping.tonemapper,
+                                                tonemapper,
+                                                format!("{tonemapper:?}"),
+                                            );
+                                        }
+                                    });
+                                ui.add(egui::Slider::new(&mut tone_mapping.exposure, -5.0..=5.0).text("Exposure (EV)"));
+                                ui.add(egui::Slider::new(&mut tone_mapping.gamma, 1.0..=3.0).text("Gamma"));
+                            });
+                            egui::CollapsingHeader::new("Anti-aliasing").show(ui, |ui| {
+                                egui::ComboBox::from_label("Mode")
+                                    .selected_text(anti_aliasing.to_string())
+                                    .show_ui(ui, |ui| {
+                                        for mode in &anti_aliasing_modes {
+                                            ui.selectable_value(&mut anti_aliasing, *mode, mode.to_string());
+                                        }
+                                    });
+                            });
+                            egui::CollapsingHeader::new("Bloom").show(ui, |ui| {
+                                let bloom = &mut self.bloom;
+                                ui.checkbox(&mut bloom.enabled, "Bloom");
+                                ui.add(egui::Slider::new(&mut bloom.threshold, 0.0..=10.0).text("Threshold"));
+                                ui.add(egui::Slider::new(&mut bloom.knee, 0.0..=2.0).text("Knee"));
+                                ui.add(egui::Slider::new(&mut bloom.intensity, 0.0..=1.0).text("Intensity"));
+                                ui.add(egui::Slider::new(&mut bloom.radius, 0.5..=3.0).text("Radius"));
+                            });
+                            egui::CollapsingHeader::new("Environment").show(ui, |ui| {
+                                match &environment_source {
+                                    EnvironmentSource::Sky => {
+                                        ui.label("Source: procedural sky");
+                                    }
+                                    EnvironmentSource::Map(name) => {
+                                        ui.label(format!("Source: {name}"));
+                                        use_sky = ui.button("Use procedural sky").clicked();
+                                    }
+                                }
+                                #[cfg(not(target_arch = "wasm32"))]
+                                ui.horizontal(|ui| {
+                                    ui.text_edit_singleline(&mut self.environment_path)
+                                        .on_hover_text("Equirectangular image, or a directory with px nx py ny pz nz faces");
+                                    load_environment = ui.button("Load").clicked();
+                                });
+                                if let Some(error) = &self.environment_error {
+                                    ui.colored_label(egui::Color32::RED, error);
+                                }
+                                ui.add(egui::Slider::new(&mut self.environment_intensity, 0.0..=4.0).text("Intensity"));
+                                let sky = &mut self.sky;
+                                for (color, label) in [
+                                    (&mut sky.zenith_color, "Zenith"),
+                                    (&mut sky.horizon_color, "Horizon"),
+                                    (&mut sky.ground_color, "Ground"),
+                                ] {
+                                    ui.horizontal(|ui| {
+                                        let mut rgb = color.to_array();
+                                        if ui.color_edit_button_rgb(&mut rgb).changed() {
+                                            *color = Vec3::from_array(rgb);
+                                        }
+                                        ui.label(label);
+                                    });
+                                }
+                                ui.add(egui::Slider::new(&mut sky.sun_size, 0.1..=5.0).text("Sun size (deg)"));
+                                ui.add(egui::Slider::new(&mut sky.sun_disk_intensity, 0.0..=100.0).text("Sun disk"));
+                                ui.add(egui::Slider::new(&mut sky.sun_glow, 0.0..=2.0).text("Sun glow"));
+                            });
+                            egui::CollapsingHeader::new("Fog").show(ui, |ui| {
+                                let fog = &mut self.fog;
+                                ui.checkbox(&mut fog.enabled, "Fog");
+                                ui.horizontal(|ui| {
+                                    let mut rgb = fog.color.to_array();
+                                    if ui.color_edit_button_rgb(&mut rgb).changed() {
+                                        fog.color = Vec3::from_array(rgb);
+                                    }
+                                    ui.label("Color");
+                                });
+                                ui.add(egui::Slider::new(&mut fog.sky_blend, 0.0..=1.0).text("Sky blend"));
+                                ui.add(egui::Slider::new(&mut fog.density, 0.0..=0.02).logarithmic(true).text("Density"));
+                                ui.add(egui::Slider::new(&mut fog.height_density, 0.0..=0.2).logarithmic(true).text("Height density"));
+                                ui.add(egui::Slider::new(&mut fog.height_falloff, 0.0..=0.5).text("Height falloff"));
+                                ui.add(egui::Slider::new(&mut fog.base_height, -200.0..=200.0).text("Base height"));
+                                ui.add(egui::Slider::new(&mut fog.aerial_density, 0.0..=0.02).logarithmic(true).text("Aerial perspective"));
+                            });
+                            egui::CollapsingHeader::new("Particles").show(ui, |ui| {
+                                if !particles_supported {
+                                    ui.label("Compute shaders are not supported by this adapter");
+                                    return;
+                                }
+                                let selected = self.selected_emitter.min(self.emitters.len().saturating_sub(1));
+                                let Some((name, emitter)) = self.emitters.get(selected) else {
+                                    return;
+                                };
+                                egui::ComboBox::from_label("Emitter")
+                                    .selected_text(name.as_str())
+                                    .show_ui(ui, |ui| {
+                                        for (i, (name, _)) in self.emitters.iter().enumerate() {
+                                            ui.selectable_value(&mut self.selected_emitter, i, name.as_str());
+                                        }
+                                    });
+                                let mut emitter = emitter.borrow_mut();
+                                ui.label(format!("Capacity: {} particles", emitter.capacity()));
+                                let settings = &mut emitter.settings;
+                                ui.checkbox(&mut settings.enabled, "Emitting");
+                                egui::ComboBox::from_label("Blend")
+                                    .selected_text(settings.blend.to_string())
+                                    .show_ui(ui, |ui| {
+                                        for blend in [ParticleBlend::Additive, ParticleBlend::Alpha] {
+                                            ui.selectable_value(&mut settings.blend, blend, blend.to_string());
+                                        }
+                                    });
+                                ui.add(egui::Slider::new(&mut settings.rate, 0.0..=1000.0).logarithmic(true).text("Rate (per s)"));
+                                ui.add(egui::Slider::new(&mut settings.lifetime, 0.1..=30.0).logarithmic(true).text("Lifetime (s)"));
+                                ui.add(egui::Slider::new(&mut settings.speed, 0.0..=100.0).text("Speed"));
+                                ui.add(egui::Slider::new(&mut settings.spread, 0.0..=180.0).text("Spread (deg)"));
+                                ui.add(egui::Slider::new(&mut settings.spawn_radius, 0.0..=200.0).text("Spawn radius"));
+                                ui.add(egui::Slider::new(&mut settings.drag, 0.0..=10.0).text("Drag"));
+                                ui.horizontal(|ui| {
+                                    ui.add(egui::DragValue::new(&mut settings.gravity.x).speed(0.1));
+                                    ui.add(egui::DragValue::new(&mut settings.gravity.y).speed(0.1));
+                                    ui.add(egui::DragValue::new(&mut settings.gravity.z).speed(0.1));
+                                    ui.label("Gravity");
+                                });
+                                for (color, label) in [
+                                    (&mut settings.start_color, "Start color"),
+                                    (&mut settings.end_color, "End color"),
+                                ] {
+                                    ui.horizontal(|ui| {
+                                        let mut rgba = color.to_array();
+                                        if ui.color_edit_button_rgba_unmultiplied(&mut rgba).changed() {
+                                            *color = Vec4::from_array(rgba);
+                                        }
+                                        ui.label(label);
+                                    });
+                                }
+                                ui.add(egui::Slider::new(&mut settings.intensity, 0.0..=20.0).text("Intensity"));
+                                ui.add(egui::Slider::new(&mut settings.start_size, 0.0..=200.0).logarithmic(true).text("Start size"));
+                                ui.add(egui::Slider::new(&mut settings.end_size, 0.0..=200.0).logarithmic(true).text("End size"));
+                                ui.add(egui::Slider::new(&mut settings.fade_in, 0.0..=1.0).text("Fade in"));
+                            });
+                            egui::CollapsingHeader::new("Trails").show(ui, |ui| {
+                                let selected = self.selected_trail.min(self.trails.len().saturating_sub(1));
+                                let Some((name, trail, _)) = self.trails.get(selected) else {
+                                    return;
+                                };
+                                egui::ComboBox::from_label("Trail")
+                                    .selected_text(name.as_str())
+                                    .show_ui(ui, |ui| {
+                                        for (i, (name, _, _)) in self.trails.iter().enumerate() {
+                                            ui.selectable_value(&mut self.selected_trail, i, name.as_str());
+                                        }
+                                    });
+                                let settings = &mut trail.borrow_mut().settings;
+                                ui.checkbox(&mut settings.enabled, "Visible");
+                                ui.add(egui::Slider::new(&mut settings.duration, 0.1..=5.0).text("Duration (s)"));
+                                ui.add(egui::Slider::new(&mut settings.width, 0.0..=30.0).text("Width"));
+                                ui.add(egui::Slider::new(&mut settings.end_width, 0.0..=2.0).text("End width"));
+                                ui.horizontal(|ui| {
+                                    let mut rgb = settings.color.to_array();
+                                    if ui.color_edit_button_rgb(&mut rgb).changed() {
+                                        settings.color = Vec3::from_array(rgb);
+                                    }
+                                    ui.label("Color");
+                                });
+                                ui.add(egui::Slider::new(&mut settings.intensity, 0.0..=20.0).text("Intensity"));
+                                ui.add(egui::Slider::new(&mut settings.start_alpha, 0.0..=1.0).text("Start alpha"));
+                                ui.add(egui::Slider::new(&mut settings.end_alpha, 0.0..=1.0).text("End alpha"));
+                                ui.add(egui::Slider::new(&mut settings.uv_scale, 0.0..=0.5).text("Streaks per unit"));
+                                ui.add(egui::Slider::new(&mut settings.scroll_speed, -10.0..=10.0).text("Scroll speed"));
+                                ui.add(egui::Slider::new(&mut settings.streaks, 0.0..=1.0).text("Streaks"));
+                            });
+                            egui::CollapsingHeader::new("Materials").show(ui, |ui| {
+                                let selected = self.selected_entity.min(self.entities.len().saturating_sub(1));
+                                let Some((name, node)) = self.entities.get(selected) else {
+                                    return;
+                                };
+                                egui::ComboBox::from_label("Entity")
+                                    .selected_text(name.as_str())
+                                    .show_ui(ui, |ui| {
+                                        for (i, (name, _)) in self.entities.iter().enumerate() {
+                                            ui.selectable_value(&mut self.selected_entity, i, name.as_str());
+                                        }
+                                    });
+                                let mut node = node.borrow_mut();
+                                egui::ComboBox::from_label("Entity blend mode")
+                                    .selected_text(node.blend.to_string())
+                                    .show_ui(ui, |ui| {
+                                        for blend in BlendMode::ALL {
+                                            ui.selectable_value(&mut node.blend, blend, blend.to_string());
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text("How this entity covers what is behind it");
+                                let material = &mut node.material;
+                                ui.horizontal(|ui| {
+                                    let mut base_color = material.base_color.to_array();
+                                    if ui.color_edit_button_rgba_unmultiplied(&mut base_color).changed() {
+                                        material.base_color = Vec4::from_array(base_color);
+                                    }
+                                    ui.label("Base color");
+                                });
+                                ui.add(egui::Slider::new(&mut material.metallic, 0.0..=1.0).text("Metallic"));
+                                ui.add(egui::Slider::new(&mut material.roughness, 0.0..=1.0).text("Roughness"));
+                                ui.add(egui::Slider::new(&mut material.normal_scale, 0.0..=2.0).text("Normal map"));
+                                ui.horizontal(|ui| {
+                                    let mut emissive = material.emissive.to_array();
+                                    if ui.color_edit_button_rgb(&mut emissive).changed() {
+                                        material.emissive = Vec3::from_array(emissive);
+                                    }
+                                    ui.label("Emissive");
+                                });
+                                ui.add(
+                                    egui::Slider::new(&mut material.emissive_strength, 0.0..=20.0)
+                                        .text("Emissive strength"),
+                                );
+                            });
+                            egui::CollapsingHeader::new("Debug").show(ui, |ui| {
+                                let debug = &mut self.debug;
+                                ui.checkbox(&mut debug.wireframe, "Wireframe");
+                                ui.checkbox(&mut debug.normals, "Normals");
+                                ui.add(egui::Slider::new(&mut debug.normal_length, 0.1..=10.0).text("Normal length"));
+                                ui.checkbox(&mut debug.path, "Path");
+                                ui.checkbox(&mut debug.frames, "Path frames");
+                                ui.add(egui::Slider::new(&mut debug.frame_step, 1..=128).text("Frame step"));
+                                ui.add(egui::Slider::new(&mut debug.frame_size, 0.5..=20.0).text("Frame size"));
+                                ui.checkbox(&mut debug.light_radius, "Light radius");
+                                ui.checkbox(&mut debug.axes, "World axes");
+                                ui.add(egui::Slider::new(&mut debug.axis_length, 1.0..=200.0).text("Axis length"));
+                                ui.checkbox(&mut debug.grid, "Grid");
+                                ui.add(egui::Slider::new(&mut debug.grid_size, 10.0..=500.0).text("Grid size"));
+                                ui.add(egui::Slider::new(&mut debug.grid_spacing, 1.0..=50.0).text("Grid spacing"));
+                                ui.checkbox(&mut debug.on_top, "Lines on top");
+                            });
+                            egui::CollapsingHeader::new("Profiler").show(ui, |ui| {
+                                ui.checkbox(&mut profiling, "Enabled");
+                                let counts = profile.counts;
+                                ui.label(format!("Draw calls: {}", counts.draw_calls));
+                                ui.label(format!("Triangles: {}", counts.triangles));
+                                ui.label(format!("Buffer writes: {}", counts.buffer_writes));
+                                ui.label(format!("Instances: {}", counts.instances));
+                                ui.label(format!("Culled: {}", counts.culled));
+                                if counts.dropped > 0 {
+                                    ui.colored_label(
+                                        egui::Color32::RED,
+                                        format!("Not drawn: {}", counts.dropped),
+                                    );
+                                }
+                                ui.label(format!("Frame buffers: {:.1} KiB", counts.frame_memory as f32 / 1024.0));
+                                profile_graph(ui, &profile);
+                                ui.label(format!("CPU: {:.2} ms", profile.cpu.average_total()));
+                                for (name, average) in profile.cpu.averages() {
+                                    ui.label(format!("  {name}: {average:.2} ms"));
+                                }
+                                match &profile.gpu {
+                                    Some(gpu) => {
+                                        ui.label(format!("GPU: {:.2} ms", gpu.average_total()));
+                                        for (name, average) in gpu.averages() {
+                                            ui.label(format!("  {name}: {average:.2} ms"));
+                                        }
+                                    }
+                                    None => {
+                                        ui.label("GPU: timestamp queries not supported");
+                                    }
+                                }
+                            });
+                            egui::CollapsingHeader::new("Benchmark").show(ui, |ui| {
+                                if gpu_culling_supported {
+                                    ui.checkbox(&mut cull_on_gpu, "Cull on the GPU");
+                                } else {
+                                    ui.label("Indirect draws are not supported, culling on the CPU");
+                                }
+                                let count = &mut self.benchmark_count;
+                                ui.add(egui::Slider::new(count, 1..=20000).logarithmic(true).text("Cubes"));
+                                ui.horizontal(|ui| {
+                                    if ui.button("Build grid").clicked() {
+                                        benchmark = Some(*count);
+                                    }
+                                    if ui.button("Clear").clicked() {
+                                        benchmark = Some(0);
+                                    }
+                                });
+                            });
                         });
                 });
 
+                renderer.anti_aliasing = anti_aliasing;
+                renderer.profiler.enabled = profiling;
+                renderer.cull_on_gpu = cull_on_gpu;
+                if use_sky {
+                    renderer.environment.use_sky();
+                    self.environment_error = None;
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                if load_environment {
+                    let path = self.environment_path.trim();
+                    let loaded = renderer.environment.load_file(&renderer.device, &renderer.queue, path);
+                    self.environment_error = loaded.err().map(|e| format!("{path}: {e}"));
+                }
+                let fly = &mut renderer.camera.fly;
+                (fly.speed, fly.acceleration, fly.roll_lock) = fly_settings;
+                renderer.camera.set_free_fly(free_fly);
+                let camera = &mut renderer.camera;
+                let (projection, fov, near, far) = lens;
+                (camera.projection, camera.near, camera.far) = (projection, near, far);
+                let orbiting = self.camera_rig.mode.is_orbit() && !self.director.enabled && !camera.free_fly;
+                if self.dolly_zoom && orbiting && fov != camera.fov {
+                    camera.dolly_zoom(fov);
+                } else {
+                    camera.fov = fov;
+                }
+                if self.director.enabled && !director_enabled {
+                    self.camera_rig.reset(&renderer.camera);
+                }
+                self.director.set_enabled(director_enabled, &renderer.camera);
+                if let Some(mode) = camera_mode {
+                    self.camera_rig.set_mode(mode, &renderer.camera);
+                }
                 if renderer.regenerate_path {
                     renderer.regenerate_path = false;
                     self.regenerate_dragon_path();
                 }
+                if let Some(count) = benchmark {
+                    self.build_benchmark(count);
+                }
             }
             WindowEvent::Resized(size) => renderer.resize(size.width, size.height),
             WindowEvent::KeyboardInput {
@@ -364,7 +1083,7 @@ impl ApplicationHandler<Renderer> for App {
                 match (event.physical_key, event.state) {
                     // space to restart animation
                     (PhysicalKey::Code(KeyCode::Space), ElementState::Released) => {
-                        self.start_time_stamp = Instant::now();
+                        self.clock.reset();
                     }
                     // escape to exit
                     (PhysicalKey::Code(KeyCode::Escape), ElementState::Released) => {
@@ -372,28 +1091,55 @@ impl ApplicationHandler<Renderer> for App {
                     }
                     // P to pause/play animation
                     (PhysicalKey::Code(KeyCode::KeyP), ElementState::Released) => {
-                        match event_loop.control_flow() {
-                            ControlFlow::Poll => event_loop.set_control_flow(ControlFlow::Wait),
-                            ControlFlow::Wait => event_loop.set_control_flow(ControlFlow::Poll),
-                            _ => {}
-                        }
+                        self.clock.paused = !self.clock.paused;
                     }
                     _ => {}
                 }
             }
-            WindowEvent::MouseWheel { delta, .. } => {
-                if !egui_consumed {
-                    match delta {
-                        MouseScrollDelta::LineDelta(_, y) => {
-                            renderer.camera.zoom(-y * 0.3);
-                        }
-                        MouseScrollDelta::PixelDelta(pos) => {
-                            renderer.camera.zoom(-pos.y as f32 * 0.003);
-                        }
-                    }
-                }
-            }
             _ => {}
         }
     }
 }
+
+/// Frame times of the last `PROFILE_HISTORY` frames, CPU in orange and GPU
+/// in blue, scaled to the slowest frame with a line at 60 fps.
+fn profile_graph(ui: &mut egui::Ui, profile: &ProfileReport) {
+    const FRAME_BUDGET: f32 = 1000.0 / 60.0;
+    let size = egui::vec2(ui.available_width().max(100.0), 80.0);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 2.0, egui::Color32::from_black_alpha(120));
+    let gpu_totals = profile.gpu.as_ref().map(|gpu| &gpu.totals);
+    let slowest = profile
+        .cpu
+        .totals
+        .iter()
+        .chain(gpu_totals.into_iter().flatten())
+        .fold(FRAME_BUDGET, |slowest, &total| slowest.max(total));
+    let to_screen = |i: usize, ms: f32| {
+        let x = rect.left() + rect.width() * i as f32 / (PROFILE_HISTORY - 1) as f32;
+        egui::pos2(x, rect.bottom() - rect.height() * ms / slowest)
+    };
+    let budget = to_screen(0, FRAME_BUDGET).y;
+    painter.hline(rect.x_range(), budget, (1.0, egui::Color32::DARK_GRAY));
+    let histories = [
+        (Some(&profile.cpu.totals), egui::Color32::from_rgb(255, 160, 60)),
+        (gpu_totals, egui::Color32::from_rgb(80, 160, 255)),
+    ];
+    for (totals, color) in histories {
+        let Some(totals) = totals else {
+            continue;
+        };
+        // the newest frame on the right edge
+        let start = PROFILE_HISTORY - totals.len();
+        let points = totals.iter().enumerate().map(|(i, &ms)| to_screen(start + i, ms)).collect();
+        painter.add(egui::Shape::line(points, (1.5, color)));
+    }
+    painter.text(
+        rect.left_top() + egui::vec2(4.0, 2.0),
+        egui::Align2::LEFT_TOP,
+        format!("{slowest:.1} ms"),
+        egui::FontId::monospace(10.0),
+        egui::Color32::LIGHT_GRAY,
+    );
+}
@@ -0,0 +1,294 @@
+use glam::{Mat4, Vec3, Vec4};
+
+/// Axis aligned box around a set of points.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// An empty box, growing it by any point gives a box around that point.
+    pub const EMPTY: Aabb = Aabb {
+        min: Vec3::INFINITY,
+        max: Vec3::NEG_INFINITY,
+    };
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(Self::EMPTY, Self::grow)
+    }
+
+    pub fn grow(self, point: Vec3) -> Self {
+        Self {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    /// The box around both.
+    pub fn union(self, other: Aabb) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Grow by `amount` on every side.
+    pub fn expand(self, amount: f32) -> Self {
+        Self {
+            min: self.min - amount,
+            max: self.max + amount,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// The box around this one after `transform`, which may be larger than
+    /// the transformed box itself when it rotates.
+    pub fn transform(&self, transform: &Mat4) -> Self {
+        let center = transform.transform_point3(self.center());
+        let half_extents = self.half_extents();
+        let extents = transform.x_axis.truncate().abs() * half_extents.x
+            + transform.y_axis.truncate().abs() * half_extents.y
+            + transform.z_axis.truncate().abs() * half_extents.z;
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+
+/// Sphere around a set of points, centered on their box, so not the
+/// smallest one but cheap to build.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let center = Aabb::from_points(points.iter().copied()).center();
+        let radius = points.iter().map(|p| p.distance(center)).fold(0.0, f32::max);
+        Self { center, radius }
+    }
+
+    /// The sphere through the corners of `aabb`.
+    pub fn from_aabb(aabb: &Aabb) -> Self {
+        Self {
+            center: aabb.center(),
+            radius: aabb.half_extents().length(),
+        }
+    }
+
+    /// The smallest sphere around both.
+    pub fn union(self, other: BoundingSphere) -> Self {
+        let offset = other.center - self.center;
+        let distance = offset.length();
+        if distance + other.radius <= self.radius {
+            return self;
+        }
+        if distance + self.radius <= other.radius {
+            return other;
+        }
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        Self {
+            center: self.center + offset * ((radius - self.radius) / distance),
+            radius,
+        }
+    }
+
+    /// The sphere after `transform`, grown by its largest scale.
+    pub fn transform(&self, transform: &Mat4) -> Self {
+        let scale = transform
+            .x_axis
+            .truncate()
+            .length()
+            .max(transform.y_axis.truncate().length())
+            .max(transform.z_axis.truncate().length());
+        Self {
+            center: transform.transform_point3(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// The six planes bounding what a view projection sees, facing inwards.
+/// Tests are conservative, some volumes just outside a corner pass.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    /// xyz: normal, w: distance, normalized so points inside have
+    /// `dot(normal, p) + w >= 0`
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Planes of a projection into reversed depth, near plane at 1 and far
+    /// plane at 0.
+    pub fn from_view_proj(view_proj: &Mat4) -> Self {
+        let (x, y, z, w) = (view_proj.row(0), view_proj.row(1), view_proj.row(2), view_proj.row(3));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z]
+            .map(|plane| plane / plane.truncate().length().max(f32::EPSILON));
+        Self { planes }
+    }
+
+    pub fn planes(&self) -> [Vec4; 6] {
+        self.planes
+    }
+
+    fn distance(plane: Vec4, point: Vec3) -> f32 {
+        plane.truncate().dot(point) + plane.w
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|&plane| Self::distance(plane, sphere.center) >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let (center, half_extents) = (aabb.center(), aabb.half_extents());
+        self.planes.iter().all(|&plane| {
+            let radius = plane.truncate().abs().dot(half_extents);
+            Self::distance(plane, center) >= -radius
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{BVec3, EulerRot, Quat};
+    use std::f32::consts::FRAC_PI_2;
+
+    /// Looking down -z from the origin, a quarter turn wide and high,
+    /// seeing from 1 to 100 away.
+    fn frustum() -> Frustum {
+        Frustum::from_view_proj(&Mat4::perspective_rh(FRAC_PI_2, 1.0, 100.0, 1.0))
+    }
+
+    fn assert_close(a: Vec4, b: Vec4) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{a} is not {b}");
+    }
+
+    fn cube(center: Vec3, half_extent: f32) -> Aabb {
+        Aabb {
+            min: center - half_extent,
+            max: center + half_extent,
+        }
+    }
+
+    fn corners(aabb: &Aabb) -> impl Iterator<Item = Vec3> + '_ {
+        (0..8).map(|i| {
+            Vec3::select(
+                BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0),
+                aabb.max,
+                aabb.min,
+            )
+        })
+    }
+
+    #[test]
+    fn planes_of_a_reversed_depth_projection() {
+        let planes = frustum().planes();
+        let h = 0.5f32.sqrt();
+        assert_close(planes[0], Vec4::new(h, 0.0, -h, 0.0));
+        assert_close(planes[1], Vec4::new(-h, 0.0, -h, 0.0));
+        assert_close(planes[2], Vec4::new(0.0, h, -h, 0.0));
+        assert_close(planes[3], Vec4::new(0.0, -h, -h, 0.0));
+        // depth 0 is the far plane, 1 the near one
+        assert_close(planes[4], Vec4::new(0.0, 0.0, 1.0, 100.0));
+        assert_close(planes[5], Vec4::new(0.0, 0.0, -1.0, -1.0));
+    }
+
+    #[test]
+    fn spheres_inside_outside_and_straddling() {
+        let frustum = frustum();
+        let sphere = |x, y, z, radius| BoundingSphere {
+            center: Vec3::new(x, y, z),
+            radius,
+        };
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -50.0, 5.0)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 10.0, 5.0)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -120.0, 5.0)));
+        assert!(!frustum.intersects_sphere(&sphere(30.0, 0.0, -10.0, 5.0)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, -30.0, -10.0, 5.0)));
+        // across the near, far and a side plane
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, 2.0, 5.0)));
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -103.0, 5.0)));
+        assert!(frustum.intersects_sphere(&sphere(12.0, 0.0, -10.0, 5.0)));
+    }
+
+    #[test]
+    fn boxes_inside_outside_and_straddling() {
+        let frustum = frustum();
+        assert!(frustum.intersects_aabb(&cube(Vec3::new(0.0, 0.0, -50.0), 5.0)));
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(0.0, 0.0, 10.0), 5.0)));
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(0.0, 0.0, -120.0), 5.0)));
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(-30.0, 0.0, -10.0), 5.0)));
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(0.0, 30.0, -10.0), 5.0)));
+        assert!(frustum.intersects_aabb(&cube(Vec3::new(0.0, 0.0, 2.0), 5.0)));
+        assert!(frustum.intersects_aabb(&cube(Vec3::new(0.0, 0.0, -103.0), 5.0)));
+        assert!(frustum.intersects_aabb(&cube(Vec3::new(0.0, 13.0, -10.0), 5.0)));
+    }
+
+    #[test]
+    fn transformed_box_holds_its_transformed_corners() {
+        let aabb = Aabb {
+            min: Vec3::new(-1.0, -2.0, 0.0),
+            max: Vec3::new(3.0, 1.0, 0.5),
+        };
+        let transform = Mat4::from_scale_rotation_translation(
+            Vec3::new(2.0, 0.5, 3.0),
+            Quat::from_euler(EulerRot::XYZ, 0.3, -0.7, 1.1),
+            Vec3::new(10.0, -4.0, 2.0),
+        );
+        let transformed = aabb.transform(&transform);
+        let expected = Aabb::from_points(corners(&aabb).map(|p| transform.transform_point3(p)));
+        // for a box the corners bound all, the result fits them exactly
+        assert!(transformed.min.abs_diff_eq(expected.min, 1e-4));
+        assert!(transformed.max.abs_diff_eq(expected.max, 1e-4));
+    }
+
+    #[test]
+    fn scaled_box_and_sphere() {
+        let aabb = cube(Vec3::new(1.0, 0.0, 0.0), 1.0);
+        let transform = Mat4::from_scale(Vec3::new(2.0, 3.0, 1.0));
+        let transformed = aabb.transform(&transform);
+        assert_eq!(transformed.min, Vec3::new(0.0, -3.0, -1.0));
+        assert_eq!(transformed.max, Vec3::new(4.0, 3.0, 1.0));
+        let sphere = BoundingSphere::from_aabb(&aabb).transform(&transform);
+        assert_eq!(sphere.center, Vec3::new(2.0, 0.0, 0.0));
+        assert!((sphere.radius - 3.0 * 3.0f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn union_holds_both() {
+        let a = cube(Vec3::ZERO, 1.0);
+        let b = cube(Vec3::new(4.0, 0.0, 0.0), 1.0);
+        let union = a.union(b);
+        assert_eq!(union.min, Vec3::splat(-1.0));
+        assert_eq!(union.max, Vec3::new(5.0, 1.0, 1.0));
+        assert_eq!(Aabb::EMPTY.union(a), a);
+
+        let sphere = |x, radius| BoundingSphere {
+            center: Vec3::new(x, 0.0, 0.0),
+            radius,
+        };
+        let union = sphere(0.0, 1.0).union(sphere(4.0, 1.0));
+        assert_eq!(union, sphere(2.0, 3.0));
+        // one inside the other is the outer one
+        assert_eq!(sphere(0.0, 5.0).union(sphere(1.0, 1.0)), sphere(0.0, 5.0));
+        assert_eq!(sphere(1.0, 1.0).union(sphere(0.0, 5.0)), sphere(0.0, 5.0));
+    }
+}
@@ -6,35 +6,35 @@ impl Mesh {
     pub fn new_cube(col: u32, device: &Device) -> Self {
         let vertex_data = [
             // top (0, 0, 1)
-            Vertex::new([-1.0, -1.0, 1.0], [0.0, 0.0, 1.0], col),
-            Vertex::new([1.0, -1.0, 1.0], [0.0, 0.0, 1.0], col),
-            Vertex::new([1.0, 1.0, 1.0], [0.0, 0.0, 1.0], col),
-            Vertex::new([-1.0, 1.0, 1.0], [0.0, 0.0, 1.0], col),
+            Vertex::new([-1.0, -1.0, 1.0], [0.0, 0.0, 1.0], col).with_uv([0.0, 1.0]),
+            Vertex::new([1.0, -1.0, 1.0], [0.0, 0.0, 1.0], col).with_uv([1.0, 1.0]),
+            Vertex::new([1.0, 1.0, 1.0], [0.0, 0.0, 1.0], col).with_uv([1.0, 0.0]),
+            Vertex::new([-1.0, 1.0, 1.0], [0.0, 0.0, 1.0], col).with_uv([0.0, 0.0]),
             // bottom (0, 0, -1.0)
-            Vertex::new([-1.0, 1.0, -1.0], [0.0, 0.0, -1.0], col),
-            Vertex::new([1.0, 1.0, -1.0], [0.0, 0.0, -1.0], col),
-            Vertex::new([1.0, -1.0, -1.0], [0.0, 0.0, -1.0], col),
-            Vertex::new([-1.0, -1.0, -1.0], [0.0, 0.0, -1.0], col),
+            Vertex::new([-1.0, 1.0, -1.0], [0.0, 0.0, -1.0], col).with_uv([0.0, 1.0]),
+            Vertex::new([1.0, 1.0, -1.0], [0.0, 0.0, -1.0], col).with_uv([1.0, 1.0]),
+            Vertex::new([1.0, -1.0, -1.0], [0.0, 0.0, -1.0], col).with_uv([1.0, 0.0]),
+            Vertex::new([-1.0, -1.0, -1.0], [0.0, 0.0, -1.0], col).with_uv([0.0, 0.0]),
             // right (1, 0, 0)
-            Vertex::new([1.0, -1.0, -1.0], [1.0, 0.0, 0.0], col),
-            Vertex::new([1.0, 1.0, -1.0], [1.0, 0.0, 0.0], col),
-            Vertex::new([1.0, 1.0, 1.0], [1.0, 0.0, 0.0], col),
-            Vertex::new([1.0, -1.0, 1.0], [1.0, 0.0, 0.0], col),
+            Vertex::new([1.0, -1.0, -1.0], [1.0, 0.0, 0.0], col).with_uv([0.0, 1.0]),
+            Vertex::new([1.0, 1.0, -1.0], [1.0, 0.0, 0.0], col).with_uv([1.0, 1.0]),
+            Vertex::new([1.0, 1.0, 1.0], [1.0, 0.0, 0.0], col).with_uv([1.0, 0.0]),
+            Vertex::new([1.0, -1.0, 1.0], [1.0, 0.0, 0.0], col).with_uv([0.0, 0.0]),
             // left (-1, 0, 0)
-            Vertex::new([-1.0, -1.0, 1.0], [-1.0, 0.0, 0.0], col),
-            Vertex::new([-1.0, 1.0, 1.0], [-1.0, 0.0, 0.0], col),
-            Vertex::new([-1.0, 1.0, -1.0], [-1.0, 0.0, 0.0], col),
-            Vertex::new([-1.0, -1.0, -1.0], [-1.0, 0.0, 0.0], col),
+            Vertex::new([-1.0, -1.0, 1.0], [-1.0, 0.0, 0.0], col).with_uv([0.0, 1.0]),
+            Vertex::new([-1.0, 1.0, 1.0], [-1.0, 0.0, 0.0], col).with_uv([1.0, 1.0]),
+            Vertex::new([-1.0, 1.0, -1.0], [-1.0, 0.0, 0.0], col).with_uv([1.0, 0.0]),
+            Vertex::new([-1.0, -1.0, -1.0], [-1.0, 0.0, 0.0], col).with_uv([0.0, 0.0]),
             // front (0, 1.0, 0)
-            Vertex::new([1.0, 1.0, -1.0], [0.0, 1.0, 0.0], col),
-            Vertex::new([-1.0, 1.0, -1.0], [0.0, 1.0, 0.0], col),
-            Vertex::new([-1.0, 1.0, 1.0], [0.0, 1.0, 0.0], col),
-            Vertex::new([1.0, 1.0, 1.0], [0.0, 1.0, 0.0], col),
+            Vertex::new([1.0, 1.0, -1.0], [0.0, 1.0, 0.0], col).with_uv([0.0, 1.0]),
+            Vertex::new([-1.0, 1.0, -1.0], [0.0, 1.0, 0.0], col).with_uv([1.0, 1.0]),
+            Vertex::new([-1.0, 1.0, 1.0], [0.0, 1.0, 0.0], col).with_uv([1.0, 0.0]),
+            Vertex::new([1.0, 1.0, 1.0], [0.0, 1.0, 0.0], col).with_uv([0.0, 0.0]),
             // back (0, -1.0, 0)
-            Vertex::new([1.0, -1.0, 1.0], [0.0, -1.0, 0.0], col),
-            Vertex::new([-1.0, -1.0, 1.0], [0.0, -1.0, 0.0], col),
-            Vertex::new([-1.0, -1.0, -1.0], [0.0, -1.0, 0.0], col),
-            Vertex::new([1.0, -1.0, -1.0], [0.0, -1.0, 0.0], col),
+            Vertex::new([1.0, -1.0, 1.0], [0.0, -1.0, 0.0], col).with_uv([0.0, 1.0]),
+            Vertex::new([-1.0, -1.0, 1.0], [0.0, -1.0, 0.0], col).with_uv([1.0, 1.0]),
+            Vertex::new([-1.0, -1.0, -1.0], [0.0, -1.0, 0.0], col).with_uv([1.0, 0.0]),
+            Vertex::new([1.0, -1.0, -1.0], [0.0, -1.0, 0.0], col).with_uv([0.0, 0.0]),
         ];
         let index_data: &[u32] = &[
             0, 1, 2, 2, 3, 0, // top
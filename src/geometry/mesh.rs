@@ -1,17 +1,35 @@
-use crate::geometry::Vertex;
+use crate::geometry::{Aabb, BoundingSphere, Vertex};
+use glam::{Vec2, Vec3};
+use std::collections::HashSet;
+use std::f32::consts::TAU;
 use std::io::BufReader;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::{Buffer, BufferUsages, Device};
 
+/// World units along x covered by one repeat of a texture, for models
+/// without texture coordinates.
+const CYLINDER_UV_LENGTH: f32 = 12.0;
+/// Texture repeats around x, for models without texture coordinates.
+const CYLINDER_UV_TURNS: f32 = 2.0;
+
 pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
     pub vertex_buffer: Buffer,
     pub index_buffer: Buffer,
+    /// Pairs of indices, each edge of the triangles once, for wireframes
+    pub edge_index_buffer: Buffer,
+    pub edge_index_count: u32,
+    /// Bounds of the vertex positions, before any shader deformation
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
 }
 
 impl Mesh {
-    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>, device: &Device) -> Self {
+    /// Upload a mesh. Tangents are generated from the positions, normals
+    /// and texture coordinates of `vertices`.
+    pub fn new(mut vertices: Vec<Vertex>, indices: Vec<u32>, device: &Device) -> Self {
+        Self::generate_tangents(&mut vertices, &indices);
         let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Vertex Buffer"),
             contents: bytemuck::cast_slice(&vertices),
@@ -22,11 +40,25 @@ impl Mesh {
             contents: bytemuck::cast_slice(&indices),
             usage: BufferUsages::INDEX,
         });
+        let edge_indices = Self::edge_indices(&indices);
+        let edge_index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Edge Index Buffer"),
+            contents: bytemuck::cast_slice(&edge_indices),
+            usage: BufferUsages::INDEX,
+        });
+        let positions: Vec<Vec3> = vertices
+            .iter()
+            .map(|v| Vec3::from_slice(&v.position[..3]))
+            .collect();
         Self {
             vertices,
             indices,
             vertex_buffer,
             index_buffer,
+            edge_index_buffer,
+            edge_index_count: edge_indices.len() as u32,
+            aabb: Aabb::from_points(positions.iter().copied()),
+            sphere: BoundingSphere::from_points(&positions),
         }
     }
     pub fn load_obj(source: &[u8], device: &Device) -> Self {
@@ -59,8 +91,13 @@ impl Mesh {
                         } else {
                             [mesh.normals[i], mesh.normals[i + 1], mesh.normals[i + 2]]
                         };
+                        let uv = match mesh.texcoords.get(i / 3 * 2..i / 3 * 2 + 2) {
+                            // obj texture coordinates start at the bottom left
+                            Some(&[u, v]) => [u, 1.0 - v],
+                            _ => Self::cylinder_uv(pos),
+                        };
                         let col = 0xffffaaff;
-                        vertices.push(Vertex::new(pos, nor, col));
+                        vertices.push(Vertex::new(pos, nor, col).with_uv(uv));
                     }
                     for i in mesh.indices {
                         indices.push(offset + i);
@@ -74,4 +111,63 @@ impl Mesh {
             }
         }
     }
+
+    /// Line list through the edges of the triangles, an edge shared by two
+    /// triangles is only listed once.
+    fn edge_indices(indices: &[u32]) -> Vec<u32> {
+        let mut edges = HashSet::new();
+        let mut edge_indices = Vec::new();
+        for triangle in indices.chunks_exact(3) {
+            for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+                let (a, b) = (triangle[a], triangle[b]);
+                if edges.insert((a.min(b), a.max(b))) {
+                    edge_indices.extend([a, b]);
+                }
+            }
+        }
+        edge_indices
+    }
+
+    /// Wrap texture coordinates around the x axis, which suits long models
+    /// like the dragon that lie along it.
+    fn cylinder_uv(position: [f32; 3]) -> [f32; 2] {
+        let [x, y, z] = position;
+        let angle = z.atan2(y) / TAU + 0.5;
+        [x / CYLINDER_UV_LENGTH, angle * CYLINDER_UV_TURNS]
+    }
+
+    /// Per vertex tangents from the texture coordinate gradients of the
+    /// surrounding triangles, made orthogonal to the normal.
+    fn generate_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+        let mut tangents = vec![Vec3::ZERO; vertices.len()];
+        let mut bitangents = vec![Vec3::ZERO; vertices.len()];
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| i as usize);
+            let position = |i: usize| Vec3::from_slice(&vertices[i].position[..3]);
+            let uv = |i: usize| Vec2::from(vertices[i].uv);
+            let (e1, e2) = (position(b) - position(a), position(c) - position(a));
+            let (d1, d2) = (uv(b) - uv(a), uv(c) - uv(a));
+            let det = d1.perp_dot(d2);
+            if det.abs() < f32::EPSILON {
+                continue;
+            }
+            let tangent = (e1 * d2.y - e2 * d1.y) / det;
+            let bitangent = (e2 * d1.x - e1 * d2.x) / det;
+            for i in [a, b, c] {
+                tangents[i] += tangent;
+                bitangents[i] += bitangent;
+            }
+        }
+        for (i, vertex) in vertices.iter_mut().enumerate() {
+            let normal = Vec3::from_slice(&vertex.normal[..3]).normalize_or(Vec3::Z);
+            let tangent = tangents[i] - normal * normal.dot(tangents[i]);
+            let tangent = tangent.try_normalize().unwrap_or_else(|| normal.any_orthonormal_vector());
+            let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            vertex.tangent = tangent.extend(handedness).to_array();
+        }
+    }
 }
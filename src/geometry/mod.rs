@@ -1,5 +1,8 @@
+pub mod bounds;
 pub mod cube;
 pub mod mesh;
+pub mod plane;
 pub mod vertex;
+pub use bounds::{Aabb, BoundingSphere, Frustum};
 pub use mesh::Mesh;
 pub use vertex::Vertex;
@@ -1,13 +1,14 @@
+use crate::geometry::Mesh;
 use crate::geometry::Vertex;
 use wgpu::Device;
 
-impl Geometry {
-    pub fn new_plane(col: u32, device: &Device) -> Self {
+impl Mesh {
+    pub fn new_plane(size: f32, col: u32, device: &Device) -> Self {
         let vertex_data = [
-            vertex([size, -size, 0], [0, 0, 1]),
-            vertex([size, size, 0], [0, 0, 1]),
-            vertex([-size, -size, 0], [0, 0, 1]),
-            vertex([-size, size, 0], [0, 0, 1]),
+            Vertex::new([size, -size, 0.0], [0.0, 0.0, 1.0], col).with_uv([1.0, 1.0]),
+            Vertex::new([size, size, 0.0], [0.0, 0.0, 1.0], col).with_uv([1.0, 0.0]),
+            Vertex::new([-size, -size, 0.0], [0.0, 0.0, 1.0], col).with_uv([0.0, 1.0]),
+            Vertex::new([-size, size, 0.0], [0.0, 0.0, 1.0], col).with_uv([0.0, 0.0]),
         ];
 
         let index_data: &[u32] = &[0, 1, 2, 2, 1, 3];
@@ -8,6 +8,9 @@ pub struct Vertex {
     pub position: [f32; 4],
     pub normal: [f32; 4],
     pub color: [f32; 4],
+    /// xyz along increasing u, w is the handedness of the bitangent
+    pub tangent: [f32; 4],
+    pub uv: [f32; 2],
 }
 impl Vertex {
     pub fn new(pos: [f32; 3], nor: [f32; 3], col: u32) -> Self {
@@ -32,11 +35,22 @@ impl Vertex {
                 b as f32 / 255.0,
                 a as f32 / 255.0,
             ],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            uv: [0.0, 0.0],
         }
     }
+    pub fn with_uv(mut self, uv: [f32; 2]) -> Self {
+        self.uv = uv;
+        self
+    }
     pub fn desc() -> VertexBufferLayout<'static> {
-        const ATTRIBS: [VertexAttribute; 3] =
-            vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4];
+        const ATTRIBS: [VertexAttribute; 5] = vertex_attr_array![
+            0 => Float32x4,
+            1 => Float32x4,
+            2 => Float32x4,
+            3 => Float32x4,
+            4 => Float32x2
+        ];
         VertexBufferLayout {
             array_stride: size_of::<Vertex>() as BufferAddress,
             step_mode: VertexStepMode::Vertex,
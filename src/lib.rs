@@ -4,6 +4,7 @@ mod material;
 mod world;
 
 pub use app::App;
+pub use world::Clock;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 use winit::error::EventLoopError;
@@ -0,0 +1,52 @@
+use std::fmt;
+use wgpu::{BlendComponent, BlendFactor, BlendOperation, BlendState};
+
+/// How an entity's surface combines with what is already drawn behind it.
+/// Blended entities are drawn after the opaque ones, back to front, and
+/// leave the depth buffer alone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    /// Covers what is behind by the base color's alpha
+    Alpha,
+    /// Adds the color weighted by alpha, for glows
+    Additive,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 3] = [BlendMode::Opaque, BlendMode::Alpha, BlendMode::Additive];
+
+    pub fn is_opaque(self) -> bool {
+        self == BlendMode::Opaque
+    }
+
+    pub fn blend_state(self) -> Option<BlendState> {
+        match self {
+            BlendMode::Opaque => None,
+            BlendMode::Alpha => Some(BlendState::ALPHA_BLENDING),
+            BlendMode::Additive => Some(BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::SrcAlpha,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                alpha: BlendComponent {
+                    src_factor: BlendFactor::Zero,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+            }),
+        }
+    }
+}
+
+impl fmt::Display for BlendMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlendMode::Opaque => write!(f, "Opaque"),
+            BlendMode::Alpha => write!(f, "Alpha"),
+            BlendMode::Additive => write!(f, "Additive"),
+        }
+    }
+}
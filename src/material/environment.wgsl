@@ -0,0 +1,36 @@
+// Shared by scene pipelines, prepended after shadow.wgsl in lit ones. Image
+// based lighting from the environment the background is drawn with, in group
+// 2 next to the shadow maps, see world/environment.rs.
+struct EnvironmentUniforms {
+    inverse_view_proj: mat4x4f,
+    sun_disk: vec4f,
+    sun_disk_color: vec4f,
+    intensity: f32,
+    specular_mip: f32,
+};
+
+@group(2) @binding(4)
+var irradiance_map: texture_cube<f32>;
+@group(2) @binding(5)
+var environment_sampler: sampler;
+@group(2) @binding(6)
+var<uniform> environment: EnvironmentUniforms;
+@group(2) @binding(7)
+var specular_map: texture_cube<f32>;
+@group(2) @binding(8)
+var brdf_lut: texture_2d<f32>;
+
+// Light reflected towards `v` from all around, split into the prefiltered
+// radiance times the BRDF's response to it and the diffuse irradiance.
+fn environment_light(base_color: vec3f, metallic: f32, roughness: f32, n: vec3f, v: vec3f) -> vec3f {
+    let n_dot_v = max(dot(n, v), 1e-4);
+    let f0 = mix(vec3(0.04), base_color, metallic);
+    let brdf = textureSampleLevel(brdf_lut, environment_sampler, vec2(n_dot_v, roughness), 0.0).rg;
+    let specular_color = f0 * brdf.x + brdf.y;
+    let r = reflect(-v, n);
+    let level = roughness * environment.specular_mip;
+    let radiance = textureSampleLevel(specular_map, environment_sampler, r, level).rgb;
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, n, 0.0).rgb;
+    let diffuse = irradiance * base_color * (1.0 - specular_color) * (1.0 - metallic);
+    return (diffuse + radiance * specular_color) * environment.intensity;
+}
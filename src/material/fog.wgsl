@@ -0,0 +1,70 @@
+// Shared by scene pipelines, prepended after environment.wgsl. Exponential
+// distance fog, height fog thinning out above a base height and an aerial
+// perspective tint fading far surfaces into the sky behind them, see
+// world/fog.rs.
+// relative scattering of red, green and blue light, roughly Rayleigh
+const AERIAL_SCATTERING = vec3(0.3, 0.55, 1.0);
+// keeps the height fog finite far below its base height
+const MAX_FOG_EXPONENT = 30.0;
+// set by pipelines that add their color onto what is behind, which is fogged
+// already, so only the share of the color that makes it through remains
+override ADDITIVE_BLEND = false;
+
+struct Fog {
+    color: vec4f,
+    eye_position: vec4f,
+    density: f32,
+    height_density: f32,
+    height_falloff: f32,
+    base_height: f32,
+    aerial_density: f32,
+};
+
+@group(2) @binding(9)
+var<uniform> fog: Fog;
+
+// optical depth of the height fog between the eye and `to_surface` away,
+// its density falling off exponentially with height
+fn height_fog_depth(to_surface: vec3f, distance: f32) -> f32 {
+    let b = fog.height_falloff;
+    let eye_height = fog.eye_position.z - fog.base_height;
+    let density = fog.height_density * exp(min(-b * eye_height, MAX_FOG_EXPONENT));
+    let exponent = b * to_surface.z;
+    if abs(exponent) < 1e-4 {
+        return density * distance;
+    }
+    let exponent_clamped = max(exponent, -MAX_FOG_EXPONENT);
+    return density * distance * (1.0 - exp(-exponent_clamped)) / exponent_clamped;
+}
+
+fn fog_depth(to_surface: vec3f, distance: f32) -> f32 {
+    return fog.density * distance + height_fog_depth(to_surface, distance);
+}
+
+// `color` seen from the eye through the fog, for a surface at `world_position`
+fn apply_fog(color: vec3f, world_position: vec3f) -> vec3f {
+    if ADDITIVE_BLEND {
+        return color * fog_visibility(world_position);
+    }
+    if fog.eye_position.w == 0.0 {
+        return color;
+    }
+    let to_surface = world_position - fog.eye_position.xyz;
+    let distance = length(to_surface);
+    let direction = to_surface / max(distance, 1e-4);
+    let sky = textureSampleLevel(specular_map, environment_sampler, direction, environment.specular_mip).rgb
+        * environment.intensity;
+    let aerial = exp(-distance * fog.aerial_density * AERIAL_SCATTERING);
+    let fog_color = mix(fog.color.rgb, sky, fog.color.a);
+    return mix(fog_color, mix(sky, color, aerial), exp(-fog_depth(to_surface, distance)));
+}
+
+// share of the light from `world_position` that makes it through the fog,
+// for light that adds up on what is behind it and so takes no fog color
+fn fog_visibility(world_position: vec3f) -> f32 {
+    if fog.eye_position.w == 0.0 {
+        return 1.0;
+    }
+    let to_surface = world_position - fog.eye_position.xyz;
+    return exp(-fog_depth(to_surface, length(to_surface)));
+}
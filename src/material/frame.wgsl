@@ -0,0 +1,24 @@
+// Shared by scene pipelines, prepended to their source. Group 1 is owned by
+// the renderer and holds what every entity of a frame shares, see
+// world/frame.rs. Shadow passes bind a frame with the light's view
+// projection in its place, without the lights.
+struct Light {
+    position: vec3f,
+    radius: f32,
+    color: vec4f,
+    shadow: i32,
+    intensity: f32,
+};
+
+struct Frame {
+    view_proj: mat4x4f,
+    view: mat4x4f,
+    eye_position: vec4f,
+    time: f32,
+    light_count: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> frame: Frame;
+@group(1) @binding(1)
+var<storage> lights: array<Light>;
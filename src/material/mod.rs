@@ -1,8 +1,16 @@
+pub mod blend;
+pub mod pbr;
+pub mod scene_pipeline;
 pub mod shader;
 pub mod shader_dragon;
 pub mod shader_lit;
 pub mod shader_unlit;
+pub mod texture;
+pub use blend::BlendMode;
+pub use pbr::Material;
+pub use scene_pipeline::ScenePipeline;
 pub use shader::Shader;
 pub use shader_dragon::{ShaderDragon, PathPattern};
 pub use shader_lit::ShaderLit;
 pub use shader_unlit::ShaderUnlit;
+pub use texture::{SamplerSettings, TextureCache, TextureKind, TextureSet};
@@ -0,0 +1,57 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec3, Vec4};
+
+/// Metallic/roughness surface description, uploaded per entity. Must match
+/// `Material` in pbr.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, Debug, PartialEq)]
+pub struct Material {
+    /// Linear color, multiplied with the vertex color
+    pub base_color: Vec4,
+    /// Linear color of the light the surface gives off by itself
+    pub emissive: Vec3,
+    pub emissive_strength: f32,
+    /// 0 for dielectrics, 1 for metals
+    pub metallic: f32,
+    /// Perceptual roughness, 0 is a perfect mirror
+    pub roughness: f32,
+    /// Strength of the normal map bumps, 0 ignores the map
+    pub normal_scale: f32,
+    pub _padding: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: Vec4::ONE,
+            emissive: Vec3::ZERO,
+            emissive_strength: 1.0,
+            metallic: 0.0,
+            roughness: 0.5,
+            normal_scale: 1.0,
+            _padding: 0.0,
+        }
+    }
+}
+
+impl Material {
+    pub fn new(base_color: Vec4, metallic: f32, roughness: f32) -> Self {
+        Self {
+            base_color,
+            metallic,
+            roughness,
+            ..Default::default()
+        }
+    }
+
+    /// A surface that only glows with `color`, like a light bulb.
+    pub fn emissive(color: Vec3, strength: f32) -> Self {
+        Self {
+            base_color: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            emissive: color,
+            emissive_strength: strength,
+            roughness: 1.0,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,151 @@
+// Shared by lit pipelines, prepended after fog.wgsl. Cook-Torrance
+// specular with a GGX distribution, height correlated Smith visibility and
+// Schlick fresnel, over a Lambert diffuse. The entity's transforms and
+// material arrive as instance attributes, see world/frame.rs, and group 3
+// holds its maps, see material/texture.rs.
+const PI = 3.14159265;
+// below this GGX highlights get too small to be sampled by a single pixel
+const MIN_ROUGHNESS = 0.045;
+
+struct Material {
+    base_color: vec4f,
+    emissive: vec3f,
+    emissive_strength: f32,
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+};
+
+// per instance attributes, after the vertex's locations 0 to 4
+struct InstanceInput {
+    @location(5) world_0: vec4f,
+    @location(6) world_1: vec4f,
+    @location(7) world_2: vec4f,
+    @location(8) world_3: vec4f,
+    // columns of the rotation applied to normals
+    @location(9) rotation_0: vec4f,
+    @location(10) rotation_1: vec4f,
+    @location(11) rotation_2: vec4f,
+    @location(12) base_color: vec4f,
+    // xyz: emissive, w: emissive strength
+    @location(13) emissive: vec4f,
+    // x: metallic, y: roughness, z: normal scale
+    @location(14) material: vec4f,
+};
+
+// the entity being drawn
+struct Node {
+    world: mat4x4f,
+    rotation: mat4x4f,
+};
+
+fn instance_node(instance: InstanceInput) -> Node {
+    let world = mat4x4(instance.world_0, instance.world_1, instance.world_2, instance.world_3);
+    let rotation = mat4x4(
+        instance.rotation_0,
+        instance.rotation_1,
+        instance.rotation_2,
+        vec4(0.0, 0.0, 0.0, 1.0),
+    );
+    return Node(world, rotation);
+}
+
+// the vertex stage passes the material attributes on unchanged, flat
+fn instance_material(base_color: vec4f, emissive: vec4f, material: vec4f) -> Material {
+    return Material(base_color, emissive.xyz, emissive.w, material.x, material.y, material.z);
+}
+@group(3) @binding(0)
+var base_color_map: texture_2d<f32>;
+@group(3) @binding(1)
+var base_color_sampler: sampler;
+@group(3) @binding(2)
+var normal_map: texture_2d<f32>;
+@group(3) @binding(3)
+var normal_sampler: sampler;
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith G2 divided by 4 n.l n.v
+fn visibility_smith(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+    let l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+    return 0.5 / max(v + l, 1e-5);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3f) -> vec3f {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// reflected radiance towards `v` per unit of radiance arriving from `l`
+fn brdf(base_color: vec3f, metallic: f32, roughness: f32, n: vec3f, v: vec3f, l: vec3f) -> vec3f {
+    let n_dot_l = dot(n, l);
+    if n_dot_l <= 0.0 {
+        return vec3(0.0);
+    }
+    let h = normalize(v + l);
+    let n_dot_v = max(dot(n, v), 1e-4);
+    let n_dot_h = max(dot(n, h), 0.0);
+    let alpha = roughness * roughness;
+    let f0 = mix(vec3(0.04), base_color, metallic);
+    let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    let specular = distribution_ggx(n_dot_h, alpha) * visibility_smith(n_dot_v, n_dot_l, alpha) * f;
+    let diffuse = (1.0 - f) * (1.0 - metallic) * base_color / PI;
+    return (diffuse + specular) * n_dot_l;
+}
+
+// inverse square falloff, windowed to reach zero smoothly at `radius`
+fn attenuation(distance: f32, radius: f32) -> f32 {
+    let k = distance / radius;
+    let window = saturate(1.0 - k * k * k * k);
+    return window * window / max(distance * distance, 0.01);
+}
+
+// bend the interpolated normal by the tangent space normal map
+fn mapped_normal(normal: vec3f, tangent: vec4f, uv: vec2f, scale: f32) -> vec3f {
+    let n = normalize(normal);
+    let t = normalize(tangent.xyz - n * dot(n, tangent.xyz));
+    let b = cross(n, t) * tangent.w;
+    let texel = textureSample(normal_map, normal_sampler, uv).xyz * 2.0 - 1.0;
+    return normalize(mat3x3(t, b, n) * vec3(texel.xy * scale, texel.z));
+}
+
+fn shade(
+    material: Material,
+    vertex_color: vec4f,
+    uv: vec2f,
+    world_position: vec3f,
+    vertex_normal: vec3f,
+    tangent: vec4f,
+) -> vec4f {
+    let base_color = material.base_color * vertex_color * textureSample(base_color_map, base_color_sampler, uv);
+    let normal = mapped_normal(vertex_normal, tangent, uv, material.normal_scale);
+    let metallic = saturate(material.metallic);
+    let roughness = clamp(material.roughness, MIN_ROUGHNESS, 1.0);
+    let v = normalize(shadow.eye_position.xyz - world_position);
+    var color = vec3(0.0);
+    if shadow.sun_direction.w != 0.0 {
+        let l = -shadow.sun_direction.xyz;
+        let radiance = shadow.sun_color.rgb * sun_shadow(world_position, normal);
+        color += brdf(base_color.rgb, metallic, roughness, normal, v, l) * radiance;
+    }
+    for (var i = 0u; i < frame.light_count; i++) {
+        let light = lights[i];
+        let to_light = light.position - world_position;
+        let distance = length(to_light);
+        if distance >= light.radius {
+            continue;
+        }
+        let l = to_light / max(distance, 1e-4);
+        let visibility = point_shadow(light.shadow, light.position, world_position, normal);
+        let radiance = light.color.rgb * light.intensity * attenuation(distance, light.radius);
+        color += brdf(base_color.rgb, metallic, roughness, normal, v, l) * radiance * visibility;
+    }
+    color += environment_light(base_color.rgb, metallic, roughness, normal, v);
+    color += material.emissive * material.emissive_strength;
+    return vec4(color, base_color.a);
+}
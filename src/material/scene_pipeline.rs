@@ -0,0 +1,139 @@
+use crate::geometry::Vertex;
+use crate::material::BlendMode;
+use crate::world::{DEPTH_COMPARE, HDR_FORMAT, NodeInstance, Renderer};
+use std::cell::{Cell, Ref, RefCell};
+use wgpu::{
+    ColorTargetState, ColorWrites, DepthBiasState, DepthStencilState, Device, Face, FragmentState,
+    FrontFace, MultisampleState, PipelineCompilationOptions, PipelineLayout, PrimitiveState,
+    PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModule, StencilState,
+    TextureFormat, VertexState,
+};
+
+/// The pipelines a shader draws the main pass with, one per blend mode and
+/// one drawing mesh edges for the debug wireframe. They depend on the
+/// renderer's sample count, so they are rebuilt when anti-aliasing changes.
+pub struct ScenePipeline {
+    /// Indexed by blend mode, in the order of `BlendMode::ALL`, followed by
+    /// the wireframe
+    pipelines: RefCell<Vec<RenderPipeline>>,
+    layout: PipelineLayout,
+    module: ShaderModule,
+    sample_count: Cell<u32>,
+    /// Hand out the wireframe pipeline for every blend mode
+    wireframe: Cell<bool>,
+}
+
+impl ScenePipeline {
+    /// `module` must have a single vertex and fragment entry point, and use
+    /// fog.wgsl, whose `ADDITIVE_BLEND` the additive pipeline overrides.
+    pub fn new(renderer: &Renderer, layout: PipelineLayout, module: ShaderModule) -> Self {
+        let sample_count = renderer.sample_count();
+        let pipelines = Self::create_all(&renderer.device, &layout, &module, sample_count);
+        Self {
+            pipelines: RefCell::new(pipelines),
+            layout,
+            module,
+            sample_count: Cell::new(sample_count),
+            wireframe: Cell::new(false),
+        }
+    }
+
+    pub fn get(&self, blend: BlendMode) -> Ref<'_, RenderPipeline> {
+        let index = if self.wireframe.get() {
+            BlendMode::ALL.len()
+        } else {
+            blend as usize
+        };
+        Ref::map(self.pipelines.borrow(), |pipelines| &pipelines[index])
+    }
+
+    /// Rebuild the pipelines if the renderer's sample count changed since,
+    /// and follow its wireframe setting.
+    pub fn update(&self, renderer: &Renderer) {
+        self.wireframe.set(renderer.debug.wireframe);
+        let sample_count = renderer.sample_count();
+        if sample_count != self.sample_count.get() {
+            let pipelines =
+                Self::create_all(&renderer.device, &self.layout, &self.module, sample_count);
+            self.pipelines.replace(pipelines);
+            self.sample_count.set(sample_count);
+        }
+    }
+
+    fn create_all(
+        device: &Device,
+        layout: &PipelineLayout,
+        module: &ShaderModule,
+        sample_count: u32,
+    ) -> Vec<RenderPipeline> {
+        let mut pipelines: Vec<_> = BlendMode::ALL
+            .into_iter()
+            .map(|blend| Self::create(device, layout, module, sample_count, blend, false))
+            .collect();
+        let wireframe = Self::create(device, layout, module, sample_count, BlendMode::Opaque, true);
+        pipelines.push(wireframe);
+        pipelines
+    }
+
+    /// A wireframe pipeline draws line lists, like `Mesh::edge_index_buffer`.
+    fn create(
+        device: &Device,
+        layout: &PipelineLayout,
+        module: &ShaderModule,
+        sample_count: u32,
+        blend: BlendMode,
+        wireframe: bool,
+    ) -> RenderPipeline {
+        // additive surfaces take no fog color, the fog is already behind them
+        let constants = [("ADDITIVE_BLEND", 1.0)];
+        let additive = blend == BlendMode::Additive;
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: None,
+            layout: Some(layout),
+            vertex: VertexState {
+                module,
+                entry_point: None,
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[Vertex::desc(), NodeInstance::desc()],
+            },
+            fragment: Some(FragmentState {
+                module,
+                entry_point: None,
+                compilation_options: PipelineCompilationOptions {
+                    constants: if additive { &constants } else { &[] },
+                    ..Default::default()
+                },
+                targets: &[Some(ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: blend.blend_state(),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                topology: if wireframe {
+                    PrimitiveTopology::LineList
+                } else {
+                    PrimitiveTopology::TriangleList
+                },
+                front_face: FrontFace::Ccw,
+                cull_mode: (!wireframe).then_some(Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                // blended surfaces are sorted instead, and must not hide
+                // each other
+                depth_write_enabled: blend.is_opaque(),
+                depth_compare: DEPTH_COMPARE,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+}
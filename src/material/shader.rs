@@ -1,12 +1,37 @@
-use wgpu::{BufferAddress, Queue, RenderPass};
+use glam::{Mat4, Vec3};
+use wgpu::RenderPass;
 
-use crate::world::Light;
+use crate::geometry::{Aabb, Mesh};
+use crate::material::BlendMode;
+use crate::world::Renderer;
 
 pub trait Shader {
-    fn set_pipeline<'a>(&'a self, _pass: &mut RenderPass<'a>, _offset: BufferAddress) {}
-    fn write_transform_data(&self, _queue: &Queue, _offset: BufferAddress, _matrix: &[f32; 16]) {}
-    fn write_rotation_data(&self, _queue: &Queue, _offset: BufferAddress, _matrix: &[f32; 16]) {}
-    fn write_time_data(&self, _queue: &Queue, _time: f32) {}
-    fn write_camera_data(&self, _queue: &Queue, _matrix: &[f32; 16]) {}
-    fn write_light_data(&self, _queue: &Queue, _lights: &[Light]) {}
+    /// Bind the shader's own group 0 and set the pipeline for `blend`. The
+    /// renderer binds the frame in group 1 and the instances in vertex
+    /// buffer slot 1.
+    fn set_pipeline<'a>(&'a self, _pass: &mut RenderPass<'a>, _blend: BlendMode) {}
+    /// Called before the main pass, rebuilds pipelines that depend on the
+    /// renderer's settings, like its sample count.
+    fn update_pipelines(&self, _renderer: &Renderer) {}
+    /// Set the depth only pipeline used to render shadow maps. Returns false
+    /// if this shader does not cast shadows.
+    fn set_shadow_pipeline<'a>(&'a self, _pass: &mut RenderPass<'a>) -> bool {
+        false
+    }
+    /// Where the vertex stage moves a mesh position and normal at `time`,
+    /// before the node's transform. Lets the debug overlay follow shaders
+    /// that deform meshes.
+    fn deform_vertex(&self, position: Vec3, normal: Vec3, _time: f32) -> (Vec3, Vec3) {
+        (position, normal)
+    }
+    /// Bounds of `mesh` in the node's space once this shader deformed it,
+    /// if they no longer fit the mesh's own bounds. Culling uses them.
+    fn deformed_bounds(&self, _mesh: &Mesh) -> Option<Aabb> {
+        None
+    }
+    /// Frames of the path this shader bends meshes along, if it has one, in
+    /// the node's space. The x axis of each runs along the path.
+    fn path(&self) -> Option<Vec<Mat4>> {
+        None
+    }
 }
@@ -1,28 +1,28 @@
-use crate::geometry::Vertex;
-use crate::material::Shader;
-use crate::world::{Light, MAX_ENTITY, MAX_LIGHT, Renderer};
+use crate::geometry::{Aabb, Mesh, Vertex};
+use crate::material::{BlendMode, ScenePipeline, Shader};
+use crate::world::{Clock, NodeInstance, Renderer, Shadows};
 use core::f32;
-use glam::{Mat4, Quat, Vec3};
+use glam::{Mat4, Quat, Vec3, Vec3Swizzles};
 use splines::{Interpolation, Key, Spline};
 use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
 use std::mem::size_of;
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::Instant;
 #[cfg(target_arch = "wasm32")]
 use web_time::Instant;
-use wgpu::util::align_to;
 use wgpu::{
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferAddress, BufferBinding,
-    BufferBindingType, BufferSize, BufferUsages, CompareFunction, DepthBiasState,
-    DepthStencilState, DynamicOffset, Face, FragmentState, FrontFace, MultisampleState,
-    PipelineCompilationOptions, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass,
-    RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages,
-    StencilState, TextureFormat, VertexState,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferUsages, Face, FrontFace,
+    MultisampleState, PipelineCompilationOptions,
+    PipelineLayoutDescriptor, PrimitiveState, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, VertexState,
 };
 
 const CURVE_RESOLUTION: usize = 1024;
 const CURVE_SCALE: f32 = 15.0;
+/// Path distance travelled per millisecond, must match `SPEED` in shader_dragon.wgsl
+pub const SPEED: f64 = 0.07;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PathPattern {
@@ -31,40 +31,26 @@ pub enum PathPattern {
     Infinity,
     Sphere,
 }
-const BIND_GROUP_CAMERA: [(ShaderStages, BufferBindingType, bool); 3] = [
-    (ShaderStages::VERTEX, BufferBindingType::Uniform, false),
-    (
-        ShaderStages::FRAGMENT,
-        BufferBindingType::Storage { read_only: true },
-        false,
-    ),
-    (ShaderStages::FRAGMENT, BufferBindingType::Uniform, false), // light_count
-];
-const BIND_GROUP_NODE: [(ShaderStages, BufferBindingType, bool); 6] = [
-    (ShaderStages::VERTEX, BufferBindingType::Uniform, true),
-    (ShaderStages::VERTEX, BufferBindingType::Uniform, true),
+const BIND_GROUP_NODE: [(ShaderStages, BufferBindingType, bool); 3] = [
     (
         ShaderStages::VERTEX,
         BufferBindingType::Storage { read_only: true },
         false,
     ),
-    (ShaderStages::VERTEX, BufferBindingType::Uniform, false), // time
     (ShaderStages::VERTEX, BufferBindingType::Uniform, false), // combined_transform_map_length
     (ShaderStages::VERTEX, BufferBindingType::Uniform, false), // path_length
 ];
 
 pub struct ShaderDragon {
-    pub render_pipeline: RenderPipeline,
-    pub bind_group_camera: BindGroup,
+    pub render_pipeline: ScenePipeline,
+    pub shadow_pipeline: RenderPipeline,
     pub bind_group_node: BindGroup,
-    pub vp_buffer: Buffer,
-    pub w_buffer: Buffer,
-    pub r_buffer: Buffer,
-    pub time_buffer: Buffer,
-    pub light_buffer: Buffer,
-    pub light_count_buffer: Buffer,
     pub combined_transform_buffer: Buffer,
     pub path_length_buffer: Buffer,
+    pub path_length: Cell<f32>,
+    /// Box around the points of the path, in the node's space
+    pub path_bounds: Cell<Aabb>,
+    pub combined_transforms: RefCell<Vec<Mat4>>,
     // transform_length_buffer: Buffer,
 }
 impl ShaderDragon {
@@ -154,7 +140,7 @@ impl ShaderDragon {
             let mut combined_transforms = [Mat4::IDENTITY; CURVE_RESOLUTION];
             let mut path_length = 0.0f32;
             let normalize = |i, n| (i % n) as f32 / n as f32;
-            for i in 0..CURVE_RESOLUTION {
+            for (i, combined_transform) in combined_transforms.iter_mut().enumerate() {
                 let t1 = normalize(i, CURVE_RESOLUTION);
                 let t2 = normalize(i + 1, CURVE_RESOLUTION);
                 let p1 = spline.clamped_sample(t1).unwrap_or_default() * CURVE_SCALE;
@@ -163,7 +149,7 @@ impl ShaderDragon {
                 path_length += tangent.length();
                 let translation = Mat4::from_translation(p1);
                 let rotation = Mat4::from_quat(Quat::from_rotation_arc(Vec3::X, tangent.normalize()));
-                combined_transforms[i] = translation * rotation;
+                *combined_transform = translation * rotation;
             }
             (combined_transforms, path_length)
         };
@@ -175,7 +161,7 @@ impl ShaderDragon {
         let mut combined_transforms = [Mat4::IDENTITY; CURVE_RESOLUTION];
         let mut path_length = 0.0f32;
 
-        for i in 0..CURVE_RESOLUTION {
+        for (i, combined_transform) in combined_transforms.iter_mut().enumerate() {
             let t = (i as f32 / CURVE_RESOLUTION as f32) * std::f32::consts::TAU;
             let next_t = ((i + 1) as f32 / CURVE_RESOLUTION as f32) * std::f32::consts::TAU;
 
@@ -196,7 +182,7 @@ impl ShaderDragon {
 
             let translation = Mat4::from_translation(pos);
             let rotation = Mat4::from_quat(Quat::from_rotation_arc(Vec3::X, tangent));
-            combined_transforms[i] = translation * rotation;
+            *combined_transform = translation * rotation;
         }
 
         (combined_transforms, path_length)
@@ -207,7 +193,7 @@ impl ShaderDragon {
         let mut combined_transforms = [Mat4::IDENTITY; CURVE_RESOLUTION];
         let mut path_length = 0.0f32;
 
-        for i in 0..CURVE_RESOLUTION {
+        for (i, combined_transform) in combined_transforms.iter_mut().enumerate() {
             let t = (i as f32 / CURVE_RESOLUTION as f32) * std::f32::consts::TAU;
             let next_t = ((i + 1) as f32 / CURVE_RESOLUTION as f32) * std::f32::consts::TAU;
 
@@ -233,7 +219,7 @@ impl ShaderDragon {
 
             let translation = Mat4::from_translation(pos);
             let rotation = Mat4::from_quat(Quat::from_rotation_arc(Vec3::X, tangent));
-            combined_transforms[i] = translation * rotation;
+            *combined_transform = translation * rotation;
         }
 
         (combined_transforms, path_length)
@@ -244,7 +230,7 @@ impl ShaderDragon {
         let mut combined_transforms = [Mat4::IDENTITY; CURVE_RESOLUTION];
         let mut path_length = 0.0f32;
 
-        for i in 0..CURVE_RESOLUTION {
+        for (i, combined_transform) in combined_transforms.iter_mut().enumerate() {
             let progress = i as f32 / CURVE_RESOLUTION as f32;
 
             // Use a sine wave to smoothly oscillate from bottom to top and back
@@ -282,7 +268,7 @@ impl ShaderDragon {
 
             let translation = Mat4::from_translation(pos);
             let rotation = Mat4::from_quat(Quat::from_rotation_arc(Vec3::X, tangent));
-            combined_transforms[i] = translation * rotation;
+            *combined_transform = translation * rotation;
         }
 
         (combined_transforms, path_length)
@@ -292,16 +278,89 @@ impl ShaderDragon {
         let (combined_transforms, path_length) = Self::generate_path_data(pattern);
         renderer.queue.write_buffer(&self.combined_transform_buffer, 0, bytemuck::cast_slice(&combined_transforms));
         renderer.queue.write_buffer(&self.path_length_buffer, 0, bytemuck::bytes_of(&path_length));
+        self.path_length.set(path_length);
+        self.path_bounds.set(Self::path_bounds(&combined_transforms));
+        *self.combined_transforms.borrow_mut() = combined_transforms.to_vec();
         log::info!("Path length for {:?}: {:.2}", pattern, path_length);
     }
 
+    fn path_bounds(combined_transforms: &[Mat4]) -> Aabb {
+        Aabb::from_points(combined_transforms.iter().map(|t| t.w_axis.truncate()))
+    }
+
+    /// Box around a model with bounds `aabb` anywhere along a path within
+    /// `path_bounds`, as far out as its widest cross section reaches since
+    /// `vs_main` keeps only its y and z.
+    fn bounds_along(path_bounds: Aabb, aabb: &Aabb) -> Aabb {
+        let reach = aabb.min.abs().max(aabb.max.abs());
+        path_bounds.expand(reach.yz().length())
+    }
+
+    /// Convert simulation time into the shader's `time` uniform, wrapped to
+    /// one lap of the path so `time*SPEED` keeps full `f32` precision.
+    pub fn wrap_time(&self, time: f64) -> f32 {
+        let lap = self.path_length.get() as f64 / SPEED;
+        Clock::wrap(time, lap) as f32
+    }
+
+    /// The two path transforms around local `x` and how far to blend
+    /// between them, the same way `vs_main` picks them.
+    fn path_transforms(&self, x: f32, time: f32) -> (Mat4, Mat4, f32) {
+        let combined_transforms = self.combined_transforms.borrow();
+        Self::path_transforms_of(&combined_transforms, self.path_length.get(), x, time)
+    }
+
+    fn path_transforms_of(
+        combined_transforms: &[Mat4],
+        path_length: f32,
+        x: f32,
+        time: f32,
+    ) -> (Mat4, Mat4, f32) {
+        let n = combined_transforms.len();
+        let u = (x as f64 + time as f64 * SPEED) / path_length as f64 * n as f64;
+        let u = u.rem_euclid(n as f64);
+        let u_low = u.floor() as usize % n;
+        let u_high = u.ceil() as usize % n;
+        let k = u.fract() as f32;
+        (combined_transforms[u_low], combined_transforms[u_high], k)
+    }
+
+    /// Sample the path the same way `vs_main` does, for a point at local `x`
+    /// along the dragon's body. Returns the position and forward direction.
+    pub fn sample_path(&self, x: f32, time: f32) -> (Vec3, Vec3) {
+        let (low, high, k) = self.path_transforms(x, time);
+        let position = low.w_axis.truncate().lerp(high.w_axis.truncate(), k);
+        let forward = low
+            .x_axis
+            .truncate()
+            .lerp(high.x_axis.truncate(), k)
+            .normalize_or(Vec3::X);
+        (position, forward)
+    }
+
+    /// Where `vs_main` puts a point of the model at `position`, in world space.
+    pub fn sample_point(&self, position: Vec3, time: f32) -> Vec3 {
+        self.deform_vertex(position, Vec3::ZERO, time).0
+    }
+
+    /// Bend a point of the model and its normal along the path, between
+    /// the path transforms around its x.
+    fn deform(
+        (low, high, k): (Mat4, Mat4, f32),
+        position: Vec3,
+        normal: Vec3,
+    ) -> (Vec3, Vec3) {
+        let cross_section = Vec3::new(0.0, position.y, position.z);
+        let position = low
+            .transform_point3(cross_section)
+            .lerp(high.transform_point3(cross_section), k);
+        let normal = low.transform_vector3(normal).lerp(high.transform_vector3(normal), k);
+        (position, normal)
+    }
+
     pub fn new(renderer: &Renderer) -> Self {
         let device = &renderer.device;
         let new_shader_timestamp = Instant::now();
-        let align = |n| {
-            let alignment = device.limits().min_uniform_buffer_offset_alignment as BufferAddress;
-            align_to(n, alignment)
-        };
         let create_bind_group_layout = |entries: &[(ShaderStages, BufferBindingType, bool)]| {
             let entries =
                 entries
@@ -324,84 +383,43 @@ impl ShaderDragon {
                 entries: entries.collect::<Vec<_>>().as_slice(),
             })
         };
-        let bind_group_layout_camera = create_bind_group_layout(&BIND_GROUP_CAMERA);
         let bind_group_layout_node = create_bind_group_layout(&BIND_GROUP_NODE);
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[&bind_group_layout_node, &bind_group_layout_camera],
+            bind_group_layouts: &[
+                &bind_group_layout_node,
+                &renderer.frame.layout,
+                &renderer.scene_bind_group_layout,
+                &renderer.textures.bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let shadow_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[
+                &bind_group_layout_node,
+                &renderer.shadows.pass_bind_group_layout,
+            ],
             push_constant_ranges: &[],
         });
         let (combined_transforms, path_length) = Self::generate_path_data(PathPattern::Random);
         let combined_transform_buffer =
             renderer.create_buffer_init(bytemuck::cast_slice(&combined_transforms), BufferUsages::STORAGE);
         let path_length_buffer = renderer.create_buffer_init(bytemuck::bytes_of(&path_length), BufferUsages::UNIFORM);
-        let time_buffer = renderer.create_buffer(size_of::<f32>() as u64, BufferUsages::UNIFORM);
-        let vp_buffer = renderer.create_buffer_init(
-            bytemuck::cast_slice(Mat4::IDENTITY.as_ref()),
-            BufferUsages::UNIFORM,
-        );
-        let light_buffer = renderer.create_buffer(
-            MAX_LIGHT * size_of::<Light>() as BufferAddress,
-            BufferUsages::STORAGE,
-        );
-        let light_count_buffer = renderer.create_buffer(size_of::<u32>() as u64, BufferUsages::UNIFORM);
-        let bind_group_camera = device.create_bind_group(&BindGroupDescriptor {
-            layout: &bind_group_layout_camera,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: vp_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: light_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: light_count_buffer.as_entire_binding(),
-                },
-            ],
-            label: None,
-        });
-        let node_uniform_size = size_of::<Mat4>() as BufferAddress;
-        let w_buffer =
-            renderer.create_buffer(MAX_ENTITY * align(node_uniform_size), BufferUsages::UNIFORM);
-        let r_buffer =
-            renderer.create_buffer(MAX_ENTITY * align(node_uniform_size), BufferUsages::UNIFORM);
         let transform_length_buffer = renderer.create_buffer(size_of::<u32>() as u64, BufferUsages::UNIFORM);
         let bind_group_node = device.create_bind_group(&BindGroupDescriptor {
             layout: &bind_group_layout_node,
             entries: &[
                 BindGroupEntry {
-                    binding: 0, // world transform
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &w_buffer,
-                        offset: 0,
-                        size: BufferSize::new(node_uniform_size),
-                    }),
-                },
-                BindGroupEntry {
-                    binding: 1, // rotation
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &r_buffer,
-                        offset: 0,
-                        size: BufferSize::new(node_uniform_size),
-                    }),
-                },
-                BindGroupEntry {
-                    binding: 2, // combined_transform_map
+                    binding: 0, // combined_transform_map
                     resource: combined_transform_buffer.as_entire_binding(),
                 },
                 BindGroupEntry {
-                    binding: 3, // time
-                    resource: time_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 4, // combined_transform_map_length
+                    binding: 1, // combined_transform_map_length
                     resource: transform_length_buffer.as_entire_binding(),
                 },
                 BindGroupEntry {
-                    binding: 5, // path_length
+                    binding: 2, // path_length
                     resource: path_length_buffer.as_entire_binding(),
                 },
             ],
@@ -409,35 +427,33 @@ impl ShaderDragon {
         });
         let module = device.create_shader_module(ShaderModuleDescriptor {
             label: None,
-            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader_dragon.wgsl"))),
+            source: ShaderSource::Wgsl(Cow::Borrowed(concat!(
+                include_str!("frame.wgsl"),
+                include_str!("shadow.wgsl"),
+                include_str!("environment.wgsl"),
+                include_str!("fog.wgsl"),
+                include_str!("pbr.wgsl"),
+                include_str!("shader_dragon.wgsl")
+            ))),
         });
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        let render_pipeline = ScenePipeline::new(renderer, pipeline_layout, module.clone());
+        // the path deformation runs in vs_main, so shadows follow the dragon's body
+        let shadow_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
             label: None,
-            layout: Some(&pipeline_layout),
+            layout: Some(&shadow_pipeline_layout),
             vertex: VertexState {
                 module: &module,
                 entry_point: None,
                 compilation_options: PipelineCompilationOptions::default(),
-                buffers: &[Vertex::desc()],
+                buffers: &[Vertex::desc(), NodeInstance::desc()],
             },
-            fragment: Some(FragmentState {
-                module: &module,
-                entry_point: None,
-                compilation_options: PipelineCompilationOptions::default(),
-                targets: &[Some(renderer.config.view_formats[0].into())],
-            }),
+            fragment: None,
             primitive: PrimitiveState {
                 front_face: FrontFace::Ccw,
                 cull_mode: Some(Face::Back),
                 ..Default::default()
             },
-            depth_stencil: Some(DepthStencilState {
-                format: TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less,
-                stencil: StencilState::default(),
-                bias: DepthBiasState::default(),
-            }),
+            depth_stencil: Some(Shadows::depth_stencil_state()),
             multisample: MultisampleState::default(),
             multiview: None,
             cache: None,
@@ -447,41 +463,93 @@ impl ShaderDragon {
 
         Self {
             render_pipeline,
-            bind_group_camera,
+            shadow_pipeline,
             bind_group_node,
-            vp_buffer,
-            w_buffer,
-            r_buffer,
-            time_buffer,
-            light_buffer,
-            light_count_buffer,
             combined_transform_buffer,
             path_length_buffer,
+            path_length: Cell::new(path_length),
+            path_bounds: Cell::new(Self::path_bounds(&combined_transforms)),
+            combined_transforms: RefCell::new(combined_transforms.to_vec()),
             // transform_length_buffer,
         }
     }
 }
 impl Shader for ShaderDragon {
-    fn set_pipeline<'a>(&'a self, pass: &mut RenderPass<'a>, offset: BufferAddress) {
-        let offsets = [offset as DynamicOffset, offset as DynamicOffset];
-        pass.set_bind_group(0, &self.bind_group_node, &offsets);
-        pass.set_bind_group(1, &self.bind_group_camera, &[]);
-        pass.set_pipeline(&self.render_pipeline);
+    fn set_pipeline<'a>(&'a self, pass: &mut RenderPass<'a>, blend: BlendMode) {
+        pass.set_bind_group(0, &self.bind_group_node, &[]);
+        pass.set_pipeline(&self.render_pipeline.get(blend));
+    }
+    fn update_pipelines(&self, renderer: &Renderer) {
+        self.render_pipeline.update(renderer);
     }
-    fn write_transform_data(&self, queue: &Queue, offset: BufferAddress, matrix: &[f32; 16]) {
-        queue.write_buffer(&self.w_buffer, offset, bytemuck::bytes_of(matrix));
+    fn set_shadow_pipeline<'a>(&'a self, pass: &mut RenderPass<'a>) -> bool {
+        pass.set_bind_group(0, &self.bind_group_node, &[]);
+        pass.set_pipeline(&self.shadow_pipeline);
+        true
     }
-    fn write_rotation_data(&self, queue: &Queue, offset: BufferAddress, matrix: &[f32; 16]) {
-        queue.write_buffer(&self.r_buffer, offset, bytemuck::bytes_of(matrix));
+    fn deform_vertex(&self, position: Vec3, normal: Vec3, time: f32) -> (Vec3, Vec3) {
+        Self::deform(self.path_transforms(position.x, time), position, normal)
     }
-    fn write_time_data(&self, queue: &Queue, time: f32) {
-        queue.write_buffer(&self.time_buffer, 0, bytemuck::bytes_of(&(time)));
+    /// Anywhere along the path, see `bounds_along`.
+    fn deformed_bounds(&self, mesh: &Mesh) -> Option<Aabb> {
+        Some(Self::bounds_along(self.path_bounds.get(), &mesh.aabb))
     }
-    fn write_camera_data(&self, queue: &Queue, matrix: &[f32; 16]) {
-        queue.write_buffer(&self.vp_buffer, 0, bytemuck::bytes_of(matrix));
+    fn path(&self) -> Option<Vec<Mat4>> {
+        Some(self.combined_transforms.borrow().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(aabb: &Aabb, point: Vec3) -> bool {
+        point.cmpge(aabb.min - 1e-3).all() && point.cmple(aabb.max + 1e-3).all()
     }
-    fn write_light_data(&self, queue: &Queue, lights: &[Light]) {
-        queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(lights));
-        queue.write_buffer(&self.light_count_buffer, 0, bytemuck::bytes_of(&(lights.len() as u32)));
+
+    #[test]
+    fn deformed_bounds_hold_the_model_along_the_path() {
+        let model = Aabb {
+            min: Vec3::new(-30.0, -3.0, -2.0),
+            max: Vec3::new(30.0, 4.0, 2.5),
+        };
+        let patterns = [
+            PathPattern::Random,
+            PathPattern::Circle,
+            PathPattern::Infinity,
+            PathPattern::Sphere,
+        ];
+        // the point on the path `sample_path` gives, and the corners of the
+        // cross section around it
+        let cross_section = [
+            (0.0, 0.0),
+            (model.min.y, model.min.z),
+            (model.max.y, model.max.z),
+            (model.min.y, model.max.z),
+            (model.max.y, model.min.z),
+        ];
+        for pattern in patterns {
+            let (combined_transforms, path_length) = ShaderDragon::generate_path_data(pattern);
+            let path_bounds = ShaderDragon::path_bounds(&combined_transforms);
+            let bounds = ShaderDragon::bounds_along(path_bounds, &model);
+            for time in [0.0, 250.0, 4321.5, 99999.0] {
+                for x in [model.min.x, -7.5, 0.0, 12.25, model.max.x] {
+                    let transforms = ShaderDragon::path_transforms_of(
+                        &combined_transforms,
+                        path_length,
+                        x,
+                        time,
+                    );
+                    for (y, z) in cross_section {
+                        let position = Vec3::new(x, y, z);
+                        let (point, _) = ShaderDragon::deform(transforms, position, Vec3::ZERO);
+                        assert!(
+                            contains(&bounds, point),
+                            "{point} of {pattern:?} at {time} is outside {bounds:?}"
+                        );
+                    }
+                }
+            }
+        }
     }
 }
@@ -1,47 +1,43 @@
-const PI = 3.14159;
 const SPEED = 0.07;
+// the model has no separate eyes, they glow around these points in model
+// space, mirrored across z
+const EYE_POSITION = vec3(39.3, 2.6, 2.0);
+const EYE_RADIUS = 0.75;
+const EYE_GLOW = vec3(12.0, 4.0, 0.5);
 
 struct VertexInput {
     @location(0) position: vec4<f32>,
     @location(1) normal: vec4<f32>,
     @location(2) color: vec4<f32>,
+    @location(3) tangent: vec4<f32>,
+    @location(4) uv: vec2<f32>,
 };
 struct VertexOutput {
     @location(0) color: vec4<f32>,
     @location(1) normal: vec4<f32>,
     @location(2) world_position: vec4<f32>,
+    @location(3) tangent: vec4<f32>,
+    @location(4) uv: vec2<f32>,
+    @location(5) model_position: vec3<f32>,
+    @location(6) @interpolate(flat) base_color: vec4<f32>,
+    @location(7) @interpolate(flat) emissive: vec4<f32>,
+    @location(8) @interpolate(flat) material: vec4<f32>,
     @builtin(position) position: vec4<f32>,
 };
-struct Light {
-    position: vec3f,
-    radius: f32,
-    color: vec4f,
-};
 
 @group(0) @binding(0)
-var<uniform> world: mat4x4<f32>;
-@group(0) @binding(1)
-var<uniform> rotation: mat4x4<f32>;
-@group(0) @binding(2)
 var<storage> combined_transform_map: array<mat4x4<f32>>;
-@group(0) @binding(3)
-var<uniform> time: f32;
-@group(0) @binding(4)
+@group(0) @binding(1)
 var<uniform> combined_transform_map_length: u32;
-@group(0) @binding(5)
+@group(0) @binding(2)
 var<uniform> path_length: f32;
-@group(1) @binding(0)
-var<uniform> view_proj: mat4x4<f32>;
-@group(1) @binding(1)
-var<storage> lights: array<Light>;
-@group(1) @binding(2)
-var<uniform> light_count: u32;
 
 @vertex
-fn vs_main(input: VertexInput) -> VertexOutput {
+fn vs_main(input: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let node = instance_node(instance);
     var result: VertexOutput;
     let n = combined_transform_map_length;
-    let u = (input.position.x + time*SPEED)/path_length*f32(n)+f32(n);
+    let u = (input.position.x + frame.time*SPEED)/path_length*f32(n)+f32(n);
     let u_low = u32(floor(u))%n;
     let u_high = u32(ceil(u))%n;
     let k = fract(u) + step(fract(u), 0.0);
@@ -50,44 +46,62 @@ fn vs_main(input: VertexInput) -> VertexOutput {
     let pos = vec4(0.0, input.position.yz, 1.0);
     let transformed_low = combined_low * pos;
     let transformed_high = combined_high * pos;
-    result.world_position = world * mix(transformed_low, transformed_high, k);
-    result.position = view_proj * result.world_position;
+    result.world_position = node.world * mix(transformed_low, transformed_high, k);
+    result.position = frame.view_proj * result.world_position;
     let normal_low = combined_low * vec4(input.normal.xyz, 0.0);
     let normal_high = combined_high * vec4(input.normal.xyz, 0.0);
-    result.normal = rotation * mix(normal_low, normal_high, k);
+    result.normal = node.rotation * mix(normal_low, normal_high, k);
+    let tangent_low = combined_low * vec4(input.tangent.xyz, 0.0);
+    let tangent_high = combined_high * vec4(input.tangent.xyz, 0.0);
+    result.tangent = vec4((node.rotation * mix(tangent_low, tangent_high, k)).xyz, input.tangent.w);
+    result.uv = input.uv;
+    result.base_color = instance.base_color;
+    result.emissive = instance.emissive;
+    result.material = instance.material;
+    result.model_position = input.position.xyz;
     result.color = input.color;
     return result;
 }
 
 // @vertex
-fn vs_main_circle(input: VertexInput) -> VertexOutput {
+fn vs_main_circle(input: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let node = instance_node(instance);
     let RADIUS = 60.0 - input.position.z;
     var result: VertexOutput;
-    var polar_pos = input.position.x/RADIUS*PI*0.5 + time*SPEED/PI/2;
+    var polar_pos = input.position.x/RADIUS*PI*0.5 + frame.time*SPEED/PI/2;
     var x = cos(polar_pos) * RADIUS;
     var dy = sin(polar_pos) * RADIUS;
     var final_pos = vec4f(x, input.position.y + dy, input.position.z, input.position.w);
     result.color = input.color;
-    result.world_position = world * final_pos;
-    result.position = view_proj * result.world_position;
-    result.normal = rotation * input.normal;
+    result.world_position = node.world * final_pos;
+    result.position = frame.view_proj * result.world_position;
+    result.normal = node.rotation * input.normal;
+    result.tangent = node.rotation * vec4(input.tangent.xyz, 0.0);
+    result.tangent.w = input.tangent.w;
+    result.uv = input.uv;
+    result.base_color = instance.base_color;
+    result.emissive = instance.emissive;
+    result.material = instance.material;
+    result.model_position = input.position.xyz;
     return result;
 }
 
+fn eye_glow(model_position: vec3f) -> vec3f {
+    let p = vec3(model_position.xy, abs(model_position.z));
+    let d = length(p - EYE_POSITION) / EYE_RADIUS;
+    return EYE_GLOW * (1.0 - smoothstep(0.5, 1.0, d));
+}
+
 @fragment
 fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
-    var light_color = vec3(0.0);
-    let ambient = vec3(0.02, 0.02, 0.01);
-    for (var i = 0u; i < light_count; i++) {
-        let pos = lights[i].position;
-        let r = lights[i].radius;
-        let c = lights[i].color.rgb;
-        let world_to_light = pos - vertex.world_position.xyz;
-        let dist = clamp(length(world_to_light), 0.0, r);
-        let radiance = 1.0 - clamp(dist/r, 0.0, 1.0);
-        let strength = max(dot(vertex.normal.xyz, normalize(world_to_light)), 0.0);
-        light_color += c * radiance * strength * lights[i].color.a;
-    }
-    var color = vertex.color.rgb * light_color + ambient;
-    return vec4(color, vertex.color.a);
+    let color = shade(
+        instance_material(vertex.base_color, vertex.emissive, vertex.material),
+        vertex.color,
+        vertex.uv,
+        vertex.world_position.xyz,
+        vertex.normal.xyz,
+        vertex.tangent,
+    );
+    let glowing = color.rgb + eye_glow(vertex.model_position);
+    return vec4(apply_fog(glowing, vertex.world_position.xyz), color.a);
 }
@@ -1,216 +1,107 @@
-use crate::material::Shader;
-use glam::Mat4;
+use crate::material::{BlendMode, ScenePipeline, Shader};
 use std::borrow::Cow;
-use std::mem::size_of;
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::Instant;
 #[cfg(target_arch = "wasm32")]
 use web_time::Instant;
-use wgpu::util::align_to;
 use wgpu::{
-    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferAddress, BufferBinding,
-    BufferBindingType, BufferSize, BufferUsages, CompareFunction, DepthBiasState,
-    DepthStencilState, DynamicOffset, Face, FragmentState, FrontFace, MultisampleState,
-    PipelineCompilationOptions, PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass,
-    RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages,
-    StencilState, TextureFormat, VertexState,
+    BindGroup, BindGroupDescriptor, BindGroupLayoutDescriptor, Face, FrontFace, MultisampleState, PipelineCompilationOptions, PipelineLayoutDescriptor,
+    PrimitiveState, RenderPass, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor,
+    ShaderSource, VertexState,
 };
 
 use crate::geometry::Vertex;
-use crate::world::{Light, MAX_ENTITY, MAX_LIGHT, Renderer};
+use crate::world::{NodeInstance, Renderer, Shadows};
 
 pub struct ShaderLit {
-    pub render_pipeline: RenderPipeline,
-    pub bind_group_camera: BindGroup,
+    pub render_pipeline: ScenePipeline,
+    pub shadow_pipeline: RenderPipeline,
+    /// Empty, lit entities need nothing besides their instance, but group 0
+    /// keeps the other groups at the same index as in every scene shader
     pub bind_group_node: BindGroup,
-    pub vp_buffer: Buffer,
-    pub w_buffer: Buffer,
-    pub r_buffer: Buffer,
-    pub light_buffer: Buffer,
 }
 impl ShaderLit {
     pub fn new(renderer: &Renderer) -> Self {
         let device = &renderer.device;
         let new_shader_timestamp = Instant::now();
-        let align = |n| {
-            let alignment = device.limits().min_uniform_buffer_offset_alignment as BufferAddress;
-            align_to(n, alignment)
-        };
-        let bind_group_layout_camera =
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[
-                    BindGroupLayoutEntry {
-                        binding: 0, // view projection
-                        visibility: ShaderStages::VERTEX,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: BufferSize::new(size_of::<Mat4>() as u64),
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 1, // light
-                        visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: BufferSize::new(0),
-                        },
-                        count: None,
-                    },
-                ],
-            });
         let bind_group_layout_node = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: None,
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0, // world
-                    visibility: ShaderStages::VERTEX,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: true,
-                        min_binding_size: BufferSize::new(size_of::<Mat4>() as u64),
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 1, // rotation
-                    visibility: ShaderStages::VERTEX,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: true,
-                        min_binding_size: BufferSize::new(size_of::<Mat4>() as u64),
-                    },
-                    count: None,
-                },
-            ],
+            entries: &[],
         });
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[&bind_group_layout_node, &bind_group_layout_camera],
+            bind_group_layouts: &[
+                &bind_group_layout_node,
+                &renderer.frame.layout,
+                &renderer.scene_bind_group_layout,
+                &renderer.textures.bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let shadow_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[
+                &bind_group_layout_node,
+                &renderer.shadows.pass_bind_group_layout,
+            ],
             push_constant_ranges: &[],
         });
         let module = device.create_shader_module(ShaderModuleDescriptor {
             label: None,
-            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader_lit.wgsl"))),
+            source: ShaderSource::Wgsl(Cow::Borrowed(concat!(
+                include_str!("frame.wgsl"),
+                include_str!("shadow.wgsl"),
+                include_str!("environment.wgsl"),
+                include_str!("fog.wgsl"),
+                include_str!("pbr.wgsl"),
+                include_str!("shader_lit.wgsl")
+            ))),
         });
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        let render_pipeline = ScenePipeline::new(renderer, pipeline_layout, module.clone());
+        let shadow_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
             label: None,
-            layout: Some(&pipeline_layout),
+            layout: Some(&shadow_pipeline_layout),
             vertex: VertexState {
                 module: &module,
                 entry_point: None,
                 compilation_options: PipelineCompilationOptions::default(),
-                buffers: &[Vertex::desc()],
+                buffers: &[Vertex::desc(), NodeInstance::desc()],
             },
-            fragment: Some(FragmentState {
-                module: &module,
-                entry_point: None,
-                compilation_options: PipelineCompilationOptions::default(),
-                targets: &[Some(renderer.config.format.into())],
-            }),
+            fragment: None,
             primitive: PrimitiveState {
                 front_face: FrontFace::Ccw,
                 cull_mode: Some(Face::Back),
                 ..Default::default()
             },
-            depth_stencil: Some(DepthStencilState {
-                format: TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less,
-                stencil: StencilState::default(),
-                bias: DepthBiasState::default(),
-            }),
+            depth_stencil: Some(Shadows::depth_stencil_state()),
             multisample: MultisampleState::default(),
             multiview: None,
             cache: None,
         });
-        let vp_buffer = renderer.create_buffer_init(
-            bytemuck::cast_slice(Mat4::IDENTITY.as_ref()),
-            BufferUsages::UNIFORM,
-        );
-        let light_uniform_size = size_of::<Light>() as BufferAddress;
-        let light_buffer = renderer.create_buffer(
-            MAX_LIGHT as BufferAddress * light_uniform_size,
-            BufferUsages::STORAGE,
-        );
-        let bind_group_camera = device.create_bind_group(&BindGroupDescriptor {
-            layout: &bind_group_layout_camera,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: vp_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: light_buffer.as_entire_binding(),
-                },
-            ],
-            label: None,
-        });
-        let node_uniform_size = size_of::<Mat4>() as BufferAddress;
-        let w_buffer = renderer.create_buffer(
-            MAX_ENTITY as BufferAddress * align(node_uniform_size),
-            BufferUsages::UNIFORM,
-        );
-        let r_buffer = renderer.create_buffer(
-            MAX_ENTITY as BufferAddress * align(node_uniform_size),
-            BufferUsages::UNIFORM,
-        );
         let bind_group_node = device.create_bind_group(&BindGroupDescriptor {
             layout: &bind_group_layout_node,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0, // world transform
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &w_buffer,
-                        offset: 0,
-                        size: BufferSize::new(node_uniform_size),
-                    }),
-                },
-                BindGroupEntry {
-                    binding: 1, // rotation
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &r_buffer,
-                        offset: 0,
-                        size: BufferSize::new(node_uniform_size),
-                    }),
-                },
-            ],
+            entries: &[],
             label: None,
         });
         log::info!("created shader in {:?}", new_shader_timestamp.elapsed());
         Self {
             render_pipeline,
-            bind_group_camera,
+            shadow_pipeline,
             bind_group_node,
-            vp_buffer,
-            w_buffer,
-            r_buffer,
-            light_buffer,
         }
     }
 }
 impl Shader for ShaderLit {
-    fn set_pipeline<'a>(&'a self, pass: &mut RenderPass<'a>, offset: BufferAddress) {
-        let offsets = [offset as DynamicOffset, offset as DynamicOffset];
-        pass.set_bind_group(0, &self.bind_group_node, &offsets);
-        pass.set_bind_group(1, &self.bind_group_camera, &[]);
-        pass.set_pipeline(&self.render_pipeline);
-    }
-    fn write_transform_data(&self, queue: &Queue, offset: BufferAddress, matrix: &[f32; 16]) {
-        queue.write_buffer(&self.w_buffer, offset, bytemuck::bytes_of(matrix));
-    }
-    fn write_rotation_data(&self, queue: &Queue, offset: BufferAddress, matrix: &[f32; 16]) {
-        queue.write_buffer(&self.r_buffer, offset, bytemuck::bytes_of(matrix));
+    fn set_pipeline<'a>(&'a self, pass: &mut RenderPass<'a>, blend: BlendMode) {
+        pass.set_bind_group(0, &self.bind_group_node, &[]);
+        pass.set_pipeline(&self.render_pipeline.get(blend));
     }
-    fn write_camera_data(&self, queue: &Queue, matrix: &[f32; 16]) {
-        queue.write_buffer(&self.vp_buffer, 0, bytemuck::bytes_of(matrix));
+    fn update_pipelines(&self, renderer: &Renderer) {
+        self.render_pipeline.update(renderer);
     }
-    fn write_light_data(&self, queue: &Queue, lights: &[Light]) {
-        queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(lights));
+    fn set_shadow_pipeline<'a>(&'a self, pass: &mut RenderPass<'a>) -> bool {
+        pass.set_bind_group(0, &self.bind_group_node, &[]);
+        pass.set_pipeline(&self.shadow_pipeline);
+        true
     }
 }
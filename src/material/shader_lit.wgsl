@@ -1,54 +1,48 @@
-const MAX_LIGHT = 10;
 struct VertexInput {
     @location(0) position: vec4<f32>,
     @location(1) normal: vec4<f32>,
     @location(2) color: vec4<f32>,
+    @location(3) tangent: vec4<f32>,
+    @location(4) uv: vec2<f32>,
 };
 struct VertexOutput {
     @location(0) color: vec4<f32>,
     @location(1) normal: vec4<f32>,
     @location(2) world_position: vec4<f32>,
+    @location(3) tangent: vec4<f32>,
+    @location(4) uv: vec2<f32>,
+    @location(5) @interpolate(flat) base_color: vec4<f32>,
+    @location(6) @interpolate(flat) emissive: vec4<f32>,
+    @location(7) @interpolate(flat) material: vec4<f32>,
     @builtin(position) position: vec4<f32>,
 };
-struct Light {
-    position: vec3f,
-    radius: f32,
-    color: vec4f,
-};
-
-@group(0) @binding(0)
-var<uniform> world: mat4x4<f32>;
-@group(0) @binding(1)
-var<uniform> rotation: mat4x4<f32>;
-@group(1) @binding(0)
-var<uniform> view_proj: mat4x4<f32>;
 
 @vertex
-fn vs_main(input: VertexInput) -> VertexOutput {
+fn vs_main(input: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let node = instance_node(instance);
     var result: VertexOutput;
     result.color = input.color;
-    result.world_position = world * input.position;
-    result.position = view_proj * result.world_position;
-    result.normal = rotation * input.normal;
+    result.world_position = node.world * input.position;
+    result.position = frame.view_proj * result.world_position;
+    result.normal = node.rotation * input.normal;
+    let tangent = node.rotation * vec4(input.tangent.xyz, 0.0);
+    result.tangent = vec4(normalize(tangent.xyz), input.tangent.w);
+    result.uv = input.uv;
+    result.base_color = instance.base_color;
+    result.emissive = instance.emissive;
+    result.material = instance.material;
     return result;
 }
 
-@group(1) @binding(1)
-var<storage> lights: array<Light>;
-
 @fragment
 fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
-    var color = vec3(0.0);
-    let n = arrayLength(&lights);
-    for (var i = 0u; i < n; i++) {
-        let pos = lights[i].position;
-        let r = lights[i].radius;
-        let c = lights[i].color.rgb;
-        let world_to_light = pos - vertex.world_position.xyz;
-        let dist = clamp(length(world_to_light), 0.0, r);
-        let radiance = 1.0 - clamp(dist/r, 0.0, 1.0);
-        let strength = max(dot(vertex.normal.xyz, normalize(world_to_light)), 0.0);
-        color += vertex.color.rgb * radiance * strength;
-    }
-    return vec4(color, vertex.color.a);
+    let color = shade(
+        instance_material(vertex.base_color, vertex.emissive, vertex.material),
+        vertex.color,
+        vertex.uv,
+        vertex.world_position.xyz,
+        vertex.normal.xyz,
+        vertex.tangent,
+    );
+    return vec4(apply_fog(color.rgb, vertex.world_position.xyz), color.a);
 }
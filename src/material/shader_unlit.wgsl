@@ -1,27 +1,31 @@
-struct VertexInput {
-    @location(0) position: vec4<f32>,
-    @location(1) normal: vec4<f32>,
-    @location(2) color: vec4<f32>,
-};
-struct VertexOutput {
-    @location(0) color: vec4<f32>,
-    @builtin(position) position: vec4<f32>,
-};
-
-@group(0) @binding(0)
-var<uniform> world: mat4x4<f32>;
-@group(1) @binding(0)
-var<uniform> view_proj: mat4x4<f32>;
-
-@vertex
-fn vs_main(input: VertexInput) -> VertexOutput {
-    var result: VertexOutput;
-    result.color = input.color;
-    result.position = view_proj * world * input.position;
-    return result;
-}
-
-@fragment
-fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
-    return vertex.color;
-}
+struct VertexInput {
+    @location(0) position: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) color: vec4<f32>,
+};
+struct VertexOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) world_position: vec4<f32>,
+    @builtin(position) position: vec4<f32>,
+};
+
+// no light reaches unlit entities, they show the vertex color tinted by the
+// base color, along with what they give off
+@vertex
+fn vs_main(input: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let node = instance_node(instance);
+    var result: VertexOutput;
+    let emissive = instance.emissive.xyz * instance.emissive.w;
+    result.color = vec4(
+        input.color.rgb * instance.base_color.rgb + emissive,
+        input.color.a * instance.base_color.a,
+    );
+    result.world_position = node.world * input.position;
+    result.position = frame.view_proj * result.world_position;
+    return result;
+}
+
+@fragment
+fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(apply_fog(vertex.color.rgb, vertex.world_position.xyz), vertex.color.a);
+}
@@ -0,0 +1,80 @@
+// Shared by lit pipelines, prepended to their source. Group 2 is owned by
+// the renderer, bindings 0 to 3 hold the shadow maps, see world/shadow.rs.
+const CASCADE_COUNT = 3u;
+const SHADOW_NORMAL_OFFSET = 0.05;
+
+struct ShadowUniforms {
+    sun_direction: vec4f,
+    sun_color: vec4f,
+    eye_position: vec4f,
+    eye_forward: vec4f,
+    cascade_splits: vec4f,
+    cascade_view_proj: array<mat4x4f, 3>,
+    point_view_proj: array<mat4x4f, 24>,
+};
+
+@group(2) @binding(0)
+var<uniform> shadow: ShadowUniforms;
+@group(2) @binding(1)
+var sun_shadow_map: texture_depth_2d_array;
+@group(2) @binding(2)
+var point_shadow_map: texture_depth_2d_array;
+@group(2) @binding(3)
+var shadow_sampler: sampler_comparison;
+
+// 3x3 PCF, 1 is fully lit, 0 is fully in shadow
+fn sample_shadow(map: texture_depth_2d_array, layer: u32, view_proj: mat4x4f, world_position: vec3f) -> f32 {
+    let clip = view_proj * vec4(world_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+    if any(uv < vec2(0.0)) || any(uv > vec2(1.0)) || ndc.z > 1.0 || ndc.z < 0.0 {
+        return 1.0;
+    }
+    let texel = 1.0 / vec2f(textureDimensions(map));
+    var lit = 0.0;
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            let offset = vec2(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(map, shadow_sampler, uv + offset, layer, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// 1 where the sun reaches `world_position`, 0 where it is in shadow
+fn sun_shadow(world_position: vec3f, normal: vec3f) -> f32 {
+    if shadow.sun_color.w == 0.0 {
+        return 1.0;
+    }
+    let depth = dot(world_position - shadow.eye_position.xyz, shadow.eye_forward.xyz);
+    var cascade = 0u;
+    for (var i = 0u; i < CASCADE_COUNT; i++) {
+        if depth > shadow.cascade_splits[i] {
+            cascade = i + 1u;
+        }
+    }
+    if cascade >= CASCADE_COUNT {
+        return 1.0;
+    }
+    let position = world_position + normal * SHADOW_NORMAL_OFFSET * f32(cascade + 1u);
+    return sample_shadow(sun_shadow_map, cascade, shadow.cascade_view_proj[cascade], position);
+}
+
+fn point_shadow(index: i32, light_position: vec3f, world_position: vec3f, normal: vec3f) -> f32 {
+    if index < 0 {
+        return 1.0;
+    }
+    let d = world_position - light_position;
+    let a = abs(d);
+    var face = 0u;
+    if a.x >= a.y && a.x >= a.z {
+        face = select(1u, 0u, d.x > 0.0);
+    } else if a.y >= a.z {
+        face = select(3u, 2u, d.y > 0.0);
+    } else {
+        face = select(5u, 4u, d.z > 0.0);
+    }
+    let layer = u32(index) * 6u + face;
+    let position = world_position + normal * SHADOW_NORMAL_OFFSET;
+    return sample_shadow(point_shadow_map, layer, shadow.point_view_proj[layer], position);
+}
@@ -0,0 +1,466 @@
+use image::ImageError;
+use std::borrow::Cow;
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::rc::Rc;
+use wgpu::{
+    AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Color,
+    CommandEncoderDescriptor, Device, Extent3d, FilterMode, FragmentState, LoadOp,
+    MultisampleState, Operations, Origin3d, PipelineCompilationOptions, PipelineLayoutDescriptor,
+    PrimitiveState, Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StoreOp, TexelCopyBufferLayout,
+    TexelCopyTextureInfo, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
+    VertexState,
+};
+
+/// How the texels of an image are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureKind {
+    /// sRGB encoded colors, decoded to linear when sampled
+    Color,
+    /// Linear values, like normal maps
+    Data,
+}
+
+impl TextureKind {
+    const ALL: [TextureKind; 2] = [TextureKind::Color, TextureKind::Data];
+
+    fn format(self) -> TextureFormat {
+        match self {
+            TextureKind::Color => TextureFormat::Rgba8UnormSrgb,
+            TextureKind::Data => TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerSettings {
+    pub address_mode: AddressMode,
+    pub filter: FilterMode,
+    /// Generate and sample mip levels
+    pub mipmaps: bool,
+    /// Maximum anisotropy, 1 turns anisotropic filtering off. Only used
+    /// with linear filtering.
+    pub anisotropy: u16,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            address_mode: AddressMode::Repeat,
+            filter: FilterMode::Linear,
+            mipmaps: true,
+            anisotropy: 8,
+        }
+    }
+}
+
+pub struct Texture {
+    pub texture: wgpu::Texture,
+    pub view: TextureView,
+    pub sampler: Rc<Sampler>,
+}
+
+/// Maps bound as group 3 by textured pipelines, see
+/// [`TextureCache::create_set`].
+pub struct TextureSet {
+    pub bind_group: BindGroup,
+}
+
+/// Loads images into GPU textures once per name, and owns the samplers and
+/// the bind group layout they are used with.
+pub struct TextureCache {
+    pub bind_group_layout: BindGroupLayout,
+    /// White base color and flat normal, for entities without maps
+    pub default_set: Rc<TextureSet>,
+    textures: HashMap<(String, TextureKind, SamplerSettings), Rc<Texture>>,
+    samplers: HashMap<SamplerSettings, Rc<Sampler>>,
+    white: Rc<Texture>,
+    flat_normal: Rc<Texture>,
+    mipmaps: MipmapGenerator,
+}
+
+impl TextureCache {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let texture_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Texture Bind Group Layout"),
+            entries: &[
+                texture_entry(0), // base color
+                sampler_entry(1),
+                texture_entry(2), // normal
+                sampler_entry(3),
+            ],
+        });
+        let mipmaps = MipmapGenerator::new(device);
+        let mut samplers = HashMap::new();
+        let settings = SamplerSettings::default();
+        let sampler = Rc::new(create_sampler(device, settings));
+        samplers.insert(settings, sampler.clone());
+        let white = Rc::new(Self::upload(
+            device,
+            queue,
+            None,
+            "White",
+            (1, 1, &[255, 255, 255, 255]),
+            TextureKind::Color,
+            sampler.clone(),
+        ));
+        let flat_normal = Rc::new(Self::upload(
+            device,
+            queue,
+            None,
+            "Flat Normal",
+            (1, 1, &[128, 128, 255, 255]),
+            TextureKind::Data,
+            sampler,
+        ));
+        let default_set = Rc::new(Self::bind(device, &bind_group_layout, &white, &flat_normal));
+        Self {
+            bind_group_layout,
+            default_set,
+            textures: HashMap::new(),
+            samplers,
+            white,
+            flat_normal,
+            mipmaps,
+        }
+    }
+
+    /// Decode a PNG or JPEG image, like one embedded with `include_bytes!`.
+    /// Loading the same name again returns the cached texture.
+    pub fn load_bytes(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        name: &str,
+        bytes: &[u8],
+        kind: TextureKind,
+        settings: SamplerSettings,
+    ) -> Result<Rc<Texture>, ImageError> {
+        let key = (name.to_string(), kind, settings);
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+        let image = image::load_from_memory(bytes)?.to_rgba8();
+        let texture = self.create(device, queue, name, &image, kind, settings);
+        self.textures.insert(key, texture.clone());
+        Ok(texture)
+    }
+
+    /// Read and decode a PNG or JPEG file, not available on the web where
+    /// images have to be embedded and loaded with [`Self::load_bytes`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_file(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        path: impl AsRef<Path>,
+        kind: TextureKind,
+        settings: SamplerSettings,
+    ) -> Result<Rc<Texture>, ImageError> {
+        let name = path.as_ref().to_string_lossy().into_owned();
+        let key = (name.clone(), kind, settings);
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+        let image = image::open(path)?.to_rgba8();
+        let texture = self.create(device, queue, &name, &image, kind, settings);
+        self.textures.insert(key, texture.clone());
+        Ok(texture)
+    }
+
+    /// Bind a base color and a tangent space normal map together, missing
+    /// maps are replaced by white and a flat normal.
+    pub fn create_set(
+        &self,
+        device: &Device,
+        base_color: Option<&Rc<Texture>>,
+        normal: Option<&Rc<Texture>>,
+    ) -> Rc<TextureSet> {
+        Rc::new(Self::bind(
+            device,
+            &self.bind_group_layout,
+            base_color.unwrap_or(&self.white),
+            normal.unwrap_or(&self.flat_normal),
+        ))
+    }
+
+    fn create(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        name: &str,
+        image: &image::RgbaImage,
+        kind: TextureKind,
+        settings: SamplerSettings,
+    ) -> Rc<Texture> {
+        let sampler = self
+            .samplers
+            .entry(settings)
+            .or_insert_with(|| Rc::new(create_sampler(device, settings)))
+            .clone();
+        let mipmaps = settings.mipmaps.then_some(&self.mipmaps);
+        let (width, height) = image.dimensions();
+        let texels = (width, height, image.as_raw().as_slice());
+        Rc::new(Self::upload(device, queue, mipmaps, name, texels, kind, sampler))
+    }
+
+    fn upload(
+        device: &Device,
+        queue: &Queue,
+        mipmaps: Option<&MipmapGenerator>,
+        name: &str,
+        (width, height, rgba): (u32, u32, &[u8]),
+        kind: TextureKind,
+        sampler: Rc<Sampler>,
+    ) -> Texture {
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let mip_level_count = match mipmaps {
+            Some(_) => size.max_mips(TextureDimension::D2),
+            None => 1,
+        };
+        let mut usage = TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST;
+        if mip_level_count > 1 {
+            usage |= TextureUsages::RENDER_ATTACHMENT;
+        }
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(name),
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: kind.format(),
+            usage,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            rgba,
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+        if let Some(mipmaps) = mipmaps {
+            mipmaps.generate(device, queue, &texture);
+        }
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    fn bind(
+        device: &Device,
+        layout: &BindGroupLayout,
+        base_color: &Texture,
+        normal: &Texture,
+    ) -> TextureSet {
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Texture Bind Group"),
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&base_color.view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&base_color.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&normal.view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Sampler(&normal.sampler),
+                },
+            ],
+        });
+        TextureSet { bind_group }
+    }
+}
+
+fn create_sampler(device: &Device, settings: SamplerSettings) -> Sampler {
+    let linear = settings.filter == FilterMode::Linear;
+    device.create_sampler(&SamplerDescriptor {
+        label: Some("Texture Sampler"),
+        address_mode_u: settings.address_mode,
+        address_mode_v: settings.address_mode,
+        address_mode_w: settings.address_mode,
+        mag_filter: settings.filter,
+        min_filter: settings.filter,
+        mipmap_filter: settings.filter,
+        lod_max_clamp: if settings.mipmaps { 32.0 } else { 0.0 },
+        anisotropy_clamp: if linear { settings.anisotropy.clamp(1, 16) } else { 1 },
+        ..Default::default()
+    })
+}
+
+/// Fills in mip levels by repeatedly rendering each level into the next
+/// with linear filtering. sRGB textures are averaged in linear space.
+struct MipmapGenerator {
+    bind_group_layout: BindGroupLayout,
+    sampler: Sampler,
+    pipelines: Vec<(TextureFormat, RenderPipeline)>,
+}
+
+impl MipmapGenerator {
+    fn new(device: &Device) -> Self {
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Mipmap Shader"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("mipmap.wgsl"))),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Mipmap Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipelines = TextureKind::ALL
+            .iter()
+            .map(|kind| {
+                let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+                    label: Some("Mipmap Pipeline"),
+                    layout: Some(&layout),
+                    vertex: VertexState {
+                        module: &module,
+                        entry_point: None,
+                        compilation_options: PipelineCompilationOptions::default(),
+                        buffers: &[],
+                    },
+                    fragment: Some(FragmentState {
+                        module: &module,
+                        entry_point: None,
+                        compilation_options: PipelineCompilationOptions::default(),
+                        targets: &[Some(kind.format().into())],
+                    }),
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    multiview: None,
+                    cache: None,
+                });
+                (kind.format(), pipeline)
+            })
+            .collect();
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Mipmap Sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        Self {
+            bind_group_layout,
+            sampler,
+            pipelines,
+        }
+    }
+
+    fn generate(&self, device: &Device, queue: &Queue, texture: &wgpu::Texture) {
+        let Some((_, pipeline)) = self.pipelines.iter().find(|(f, _)| *f == texture.format())
+        else {
+            log::error!("no mipmap pipeline for {:?}", texture.format());
+            return;
+        };
+        let views: Vec<TextureView> = (0..texture.mip_level_count())
+            .map(|level| {
+                texture.create_view(&TextureViewDescriptor {
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        for levels in views.windows(2) {
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &self.bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&levels[0]),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            });
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Mipmap Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &levels[1],
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::BLACK),
+                        store: StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                ..Default::default()
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+}
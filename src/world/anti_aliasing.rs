@@ -0,0 +1,47 @@
+use crate::world::HDR_FORMAT;
+use std::fmt;
+use wgpu::{Adapter, Features, TextureFormat};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AntiAliasing {
+    Off,
+    /// Multisampling with this many samples per pixel
+    Msaa(u32),
+    /// Edge blur after tone mapping, for when multisampling isn't available
+    Fxaa,
+}
+
+impl AntiAliasing {
+    pub fn sample_count(self) -> u32 {
+        match self {
+            AntiAliasing::Msaa(samples) => samples,
+            AntiAliasing::Off | AntiAliasing::Fxaa => 1,
+        }
+    }
+
+    /// Modes the adapter can render the scene with. Sample counts other than
+    /// 4 need `features` to include adapter specific format features.
+    pub fn supported(adapter: &Adapter, features: Features) -> Vec<AntiAliasing> {
+        let adapter_specific = features.contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        let color = adapter.get_texture_format_features(HDR_FORMAT).flags;
+        let depth = adapter.get_texture_format_features(TextureFormat::Depth32Float).flags;
+        let msaa = [2, 4, 8]
+            .into_iter()
+            .filter(|&samples| samples == 4 || adapter_specific)
+            .filter(|&samples| {
+                color.sample_count_supported(samples) && depth.sample_count_supported(samples)
+            })
+            .map(AntiAliasing::Msaa);
+        [AntiAliasing::Off].into_iter().chain(msaa).chain([AntiAliasing::Fxaa]).collect()
+    }
+}
+
+impl fmt::Display for AntiAliasing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AntiAliasing::Off => write!(f, "Off"),
+            AntiAliasing::Msaa(samples) => write!(f, "MSAA {samples}x"),
+            AntiAliasing::Fxaa => write!(f, "FXAA"),
+        }
+    }
+}
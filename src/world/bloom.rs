@@ -0,0 +1,305 @@
+use crate::world::HDR_FORMAT;
+use bytemuck::{Pod, Zeroable};
+use std::borrow::Cow;
+use std::mem::size_of;
+use wgpu::{
+    AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BlendComponent,
+    BlendFactor, BlendOperation, BlendState, Buffer, BufferAddress, BufferBindingType,
+    BufferDescriptor, BufferSize, BufferUsages, Color, ColorTargetState, ColorWrites,
+    CommandEncoder, Device, Extent3d, FilterMode, FragmentState, LoadOp, MultisampleState,
+    Operations, PipelineCompilationOptions, PipelineLayoutDescriptor, PrimitiveState, Queue,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
+    Sampler, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, StoreOp, TextureDescriptor, TextureDimension, TextureSampleType,
+    TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+/// Upper bound of mips in the blur chain, each one doubles the blur width.
+const MAX_BLOOM_MIPS: u32 = 6;
+/// The chain stops before a mip gets smaller than this.
+const MIN_BLOOM_SIZE: u32 = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    /// Scene brightness where bloom starts, in linear HDR units
+    pub threshold: f32,
+    /// Softens the cut at the threshold, 0 is a hard cut
+    pub knee: f32,
+    /// Share of the blurred light added back to the scene
+    pub intensity: f32,
+    /// Spread of each upsampling step, in texels
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.15,
+            radius: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct BloomUniforms {
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+    _padding: f32,
+}
+
+/// Half resolution mip chain blurring the bright parts of the HDR scene,
+/// the result is composited by the tone mapping pass.
+pub struct Bloom {
+    mip_views: Vec<TextureView>,
+    /// `bind_groups[0]` samples the HDR scene, `bind_groups[i + 1]` mip `i`
+    bind_groups: Vec<BindGroup>,
+    bind_group_layout: BindGroupLayout,
+    uniform_buffer: Buffer,
+    sampler: Sampler,
+    prefilter_pipeline: RenderPipeline,
+    downsample_pipeline: RenderPipeline,
+    upsample_pipeline: RenderPipeline,
+}
+
+impl Bloom {
+    pub fn new(device: &Device, hdr_view: &TextureView, width: u32, height: u32) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Bloom Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0, // bloom uniforms
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(size_of::<BloomUniforms>() as u64),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1, // source mip
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Bloom Shader"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("bloom.wgsl"))),
+        });
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point, blend| {
+            let targets = [Some(ColorTargetState {
+                format: HDR_FORMAT,
+                blend,
+                write_mask: ColorWrites::ALL,
+            })];
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                vertex: VertexState {
+                    module: &module,
+                    entry_point: Some("vs_main"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    buffers: &[],
+                },
+                fragment: Some(FragmentState {
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    targets: &targets,
+                }),
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let additive = BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+        let prefilter_pipeline = create_pipeline("fs_prefilter", None);
+        let downsample_pipeline = create_pipeline("fs_downsample", None);
+        let upsample_pipeline = create_pipeline(
+            "fs_upsample",
+            Some(BlendState {
+                color: additive,
+                alpha: additive,
+            }),
+        );
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Bloom Uniforms"),
+            size: size_of::<BloomUniforms>() as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Bloom Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let mut bloom = Self {
+            mip_views: Self::create_chain(device, width, height),
+            bind_groups: Vec::new(),
+            bind_group_layout,
+            uniform_buffer,
+            sampler,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+        };
+        bloom.bind_groups = bloom.create_bind_groups(device, hdr_view);
+        bloom
+    }
+
+    /// Recreate the chain for a new scene size, `hdr_view` is the scene
+    /// target the chain starts from.
+    pub fn resize(&mut self, device: &Device, hdr_view: &TextureView, width: u32, height: u32) {
+        self.mip_views = Self::create_chain(device, width, height);
+        self.bind_groups = self.create_bind_groups(device, hdr_view);
+    }
+
+    /// Mip 0 of the chain, holding the final blur after [`Bloom::render`].
+    pub fn view(&self) -> &TextureView {
+        &self.mip_views[0]
+    }
+
+    fn create_bind_groups(&self, device: &Device, hdr_view: &TextureView) -> Vec<BindGroup> {
+        std::iter::once(hdr_view)
+            .chain(&self.mip_views)
+            .map(|source| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Bloom Bind Group"),
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: self.uniform_buffer.as_entire_binding(),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(source),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                })
+            })
+            .collect()
+    }
+
+    /// Blur the bright parts of the scene into [`Bloom::view`].
+    pub fn render(&self, queue: &Queue, encoder: &mut CommandEncoder, settings: &BloomSettings) {
+        let uniforms = BloomUniforms {
+            threshold: settings.threshold,
+            knee: settings.knee,
+            radius: settings.radius,
+            _padding: 0.0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        for (i, target) in self.mip_views.iter().enumerate() {
+            let pipeline = if i == 0 {
+                &self.prefilter_pipeline
+            } else {
+                &self.downsample_pipeline
+            };
+            self.blit(encoder, pipeline, &self.bind_groups[i], target, LoadOp::Clear(Color::BLACK));
+        }
+        for i in (1..self.mip_views.len()).rev() {
+            let target = &self.mip_views[i - 1];
+            let load = LoadOp::Load;
+            self.blit(encoder, &self.upsample_pipeline, &self.bind_groups[i + 1], target, load);
+        }
+    }
+
+    fn blit(
+        &self,
+        encoder: &mut CommandEncoder,
+        pipeline: &RenderPipeline,
+        bind_group: &BindGroup,
+        target: &TextureView,
+        load: LoadOp<Color>,
+    ) {
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Bloom Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations {
+                    load,
+                    store: StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    /// One view per mip of a new chain for a scene of `width` by `height`.
+    fn create_chain(device: &Device, width: u32, height: u32) -> Vec<TextureView> {
+        let (width, height) = ((width / 2).max(1), (height / 2).max(1));
+        let mut mip_level_count = 1;
+        while mip_level_count < MAX_BLOOM_MIPS
+            && (width.min(height) >> mip_level_count) >= MIN_BLOOM_SIZE
+        {
+            mip_level_count += 1;
+        }
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Bloom Texture"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        (0..mip_level_count)
+            .map(|mip| {
+                texture.create_view(&TextureViewDescriptor {
+                    label: Some("Bloom Mip"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,89 @@
+// Bloom mip chain, see world/bloom.rs. The bright parts of the HDR scene
+// are downsampled into ever smaller mips, which are then upsampled and
+// added back up the chain, giving a wide and smooth blur.
+struct BloomUniforms {
+    threshold: f32,
+    // width of the soft transition below the threshold
+    knee: f32,
+    // upsampling tent size in source texels
+    radius: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> bloom: BloomUniforms;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(2)
+var source_sampler: sampler;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var result: VertexOutput;
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+    result.position = vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    result.uv = uv;
+    return result;
+}
+
+fn tap(uv: vec2f, offset: vec2f) -> vec3f {
+    let texel = 1.0 / vec2f(textureDimensions(source));
+    return textureSample(source, source_sampler, uv + offset * texel).rgb;
+}
+
+// 13 taps in overlapping 2x2 boxes, from Jimenez' Call of Duty talk,
+// which keeps small bright spots from flickering while they move
+fn downsample(uv: vec2f) -> vec3f {
+    let a = tap(uv, vec2(-2.0, -2.0));
+    let b = tap(uv, vec2(0.0, -2.0));
+    let c = tap(uv, vec2(2.0, -2.0));
+    let d = tap(uv, vec2(-2.0, 0.0));
+    let e = tap(uv, vec2(0.0, 0.0));
+    let f = tap(uv, vec2(2.0, 0.0));
+    let g = tap(uv, vec2(-2.0, 2.0));
+    let h = tap(uv, vec2(0.0, 2.0));
+    let i = tap(uv, vec2(2.0, 2.0));
+    let j = tap(uv, vec2(-1.0, -1.0));
+    let k = tap(uv, vec2(1.0, -1.0));
+    let l = tap(uv, vec2(-1.0, 1.0));
+    let m = tap(uv, vec2(1.0, 1.0));
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+}
+
+// keep what is brighter than the threshold, fading in over the knee
+fn threshold(color: vec3f) -> vec3f {
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = max(bloom.knee, 1e-4);
+    var soft = clamp(brightness - bloom.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee);
+    return color * max(soft, brightness - bloom.threshold) / max(brightness, 1e-4);
+}
+
+@fragment
+fn fs_prefilter(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    // a single extreme pixel would otherwise swamp the whole blur
+    let color = clamp(downsample(vertex.uv), vec3(0.0), vec3(65000.0));
+    return vec4(threshold(color), 1.0);
+}
+
+@fragment
+fn fs_downsample(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(downsample(vertex.uv), 1.0);
+}
+
+// 3x3 tent, blended additively onto the next larger mip
+@fragment
+fn fs_upsample(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    let r = bloom.radius;
+    let color = (tap(vertex.uv, vec2(-r, -r)) + tap(vertex.uv, vec2(r, -r))
+        + tap(vertex.uv, vec2(-r, r)) + tap(vertex.uv, vec2(r, r))) * 0.0625
+        + (tap(vertex.uv, vec2(0.0, -r)) + tap(vertex.uv, vec2(-r, 0.0))
+        + tap(vertex.uv, vec2(r, 0.0)) + tap(vertex.uv, vec2(0.0, r))) * 0.125
+        + tap(vertex.uv, vec2(0.0, 0.0)) * 0.25;
+    return vec4(color, 1.0);
+}
@@ -1,7 +1,21 @@
+use crate::world::FlyCamera;
 use glam::{Mat4, Vec3};
 use std::f32::consts::FRAC_PI_4;
 
+/// Closest the orbit camera gets to its target, by zooming or dolly zoom
+pub const MIN_DISTANCE: f32 = 15.0;
+/// Farthest the orbit camera gets from its target, by zooming or dolly zoom
+pub const MAX_DISTANCE: f32 = 400.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projection {
+    Perspective,
+    /// Parallel projection sized to match the perspective view at the target
+    Orthographic,
+}
+
 pub struct Camera {
+    pub projection: Projection,
     pub azimuth: f32,
     pub elevation: f32,
     pub distance: f32,
@@ -9,11 +23,14 @@ pub struct Camera {
     pub fov: f32,
     pub near: f32,
     pub far: f32,
+    pub fly: FlyCamera,
+    pub free_fly: bool,
 }
 
 impl Default for Camera {
     fn default() -> Self {
         Self {
+            projection: Projection::Perspective,
             azimuth: -0.5,
             elevation: 0.7,
             distance: 60.0,
@@ -21,6 +38,8 @@ impl Default for Camera {
             fov: FRAC_PI_4,
             near: 1.0,
             far: 1000.0,
+            fly: FlyCamera::default(),
+            free_fly: false,
         }
     }
 }
@@ -31,6 +50,13 @@ impl Camera {
     }
 
     pub fn get_eye_position(&self) -> Vec3 {
+        if self.free_fly {
+            return self.fly.position;
+        }
+        self.get_orbit_eye_position()
+    }
+
+    fn get_orbit_eye_position(&self) -> Vec3 {
         let x = self.distance * self.elevation.cos() * self.azimuth.sin();
         let y = self.distance * self.elevation.cos() * self.azimuth.cos();
         let z = self.distance * self.elevation.sin();
@@ -38,11 +64,31 @@ impl Camera {
     }
 
     pub fn get_view_matrix(&self) -> Mat4 {
+        if self.free_fly {
+            return self.fly.get_view_matrix();
+        }
         Mat4::look_at_rh(self.get_eye_position(), self.target, Vec3::Z)
     }
 
+    /// Projection into reversed depth, near plane at 1 and far plane at 0.
     pub fn get_projection_matrix(&self, aspect_ratio: f32) -> Mat4 {
-        Mat4::perspective_rh(self.fov, aspect_ratio, self.near, self.far)
+        match self.projection {
+            Projection::Perspective => {
+                Mat4::perspective_rh(self.fov, aspect_ratio, self.far, self.near)
+            }
+            Projection::Orthographic => {
+                let half_height = self.distance * (self.fov * 0.5).tan();
+                let half_width = half_height * aspect_ratio;
+                Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.far,
+                    self.near,
+                )
+            }
+        }
     }
 
     pub fn make_vp_matrix(&self, aspect_ratio: f32) -> Mat4 {
@@ -54,13 +100,102 @@ impl Camera {
         self.elevation = (self.elevation + delta_elevation).clamp(-1.5, 1.5);
     }
 
+    /// Switch between the orbit and the free-fly camera. Flying starts from
+    /// the current orbit viewpoint.
+    pub fn set_free_fly(&mut self, free_fly: bool) {
+        if free_fly && !self.free_fly {
+            self.fly.place(self.get_orbit_eye_position(), self.target);
+        }
+        if !free_fly {
+            self.fly.release_keys();
+        }
+        self.free_fly = free_fly;
+    }
+
+    /// Place the camera at `eye` looking at `target`, keeping the orbit
+    /// parameters in sync so manual controls continue from there.
+    pub fn look_from(&mut self, eye: Vec3, target: Vec3) {
+        let offset = eye - target;
+        let distance = offset.length();
+        if distance < f32::EPSILON {
+            return;
+        }
+        self.target = target;
+        self.distance = distance;
+        self.azimuth = offset.x.atan2(offset.y);
+        self.elevation = (offset.z / distance).asin().clamp(-1.5, 1.5);
+    }
+
+    /// Slide the target across the view plane by a screen space delta in
+    /// pixels. Returns the world space offset applied.
+    pub fn pan(&mut self, dx: f32, dy: f32) -> Vec3 {
+        let forward = (self.target - self.get_eye_position()).normalize_or(Vec3::Y);
+        let right = forward.cross(Vec3::Z).normalize_or(Vec3::X);
+        let up = right.cross(forward);
+        let offset = (up * dy - right * dx) * self.distance * 0.0015;
+        self.target += offset;
+        offset
+    }
+
+    /// Restore the default orbit angles and distance, keeping the target.
+    pub fn reset_orbit(&mut self) {
+        let default = Self::default();
+        self.azimuth = default.azimuth;
+        self.elevation = default.elevation;
+        self.distance = default.distance;
+    }
+
+    /// Change the field of view while moving the camera so the target plane
+    /// keeps the same size on screen. The field of view stops where the
+    /// camera would leave the distances zoom reaches.
+    pub fn dolly_zoom(&mut self, fov: f32) {
+        let width = self.distance * (self.fov * 0.5).tan();
+        let min_fov = 2.0 * (width / MAX_DISTANCE).atan();
+        let max_fov = 2.0 * (width / MIN_DISTANCE).atan();
+        self.fov = fov.clamp(min_fov, max_fov);
+        self.distance = (width / (self.fov * 0.5).tan()).clamp(MIN_DISTANCE, MAX_DISTANCE);
+    }
+
     pub fn zoom(&mut self, delta: f32) {
-        self.distance = (self.distance * (1.0 + delta * 0.2)).clamp(15.0, 400.0);
+        self.distance = (self.distance * (1.0 + delta * 0.2)).clamp(MIN_DISTANCE, MAX_DISTANCE);
     }
 
     pub fn make_vp_matrix_static(aspect_ratio: f32, distance: f32) -> Mat4 {
-        let projection = Mat4::perspective_rh(FRAC_PI_4, aspect_ratio, 1.0, 1000.0);
+        let projection = Mat4::perspective_rh(FRAC_PI_4, aspect_ratio, 1000.0, 1.0);
         let view = Mat4::look_at_rh(Vec3::new(1.0, -2.0, 2.0) * distance, Vec3::ZERO, Vec3::Z);
         projection * view
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Half the height of the target plane in view
+    fn target_size(camera: &Camera) -> f32 {
+        camera.distance * (camera.fov * 0.5).tan()
+    }
+
+    #[test]
+    fn dolly_zoom_keeps_target_size() {
+        let mut camera = Camera::new();
+        let size = target_size(&camera);
+        camera.dolly_zoom(camera.fov * 0.8);
+        assert!((target_size(&camera) - size).abs() < 1e-3);
+    }
+
+    #[test]
+    fn dolly_zoom_keeps_target_size_at_the_zoom_limits() {
+        let mut camera = Camera::new();
+        let size = target_size(&camera);
+        camera.dolly_zoom(0.01);
+        assert!((camera.distance - MAX_DISTANCE).abs() < 1e-2);
+        assert!(camera.fov > 0.01);
+        assert!((target_size(&camera) - size).abs() < 1e-3);
+        camera.dolly_zoom(3.0);
+        assert!((camera.distance - MIN_DISTANCE).abs() < 1e-3);
+        assert!(camera.fov < 3.0);
+        assert!((target_size(&camera) - size).abs() < 1e-3);
+        camera.zoom(-1.0);
+        assert_eq!(camera.distance, MIN_DISTANCE);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::world::{Camera, CameraRig};
+use glam::{Vec2, Vec3};
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+#[cfg(target_arch = "wasm32")]
+use web_time::{Duration, Instant};
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, Touch, TouchPhase, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+const ORBIT_SPEED: f32 = 0.005;
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(300);
+const DOUBLE_CLICK_DISTANCE: f32 = 5.0;
+
+/// Translates mouse and touch input into camera orbit, pan and zoom.
+///
+/// - left drag: orbit
+/// - right or middle drag: pan
+/// - double click: reset the orbit
+/// - scroll or pinch: zoom
+/// - two finger drag: orbit
+/// - F: toggle the free-fly camera, which takes left drag to look around
+///   and scroll to change speed
+#[derive(Default)]
+pub struct CameraInput {
+    cursor: Vec2,
+    drag: Option<MouseButton>,
+    last_click: Option<(Instant, Vec2)>,
+    touches: HashMap<u64, Vec2>,
+}
+
+impl CameraInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle a window event. Presses that egui consumed never start a drag,
+    /// so interacting with the UI does not move the camera.
+    pub fn handle(
+        &mut self,
+        event: &WindowEvent,
+        egui_consumed: bool,
+        camera: &mut Camera,
+        rig: &mut CameraRig,
+    ) {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let cursor = Vec2::new(position.x as f32, position.y as f32);
+                let delta = cursor - self.cursor;
+                self.cursor = cursor;
+                match self.drag {
+                    Some(MouseButton::Left) if camera.free_fly => {
+                        camera.fly.look(delta.x, delta.y);
+                    }
+                    Some(MouseButton::Left) => {
+                        camera.rotate(-delta.x * ORBIT_SPEED, delta.y * ORBIT_SPEED);
+                    }
+                    Some(MouseButton::Right | MouseButton::Middle) if !camera.free_fly => {
+                        rig.offset += camera.pan(delta.x, delta.y);
+                    }
+                    _ => {}
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed if !egui_consumed => {
+                    self.drag = Some(*button);
+                    if *button == MouseButton::Left {
+                        self.click(camera, rig);
+                    }
+                }
+                ElementState::Released if self.drag == Some(*button) => {
+                    self.drag = None;
+                }
+                _ => {}
+            },
+            WindowEvent::MouseWheel { delta, .. } if !egui_consumed && camera.free_fly => {
+                let steps = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 * 0.01,
+                };
+                camera.fly.speed = (camera.fly.speed * 1.1f32.powf(steps)).clamp(1.0, 500.0);
+            }
+            WindowEvent::MouseWheel { delta, .. } if !egui_consumed => match delta {
+                MouseScrollDelta::LineDelta(_, y) => {
+                    camera.zoom(-y * 0.3);
+                }
+                MouseScrollDelta::PixelDelta(pos) => {
+                    camera.zoom(-pos.y as f32 * 0.003);
+                }
+            },
+            WindowEvent::Touch(touch) => self.touch(touch, egui_consumed, camera),
+            WindowEvent::KeyboardInput { event, .. } => {
+                let PhysicalKey::Code(key) = event.physical_key else {
+                    return;
+                };
+                let pressed = event.state == ElementState::Pressed;
+                if pressed && egui_consumed {
+                    return;
+                }
+                if key == KeyCode::KeyF && pressed && !event.repeat {
+                    camera.set_free_fly(!camera.free_fly);
+                } else if camera.free_fly {
+                    camera.fly.set_key(key, pressed);
+                }
+            }
+            WindowEvent::Focused(false) => {
+                self.drag = None;
+                camera.fly.release_keys();
+            }
+            _ => {}
+        }
+    }
+
+    fn click(&mut self, camera: &mut Camera, rig: &mut CameraRig) {
+        let now = Instant::now();
+        let double_click = self.last_click.is_some_and(|(time, position)| {
+            now.duration_since(time) < DOUBLE_CLICK_TIME
+                && position.distance(self.cursor) < DOUBLE_CLICK_DISTANCE
+        });
+        if double_click {
+            camera.reset_orbit();
+            rig.offset = Vec3::ZERO;
+            self.last_click = None;
+        } else {
+            self.last_click = Some((now, self.cursor));
+        }
+    }
+
+    fn touch(&mut self, touch: &Touch, egui_consumed: bool, camera: &mut Camera) {
+        let position = Vec2::new(touch.location.x as f32, touch.location.y as f32);
+        match touch.phase {
+            TouchPhase::Started => {
+                if !egui_consumed {
+                    self.touches.insert(touch.id, position);
+                }
+            }
+            TouchPhase::Moved => {
+                if !self.touches.contains_key(&touch.id) {
+                    return;
+                }
+                let (old_center, old_spread) = self.touch_centroid();
+                self.touches.insert(touch.id, position);
+                let (center, spread) = self.touch_centroid();
+                if self.touches.len() < 2 {
+                    return;
+                }
+                let delta = center - old_center;
+                camera.rotate(-delta.x * ORBIT_SPEED, delta.y * ORBIT_SPEED);
+                if spread > 0.0 && old_spread > 0.0 {
+                    // zoom scales distance by (1 + delta * 0.2)
+                    camera.zoom((old_spread / spread - 1.0) / 0.2);
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.remove(&touch.id);
+            }
+        }
+    }
+
+    /// Center of all active touches and their mean distance from it.
+    fn touch_centroid(&self) -> (Vec2, f32) {
+        let n = self.touches.len().max(1) as f32;
+        let center = self.touches.values().copied().sum::<Vec2>() / n;
+        let spread = self
+            .touches
+            .values()
+            .map(|p| p.distance(center))
+            .sum::<f32>()
+            / n;
+        (center, spread)
+    }
+}
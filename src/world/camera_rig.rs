@@ -0,0 +1,181 @@
+use crate::world::Camera;
+use glam::Vec3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Manual orbit around the origin
+    Orbit,
+    /// Manual orbit around the dragon's head
+    OrbitDragon,
+    /// Behind and above the head, looking where the dragon is heading
+    Chase,
+    /// Alongside the dragon, tracking it sideways
+    Dolly,
+    /// Fixed position, turning to follow the dragon
+    Tripod,
+}
+
+impl CameraMode {
+    pub const ALL: [CameraMode; 5] = [
+        CameraMode::Orbit,
+        CameraMode::OrbitDragon,
+        CameraMode::Chase,
+        CameraMode::Dolly,
+        CameraMode::Tripod,
+    ];
+
+    /// Whether the user places the camera, rather than the rig.
+    pub fn is_orbit(self) -> bool {
+        matches!(self, CameraMode::Orbit | CameraMode::OrbitDragon)
+    }
+}
+
+/// Critically damped spring, converges on its goal without overshooting.
+pub struct Spring {
+    pub value: Vec3,
+    velocity: Vec3,
+}
+
+impl Spring {
+    pub fn new(value: Vec3) -> Self {
+        Self {
+            value,
+            velocity: Vec3::ZERO,
+        }
+    }
+
+    pub fn update(&mut self, goal: Vec3, stiffness: f32, dt: f32) -> Vec3 {
+        let offset = self.value - goal;
+        let decay = (-stiffness * dt).exp();
+        let temp = (self.velocity + offset * stiffness) * dt;
+        self.velocity = (self.velocity - temp * stiffness) * decay;
+        self.value = goal + (offset + temp) * decay;
+        self.value
+    }
+}
+
+/// Drives a [`Camera`] to follow the dragon's head according to [`CameraMode`].
+pub struct CameraRig {
+    pub mode: CameraMode,
+    pub stiffness: f32,
+    pub chase_distance: f32,
+    pub chase_height: f32,
+    pub dolly_distance: f32,
+    pub tripod: Vec3,
+    /// User pan applied on top of the orbit modes' target
+    pub offset: Vec3,
+    eye: Spring,
+    target: Spring,
+}
+
+impl Default for CameraRig {
+    fn default() -> Self {
+        Self {
+            mode: CameraMode::Orbit,
+            stiffness: 4.0,
+            chase_distance: 25.0,
+            chase_height: 8.0,
+            dolly_distance: 40.0,
+            tripod: Vec3::new(40.0, -80.0, 40.0),
+            offset: Vec3::ZERO,
+            eye: Spring::new(Vec3::ZERO),
+            target: Spring::new(Vec3::ZERO),
+        }
+    }
+}
+
+impl CameraRig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Switch mode, starting the springs from the camera's current pose so
+    /// the transition is smooth.
+    pub fn set_mode(&mut self, mode: CameraMode, camera: &Camera) {
+        self.mode = mode;
+        self.reset(camera);
+        if mode == CameraMode::Tripod {
+            self.tripod = camera.get_eye_position();
+        }
+    }
+
+    /// Start the springs from the camera's current pose, after something
+    /// else moved the camera, so the rig does not snap back.
+    pub fn reset(&mut self, camera: &Camera) {
+        self.eye = Spring::new(camera.get_eye_position());
+        self.target = Spring::new(camera.target);
+    }
+
+    /// Move the camera towards the shot for the current mode, given the
+    /// dragon head position and heading. `dt` is in seconds.
+    pub fn update(&mut self, camera: &mut Camera, head: Vec3, heading: Vec3, dt: f32) {
+        let side = heading.cross(Vec3::Z).normalize_or(Vec3::X);
+        let (eye, target) = match self.mode {
+            CameraMode::Orbit | CameraMode::OrbitDragon => {
+                let goal = if self.mode == CameraMode::Orbit {
+                    Vec3::ZERO
+                } else {
+                    head
+                };
+                camera.target = self.target.update(goal + self.offset, self.stiffness, dt);
+                return;
+            }
+            CameraMode::Chase => (
+                head - heading * self.chase_distance + Vec3::Z * self.chase_height,
+                head + heading * self.chase_distance,
+            ),
+            CameraMode::Dolly => (head + side * self.dolly_distance, head),
+            CameraMode::Tripod => (self.tripod, head),
+        };
+        let eye = self.eye.update(eye, self.stiffness, dt);
+        let target = self.target.update(target, self.stiffness, dt);
+        camera.look_from(eye, target);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spring_converges_on_goal() {
+        let mut spring = Spring::new(Vec3::ZERO);
+        let goal = Vec3::new(10.0, -5.0, 2.0);
+        for _ in 0..240 {
+            spring.update(goal, 4.0, 1.0 / 60.0);
+        }
+        assert!(spring.value.distance(goal) < 1e-3);
+    }
+
+    #[test]
+    fn spring_does_not_overshoot() {
+        let mut spring = Spring::new(Vec3::ZERO);
+        let goal = Vec3::X * 10.0;
+        let mut last = 0.0;
+        for _ in 0..240 {
+            let x = spring.update(goal, 8.0, 1.0 / 60.0).x;
+            assert!(x >= last && x <= goal.x + 1e-4, "{last} -> {x}");
+            last = x;
+        }
+    }
+
+    #[test]
+    fn spring_at_rest_on_goal_stays() {
+        let goal = Vec3::new(1.0, 2.0, 3.0);
+        let mut spring = Spring::new(goal);
+        assert_eq!(spring.update(goal, 4.0, 0.1), goal);
+    }
+
+    #[test]
+    fn spring_does_not_depend_on_frame_rate() {
+        let goal = Vec3::new(10.0, 0.0, -4.0);
+        let mut coarse = Spring::new(Vec3::ZERO);
+        let mut fine = Spring::new(Vec3::ZERO);
+        for _ in 0..30 {
+            coarse.update(goal, 4.0, 1.0 / 30.0);
+            fine.update(goal, 4.0, 1.0 / 60.0);
+            fine.update(goal, 4.0, 1.0 / 60.0);
+        }
+        assert!(coarse.value.distance(fine.value) < 1e-3);
+    }
+}
@@ -0,0 +1,249 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+/// Fixed update rate of the simulation, in milliseconds per step.
+pub const FIXED_STEP: f64 = 1000.0 / 120.0;
+/// Longest wall clock gap fed into the simulation at once, so a stalled
+/// frame or a backgrounded tab does not fast-forward the animation.
+const MAX_FRAME_TIME: f64 = 250.0;
+
+/// Simulation clock, decoupled from the wall clock.
+///
+/// Time is accumulated in `f64` milliseconds and advanced in fixed steps,
+/// so animation speed does not depend on frame pacing and does not lose
+/// precision after running for hours. Frames are drawn at
+/// [`Clock::interpolated_time`], in between steps. The live app drives it
+/// with [`Clock::tick`], offline exporters with [`Clock::advance`].
+pub struct Clock {
+    time: f64,
+    accumulator: f64,
+    delta_time: f64,
+    last_tick: Instant,
+    frame_time: f64,
+    pub step: f64,
+    pub scale: f64,
+    pub paused: bool,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            accumulator: 0.0,
+            delta_time: 0.0,
+            last_tick: Instant::now(),
+            frame_time: 0.0,
+            step: FIXED_STEP,
+            scale: 1.0,
+            paused: false,
+        }
+    }
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advance by the wall clock time elapsed since the last tick.
+    /// Returns the number of fixed steps taken.
+    pub fn tick(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_tick).as_secs_f64() * 1000.0;
+        self.last_tick = now;
+        self.advance_frame(elapsed)
+    }
+
+    /// Advance by a frame that took `elapsed` milliseconds of wall clock
+    /// time, clamped to `MAX_FRAME_TIME`.
+    fn advance_frame(&mut self, elapsed: f64) -> u32 {
+        self.frame_time = elapsed.min(MAX_FRAME_TIME);
+        self.advance(self.frame_time)
+    }
+
+    /// Advance by `real_ms` milliseconds of real time, scaled by `scale`.
+    /// Returns the number of fixed steps taken.
+    pub fn advance(&mut self, real_ms: f64) -> u32 {
+        if self.paused {
+            self.delta_time = 0.0;
+            return 0;
+        }
+        self.delta_time = real_ms.max(0.0) * self.scale.max(0.0);
+        self.accumulator += self.delta_time;
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            self.time += self.step;
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Simulated time at the last fixed step, in milliseconds.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Simulated time including the part of a step not taken yet, in
+    /// milliseconds, for drawing in between fixed steps.
+    pub fn interpolated_time(&self) -> f64 {
+        self.time + self.accumulator
+    }
+
+    /// How far the time drawn is into the next fixed step, from 0 to 1.
+    pub fn alpha(&self) -> f64 {
+        self.accumulator / self.step
+    }
+
+    /// Simulated time the last advance added, whole steps or not, in
+    /// milliseconds. The interpolated time moved on by as much.
+    pub fn delta_time(&self) -> f64 {
+        self.delta_time
+    }
+
+    /// Unscaled wall clock time of the last tick, in milliseconds. Keeps
+    /// running while paused, for things like camera controls.
+    pub fn frame_time(&self) -> f64 {
+        self.frame_time
+    }
+
+    /// Restart from zero, keeping scale and pause state.
+    pub fn reset(&mut self) {
+        self.time = 0.0;
+        self.accumulator = 0.0;
+        self.delta_time = 0.0;
+        self.last_tick = Instant::now();
+    }
+
+    /// Wrap `time` into `[0, period)`, so it can be handed to `f32`
+    /// shader math without losing precision.
+    pub fn wrap(time: f64, period: f64) -> f64 {
+        if period > 0.0 {
+            time.rem_euclid(period)
+        } else {
+            time
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn advance_takes_whole_fixed_steps() {
+        let mut clock = Clock::new();
+        assert_eq!(clock.advance(FIXED_STEP * 0.5), 0);
+        assert_eq!(clock.time(), 0.0);
+        assert_eq!(clock.advance(FIXED_STEP * 0.5), 1);
+        assert_eq!(clock.time(), FIXED_STEP);
+        assert_eq!(clock.advance(FIXED_STEP * 3.5), 3);
+        assert_close(clock.time(), FIXED_STEP * 4.0);
+        // the remaining half step is carried into the next advance
+        assert_eq!(clock.advance(FIXED_STEP * 0.75), 1);
+        assert_close(clock.time(), FIXED_STEP * 5.0);
+    }
+
+    #[test]
+    fn interpolated_time_keeps_the_partial_step() {
+        let mut clock = Clock::new();
+        clock.advance(FIXED_STEP * 2.25);
+        assert_close(clock.time(), FIXED_STEP * 2.0);
+        assert_close(clock.alpha(), 0.25);
+        assert_close(clock.interpolated_time(), FIXED_STEP * 2.25);
+        // frames not a whole step apart still move on
+        let before = clock.interpolated_time();
+        assert_eq!(clock.advance(FIXED_STEP * 0.5), 0);
+        assert_close(clock.interpolated_time() - before, FIXED_STEP * 0.5);
+        assert_close(clock.delta_time(), FIXED_STEP * 0.5);
+        assert_close(clock.alpha(), 0.75);
+    }
+
+    #[test]
+    fn delta_time_follows_scale_and_pause() {
+        let mut clock = Clock::new();
+        clock.scale = 2.0;
+        clock.advance(10.0);
+        assert_close(clock.delta_time(), 20.0);
+        clock.paused = true;
+        clock.advance(10.0);
+        assert_eq!(clock.delta_time(), 0.0);
+    }
+
+    #[test]
+    fn advance_ignores_negative_time() {
+        let mut clock = Clock::new();
+        assert_eq!(clock.advance(-100.0), 0);
+        assert_eq!(clock.advance(FIXED_STEP), 1);
+        assert_eq!(clock.time(), FIXED_STEP);
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        let mut clock = Clock::new();
+        let steps = clock.advance_frame(10_000.0);
+        assert_eq!(clock.frame_time(), MAX_FRAME_TIME);
+        assert_eq!(steps, (MAX_FRAME_TIME / FIXED_STEP) as u32);
+        assert!(clock.time() <= MAX_FRAME_TIME);
+        clock.advance_frame(10.0);
+        assert_eq!(clock.frame_time(), 10.0);
+    }
+
+    #[test]
+    fn paused_clock_does_not_advance() {
+        let mut clock = Clock::new();
+        clock.paused = true;
+        assert_eq!(clock.advance_frame(100.0), 0);
+        assert_eq!(clock.time(), 0.0);
+        // the wall clock keeps running for camera controls
+        assert_eq!(clock.frame_time(), 100.0);
+        clock.paused = false;
+        assert_eq!(clock.advance(FIXED_STEP), 1);
+    }
+
+    #[test]
+    fn scale_changes_simulated_speed() {
+        let mut clock = Clock::new();
+        clock.scale = 2.0;
+        assert_eq!(clock.advance(FIXED_STEP * 2.25), 4);
+        assert_close(clock.time(), FIXED_STEP * 4.0);
+        clock.scale = 0.0;
+        assert_eq!(clock.advance(1000.0), 0);
+        clock.scale = -1.0;
+        assert_eq!(clock.advance(1000.0), 0);
+        assert_close(clock.time(), FIXED_STEP * 4.0);
+    }
+
+    #[test]
+    fn reset_keeps_scale_and_pause() {
+        let mut clock = Clock::new();
+        clock.scale = 3.0;
+        clock.advance(100.0);
+        clock.paused = true;
+        clock.reset();
+        assert_eq!(clock.time(), 0.0);
+        assert_eq!(clock.scale, 3.0);
+        assert!(clock.paused);
+    }
+
+    #[test]
+    fn wrap_stays_in_period() {
+        assert_eq!(Clock::wrap(2500.0, 1000.0), 500.0);
+        assert_eq!(Clock::wrap(1000.0, 1000.0), 0.0);
+        assert_eq!(Clock::wrap(-250.0, 1000.0), 750.0);
+        assert_eq!(Clock::wrap(-1000.0, 1000.0), 0.0);
+        assert_eq!(Clock::wrap(-2250.0, 1000.0), 750.0);
+    }
+
+    #[test]
+    fn wrap_without_period_keeps_time() {
+        assert_eq!(Clock::wrap(-250.0, 0.0), -250.0);
+        assert_eq!(Clock::wrap(1234.5, -1.0), 1234.5);
+    }
+}
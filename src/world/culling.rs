@@ -0,0 +1,497 @@
+use crate::geometry::{BoundingSphere, Frustum};
+use crate::world::NodeInstance;
+use bytemuck::{Pod, Zeroable};
+use glam::Vec4;
+use std::borrow::Cow;
+use std::mem::size_of;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use wgpu::util::DrawIndexedIndirectArgs;
+use wgpu::{
+    Adapter, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferAddress,
+    BufferBindingType, BufferDescriptor, BufferSize, BufferSlice, BufferUsages, CommandEncoder,
+    ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, DownlevelFlags,
+    MapMode, PipelineCompilationOptions, PipelineLayoutDescriptor, PollType, Queue,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages,
+};
+
+/// Matches `WORKGROUP_SIZE` in culling.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+/// Bytes of one batch's indirect draw arguments
+pub const DRAW_ARGS_SIZE: BufferAddress = size_of::<DrawIndexedIndirectArgs>() as BufferAddress;
+
+const BATCH_LABEL: &str = "Culling Batch Buffer";
+const CULLED_LABEL: &str = "Culled Instance Buffer";
+const ARGS_LABEL: &str = "Indirect Draw Buffer";
+const READBACK_LABEL: &str = "Indirect Draw Read Buffer";
+/// Frames of draw arguments in flight, they are read back a few frames late
+/// so the CPU never waits on the GPU
+const READBACK_FRAMES: usize = 3;
+
+/// Must match `Cull` in culling.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct CullUniforms {
+    planes: [Vec4; 6],
+    instance_count: u32,
+    batch_count: u32,
+    _padding: [u32; 2],
+}
+
+/// Must match `Batch` in culling.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct BatchBounds {
+    sphere: Vec4,
+    first_instance: u32,
+    _padding: [u32; 3],
+}
+
+/// A batch for the GPU to cull, the instances of one mesh and shader.
+pub struct CullBatch {
+    /// Bounds of the mesh in the node's space, see `Node::entity_bounds`
+    pub sphere: BoundingSphere,
+    pub instances: Range<u32>,
+    /// Indices each instance draws
+    pub index_count: u32,
+    /// Triangles of each instance, for the counts of what was drawn
+    pub triangles: u64,
+}
+
+/// What culling on the GPU left of a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CullStats {
+    /// Of the instances drawn
+    pub triangles: u64,
+    /// Instances outside the frustum
+    pub culled: u32,
+}
+
+impl CullStats {
+    /// Of batches whose instances have `triangles` each, `instances` of
+    /// them in all, and `drawn` in each that the GPU kept.
+    fn new(triangles: &[u64], instances: u32, drawn: impl Iterator<Item = u32>) -> Self {
+        let (triangles, kept) = triangles
+            .iter()
+            .zip(drawn)
+            .fold((0, 0), |(sum, kept), (&triangles, drawn)| {
+                (sum + triangles * drawn as u64, kept + drawn)
+            });
+        Self {
+            triangles,
+            culled: instances.saturating_sub(kept),
+        }
+    }
+}
+
+/// Copy of the draw arguments of a frame on their way back to the CPU.
+struct ArgsReadback {
+    buffer: Buffer,
+    /// Triangles of an instance of each batch copied
+    triangles: Vec<u64>,
+    instance_count: u32,
+    /// Submitted and waiting to be mapped
+    in_flight: bool,
+    mapped: Arc<AtomicBool>,
+    /// The mapping failed, the arguments are lost
+    failed: Arc<AtomicBool>,
+}
+
+/// Frustum culling in a compute pass. The instances of each batch inside
+/// the frustum are copied next to each other into the batch's range of a
+/// second instance buffer, and counted into the batch's indirect draw
+/// arguments, so the CPU neither tests them nor waits to learn how many
+/// remain; it reads the arguments back a few frames late, see `stats`.
+/// Only runs where compute shaders and indirect draws are available, the
+/// renderer culls on the CPU elsewhere, like on WebGL2.
+pub struct GpuCulling {
+    pipeline: ComputePipeline,
+    layout: BindGroupLayout,
+    uniform_buffer: Buffer,
+    batch_buffer: Buffer,
+    culled_buffer: Buffer,
+    args_buffer: Buffer,
+    /// Along with the instance buffer it reads, rebuilt when any buffer
+    /// grows
+    bind_group: Option<(BindGroup, Buffer)>,
+    instance_count: u32,
+    readbacks: Vec<ArgsReadback>,
+    /// Readback of the frame being recorded, None while all are in flight
+    current: Option<usize>,
+    stats: CullStats,
+}
+
+impl GpuCulling {
+    pub fn supported(adapter: &Adapter) -> bool {
+        let flags = adapter.get_downlevel_capabilities().flags;
+        flags.contains(DownlevelFlags::COMPUTE_SHADERS | DownlevelFlags::INDIRECT_EXECUTION)
+    }
+
+    pub fn new(device: &Device) -> Self {
+        let storage_entry = |binding, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Culling Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(size_of::<CullUniforms>() as u64),
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),  // instances
+                storage_entry(2, true),  // batches
+                storage_entry(3, false), // culled instances
+                storage_entry(4, false), // draw arguments
+            ],
+        });
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Culling Shader"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("culling.wgsl"))),
+        });
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Culling Pipeline"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&layout],
+                push_constant_ranges: &[],
+            })),
+            module: &module,
+            entry_point: Some("cs_cull"),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        });
+        let buffer = |label, size, usage| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let uniform_usage = BufferUsages::UNIFORM | BufferUsages::COPY_DST;
+        let batch_usage = BufferUsages::STORAGE | BufferUsages::COPY_DST;
+        let culled_usage = BufferUsages::STORAGE | BufferUsages::VERTEX;
+        let args_usage = BufferUsages::STORAGE
+            | BufferUsages::INDIRECT
+            | BufferUsages::COPY_DST
+            | BufferUsages::COPY_SRC;
+        let readback_usage = BufferUsages::MAP_READ | BufferUsages::COPY_DST;
+        let uniform_size = size_of::<CullUniforms>() as u64;
+        Self {
+            pipeline,
+            layout,
+            uniform_buffer: buffer("Culling Uniform Buffer", uniform_size, uniform_usage),
+            batch_buffer: buffer(BATCH_LABEL, size_of::<BatchBounds>() as u64, batch_usage),
+            culled_buffer: buffer(CULLED_LABEL, size_of::<NodeInstance>() as u64, culled_usage),
+            args_buffer: buffer(ARGS_LABEL, DRAW_ARGS_SIZE, args_usage),
+            bind_group: None,
+            instance_count: 0,
+            readbacks: (0..READBACK_FRAMES)
+                .map(|_| ArgsReadback {
+                    buffer: buffer(READBACK_LABEL, DRAW_ARGS_SIZE, readback_usage),
+                    triangles: Vec::new(),
+                    instance_count: 0,
+                    in_flight: false,
+                    mapped: Arc::new(AtomicBool::new(false)),
+                    failed: Arc::new(AtomicBool::new(false)),
+                })
+                .collect(),
+            current: None,
+            stats: CullStats::default(),
+        }
+    }
+
+    /// Upload the frustum and the batches to cull, whose instances are the
+    /// first of `instances`, a buffer of `NodeInstance`s. The buffers grow
+    /// to fit them, by doubling. Takes in the draw arguments of earlier
+    /// frames that arrived. Returns the number of buffer writes, none
+    /// without batches.
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        frustum: &Frustum,
+        instances: &Buffer,
+        batches: &[CullBatch],
+    ) -> u32 {
+        let instance_count = batches.last().map_or(0, |batch| batch.instances.end);
+        let uniforms = CullUniforms {
+            planes: frustum.planes(),
+            instance_count,
+            batch_count: batches.len() as u32,
+            _padding: [0; 2],
+        };
+        self.instance_count = instance_count;
+        self.collect(device);
+        self.current = None;
+        if batches.is_empty() {
+            self.stats = CullStats::default();
+            return 0;
+        }
+        let batch_count = batches.len() as u64;
+        let batch_bytes = batch_count * size_of::<BatchBounds>() as u64;
+        let culled_bytes = instance_count as u64 * size_of::<NodeInstance>() as u64;
+        let grown = Self::grow(device, &mut self.batch_buffer, BATCH_LABEL, batch_bytes)
+            | Self::grow(device, &mut self.culled_buffer, CULLED_LABEL, culled_bytes)
+            | Self::grow(device, &mut self.args_buffer, ARGS_LABEL, batch_count * DRAW_ARGS_SIZE);
+        let stale = match &self.bind_group {
+            Some((_, bound)) => grown || bound != instances,
+            None => true,
+        };
+        if stale {
+            self.bind_group = Some((self.create_bind_group(device, instances), instances.clone()));
+        }
+        let bounds: Vec<BatchBounds> = batches
+            .iter()
+            .map(|batch| BatchBounds {
+                sphere: batch.sphere.center.extend(batch.sphere.radius),
+                first_instance: batch.instances.start,
+                _padding: [0; 3],
+            })
+            .collect();
+        // the compute pass counts the instances up from 0
+        let args: Vec<u8> = batches
+            .iter()
+            .flat_map(|batch| {
+                DrawIndexedIndirectArgs {
+                    index_count: batch.index_count,
+                    instance_count: 0,
+                    first_index: 0,
+                    base_vertex: 0,
+                    first_instance: 0,
+                }
+                .as_bytes()
+                .to_vec()
+            })
+            .collect();
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        queue.write_buffer(&self.batch_buffer, 0, bytemuck::cast_slice(&bounds));
+        queue.write_buffer(&self.args_buffer, 0, &args);
+        self.current = self.readbacks.iter().position(|readback| !readback.in_flight);
+        if let Some(i) = self.current {
+            let readback = &mut self.readbacks[i];
+            let size = batch_count * DRAW_ARGS_SIZE;
+            Self::grow(device, &mut readback.buffer, READBACK_LABEL, size);
+            readback.triangles.clear();
+            readback.triangles.extend(batches.iter().map(|batch| batch.triangles));
+            readback.instance_count = instance_count;
+        }
+        3
+    }
+
+    /// What culling left of the latest frame whose draw arguments arrived.
+    pub fn stats(&self) -> CullStats {
+        self.stats
+    }
+
+    fn collect(&mut self, device: &Device) {
+        // lets the mapping of earlier frames finish, without waiting
+        let _ = device.poll(PollType::Poll);
+        for readback in &mut self.readbacks {
+            // frees the readback for a later frame
+            if readback.failed.swap(false, Ordering::Acquire) {
+                readback.in_flight = false;
+                continue;
+            }
+            if !readback.in_flight || !readback.mapped.swap(false, Ordering::Acquire) {
+                continue;
+            }
+            {
+                let data = readback.buffer.slice(..).get_mapped_range();
+                let words: &[u32] = bytemuck::cast_slice(&data);
+                // the second word of each is the instance count
+                let drawn = words
+                    .chunks_exact(DRAW_ARGS_SIZE as usize / size_of::<u32>())
+                    .map(|args| args[1]);
+                self.stats = CullStats::new(&readback.triangles, readback.instance_count, drawn);
+            }
+            readback.buffer.unmap();
+            readback.in_flight = false;
+        }
+    }
+
+    /// Map the draw arguments of the frame just submitted.
+    pub fn submitted(&mut self) {
+        let Some(i) = self.current.take() else {
+            return;
+        };
+        let readback = &mut self.readbacks[i];
+        readback.in_flight = true;
+        let mapped = readback.mapped.clone();
+        let failed = readback.failed.clone();
+        readback.buffer.slice(..).map_async(MapMode::Read, move |result| match result {
+            Ok(()) => mapped.store(true, Ordering::Release),
+            Err(err) => {
+                log::warn!("could not read back the indirect draw arguments: {err}");
+                failed.store(true, Ordering::Release);
+            }
+        });
+    }
+
+    /// Replace `buffer` by one with the same usage and at least `size`
+    /// bytes, doubling to fit later frames. Returns whether it did.
+    fn grow(device: &Device, buffer: &mut Buffer, label: &str, size: u64) -> bool {
+        if buffer.size() >= size {
+            return false;
+        }
+        *buffer = device.create_buffer(&BufferDescriptor {
+            label: Some(label),
+            size: size.next_power_of_two(),
+            usage: buffer.usage(),
+            mapped_at_creation: false,
+        });
+        true
+    }
+
+    fn create_bind_group(&self, device: &Device, instances: &Buffer) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Culling Bind Group"),
+            layout: &self.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: instances.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: self.batch_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: self.culled_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: self.args_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Bytes of all buffers culling allocated.
+    pub fn memory_usage(&self) -> u64 {
+        self.uniform_buffer.size()
+            + self.batch_buffer.size()
+            + self.culled_buffer.size()
+            + self.args_buffer.size()
+            + self.readbacks.iter().map(|readback| readback.buffer.size()).sum::<u64>()
+    }
+
+    /// Record the compute pass culling the batches of the last `prepare`,
+    /// and the copy of their draw arguments to read back.
+    pub fn cull(&mut self, encoder: &mut CommandEncoder) {
+        let Some((bind_group, _)) = &self.bind_group else {
+            self.current = None;
+            return;
+        };
+        if self.instance_count == 0 {
+            self.current = None;
+            return;
+        }
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Culling Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.dispatch_workgroups(self.instance_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        drop(pass);
+        if let Some(readback) = self.current.map(|i| &self.readbacks[i]) {
+            let size = readback.triangles.len() as BufferAddress * DRAW_ARGS_SIZE;
+            encoder.copy_buffer_to_buffer(&self.args_buffer, 0, &readback.buffer, 0, size);
+        }
+    }
+
+    /// Where the instances of a batch covering `instances` end up, for
+    /// vertex buffer slot 1. Drawn from the start of the slice, as many as
+    /// the batch's arguments count.
+    pub fn instances(&self, instances: Range<u32>) -> BufferSlice<'_> {
+        let size = size_of::<NodeInstance>() as BufferAddress;
+        self.culled_buffer
+            .slice(instances.start as BufferAddress * size..instances.end as BufferAddress * size)
+    }
+
+    /// The indirect draw arguments of the `index`th batch, at
+    /// `index * DRAW_ARGS_SIZE`.
+    pub fn draw_args(&self) -> &Buffer {
+        &self.args_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mirror of `find_batch` in culling.wgsl, the batch starting at the
+    /// last of `first_instances` at or before `instance`.
+    fn find_batch(first_instances: &[u32], instance: u32) -> usize {
+        let (mut low, mut high) = (0, first_instances.len());
+        while high - low > 1 {
+            let middle = (low + high) / 2;
+            if first_instances[middle] <= instance {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+
+    #[test]
+    fn find_batch_picks_the_batch_holding_each_instance() {
+        let first_instances = [0, 3, 4, 10, 11, 17];
+        for instance in 0..20 {
+            let expected = first_instances.iter().rposition(|&first| first <= instance).unwrap();
+            assert_eq!(find_batch(&first_instances, instance), expected, "{instance}");
+        }
+    }
+
+    #[test]
+    fn find_batch_of_a_single_batch() {
+        assert_eq!(find_batch(&[0], 0), 0);
+        assert_eq!(find_batch(&[0], 100), 0);
+    }
+
+    #[test]
+    fn stats_count_what_was_kept() {
+        let stats = CullStats::new(&[100, 12, 2], 20, [3, 0, 5].into_iter());
+        assert_eq!(
+            stats,
+            CullStats {
+                triangles: 300 + 10,
+                culled: 12,
+            }
+        );
+    }
+
+    #[test]
+    fn stats_without_batches() {
+        assert_eq!(CullStats::new(&[], 0, std::iter::empty()), CullStats::default());
+    }
+
+    #[test]
+    fn uniform_layouts_match_the_shader() {
+        assert_eq!(size_of::<CullUniforms>(), 6 * 16 + 16);
+        assert_eq!(size_of::<BatchBounds>(), 32);
+        assert_eq!(DRAW_ARGS_SIZE, 20);
+    }
+}
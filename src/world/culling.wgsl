@@ -0,0 +1,82 @@
+// `cs_cull` tests every instance of the opaque batches against the view
+// frustum and copies the ones inside into their batch's range of
+// `culled`, counting them in the batch's indirect draw arguments.
+const WORKGROUP_SIZE = 64u;
+
+// see NodeInstance in world/frame.rs, only the world transform is read
+struct Instance {
+    world: mat4x4f,
+    rest: array<vec4f, 6>,
+};
+
+struct Batch {
+    // xyz: center, w: radius of the bounding sphere, in the node's space
+    sphere: vec4f,
+    first_instance: u32,
+    _padding: array<u32, 3>,
+};
+
+struct Cull {
+    // xyz: inward normal, w: distance
+    planes: array<vec4f, 6>,
+    instance_count: u32,
+    batch_count: u32,
+    _padding: vec2u,
+};
+
+// see wgpu's DrawIndexedIndirectArgs
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> cull: Cull;
+@group(0) @binding(1)
+var<storage, read> instances: array<Instance>;
+@group(0) @binding(2)
+var<storage, read> batches: array<Batch>;
+@group(0) @binding(3)
+var<storage, read_write> culled: array<Instance>;
+@group(0) @binding(4)
+var<storage, read_write> draws: array<DrawArgs>;
+
+// the batch holding `instance`, the last one starting at or before it
+fn find_batch(instance: u32) -> u32 {
+    var low = 0u;
+    var high = cull.batch_count;
+    while high - low > 1u {
+        let middle = (low + high) / 2u;
+        if batches[middle].first_instance <= instance {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    return low;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_cull(@builtin(global_invocation_id) id: vec3u) {
+    let index = id.x;
+    if index >= cull.instance_count {
+        return;
+    }
+    let b = find_batch(index);
+    let batch = batches[b];
+    let world = instances[index].world;
+    let center = (world * vec4(batch.sphere.xyz, 1.0)).xyz;
+    let scale = max(length(world[0].xyz), max(length(world[1].xyz), length(world[2].xyz)));
+    let radius = batch.sphere.w * scale;
+    for (var i = 0u; i < 6u; i++) {
+        let plane = cull.planes[i];
+        if dot(plane.xyz, center) + plane.w < -radius {
+            return;
+        }
+    }
+    let slot = atomicAdd(&draws[b].instance_count, 1u);
+    culled[batch.first_instance + slot] = instances[index];
+}
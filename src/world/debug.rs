@@ -0,0 +1,329 @@
+use crate::world::HDR_FORMAT;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3, Vec4};
+use std::borrow::Cow;
+use std::f32::consts::TAU;
+use std::mem::size_of;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferAddress, BufferBindingType, BufferDescriptor,
+    BufferSize, BufferUsages, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
+    DepthStencilState, Device, FragmentState, MultisampleState, PipelineCompilationOptions,
+    PipelineLayout, PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology, Queue,
+    RenderPass, RenderPipeline, RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, StencilState, TextureFormat, VertexAttribute,
+    VertexBufferLayout, VertexState, VertexStepMode, vertex_attr_array,
+};
+
+/// Segments of the circles lights are drawn with
+const CIRCLE_SEGMENTS: usize = 48;
+const GRID_COLOR: Vec4 = Vec4::new(0.4, 0.4, 0.4, 1.0);
+
+/// What the debug overlay shows, all of it can be switched at runtime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugSettings {
+    /// Draw entities as the edges of their triangles
+    pub wireframe: bool,
+    /// A line along each vertex normal, colored by its direction
+    pub normals: bool,
+    /// In units
+    pub normal_length: f32,
+    /// Line through the path of shaders that bend meshes along one
+    pub path: bool,
+    /// Axes of every `frame_step`th frame of such a path, red along it
+    pub frames: bool,
+    pub frame_step: usize,
+    /// Length of the frame axes, in units
+    pub frame_size: f32,
+    /// Circles around each light at the radius its light fades out at
+    pub light_radius: bool,
+    /// World x, y and z in red, green and blue, from the origin
+    pub axes: bool,
+    pub axis_length: f32,
+    /// Lines on the z = 0 plane, `grid_size` either way from the origin
+    pub grid: bool,
+    pub grid_size: f32,
+    pub grid_spacing: f32,
+    /// Draw the lines over the scene instead of hiding them behind it
+    pub on_top: bool,
+}
+
+impl Default for DebugSettings {
+    fn default() -> Self {
+        Self {
+            wireframe: false,
+            normals: false,
+            normal_length: 1.0,
+            path: false,
+            frames: false,
+            frame_step: 16,
+            frame_size: 4.0,
+            light_radius: false,
+            axes: false,
+            axis_length: 50.0,
+            grid: false,
+            grid_size: 200.0,
+            grid_spacing: 10.0,
+            on_top: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl LineVertex {
+    const ATTRIBUTES: [VertexAttribute; 2] =
+        vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+    fn desc() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: size_of::<Self>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Colored lines in world space for the debug overlay. They are gathered
+/// anew every frame, uploaded by `prepare` and drawn at the end of the main
+/// pass.
+pub struct DebugDraw {
+    camera_buffer: Buffer,
+    camera_bind_group: BindGroup,
+    layout: PipelineLayout,
+    module: ShaderModule,
+    /// Hidden behind the scene
+    pipeline: RenderPipeline,
+    /// Drawn over the scene
+    on_top_pipeline: RenderPipeline,
+    vertices: Vec<LineVertex>,
+    /// Grows to fit the most lines drawn so far
+    vertex_buffer: Option<Buffer>,
+    vertex_count: u32,
+}
+
+impl DebugDraw {
+    pub fn new(device: &Device, sample_count: u32) -> Self {
+        let camera_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Debug Camera Bind Group Layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(size_of::<Mat4>() as u64),
+                },
+                count: None,
+            }],
+        });
+        let camera_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Debug Camera Buffer"),
+            size: size_of::<Mat4>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Debug Camera Bind Group"),
+            layout: &camera_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+        });
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Debug Shader"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("debug.wgsl"))),
+        });
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&camera_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &layout, &module, sample_count, false);
+        let on_top_pipeline = Self::create_pipeline(device, &layout, &module, sample_count, true);
+        Self {
+            camera_buffer,
+            camera_bind_group,
+            layout,
+            module,
+            pipeline,
+            on_top_pipeline,
+            vertices: Vec::new(),
+            vertex_buffer: None,
+            vertex_count: 0,
+        }
+    }
+
+    /// Rebuild the pipelines for a new sample count of the main pass.
+    pub fn update_pipelines(&mut self, device: &Device, sample_count: u32) {
+        let (layout, module) = (&self.layout, &self.module);
+        self.pipeline = Self::create_pipeline(device, layout, module, sample_count, false);
+        self.on_top_pipeline = Self::create_pipeline(device, layout, module, sample_count, true);
+    }
+
+    pub fn line(&mut self, from: Vec3, to: Vec3, color: Vec4) {
+        let color = color.to_array();
+        self.vertices.extend([
+            LineVertex {
+                position: from.to_array(),
+                color,
+            },
+            LineVertex {
+                position: to.to_array(),
+                color,
+            },
+        ]);
+    }
+
+    /// Closed line through `points`.
+    pub fn polyline(&mut self, points: &[Vec3], color: Vec4) {
+        for (i, &point) in points.iter().enumerate() {
+            self.line(point, points[(i + 1) % points.len()], color);
+        }
+    }
+
+    /// Circle around `center` in the plane of `x` and `y`, whose lengths are
+    /// its radii along them.
+    pub fn circle(&mut self, center: Vec3, x: Vec3, y: Vec3, color: Vec4) {
+        let points: Vec<Vec3> = (0..CIRCLE_SEGMENTS)
+            .map(|i| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+                center + x * angle.cos() + y * angle.sin()
+            })
+            .collect();
+        self.polyline(&points, color);
+    }
+
+    /// Three circles around the axes.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Vec4) {
+        let [x, y, z] = [Vec3::X, Vec3::Y, Vec3::Z].map(|axis| axis * radius);
+        self.circle(center, x, y, color);
+        self.circle(center, y, z, color);
+        self.circle(center, z, x, color);
+    }
+
+    /// The x, y and z axes of `transform` in red, green and blue.
+    pub fn axes(&mut self, transform: Mat4, length: f32) {
+        let origin = transform.w_axis.truncate();
+        let axes = [transform.x_axis, transform.y_axis, transform.z_axis];
+        let colors = [
+            Vec4::new(1.0, 0.1, 0.1, 1.0),
+            Vec4::new(0.1, 1.0, 0.1, 1.0),
+            Vec4::new(0.1, 0.2, 1.0, 1.0),
+        ];
+        for (axis, color) in axes.into_iter().zip(colors) {
+            let direction = axis.truncate().normalize_or_zero();
+            self.line(origin, origin + direction * length, color);
+        }
+    }
+
+    /// Lines on the z = 0 plane, `spacing` apart, out to `size` either way.
+    pub fn grid(&mut self, size: f32, spacing: f32) {
+        let lines = (size / spacing.max(f32::EPSILON)).floor() as i32;
+        for i in -lines..=lines {
+            let offset = i as f32 * spacing;
+            self.line(Vec3::new(offset, -size, 0.0), Vec3::new(offset, size, 0.0), GRID_COLOR);
+            self.line(Vec3::new(-size, offset, 0.0), Vec3::new(size, offset, 0.0), GRID_COLOR);
+        }
+    }
+
+    /// Upload the lines gathered since the last call and start over.
+    pub fn prepare(&mut self, device: &Device, queue: &Queue, view_proj: Mat4) {
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::bytes_of(&view_proj));
+        self.vertex_count = self.vertices.len() as u32;
+        if self.vertices.is_empty() {
+            return;
+        }
+        let size = (self.vertices.len() * size_of::<LineVertex>()) as u64;
+        if self.vertex_buffer.as_ref().is_none_or(|buffer| buffer.size() < size) {
+            self.vertex_buffer = Some(device.create_buffer(&BufferDescriptor {
+                label: Some("Debug Vertex Buffer"),
+                size: size.next_power_of_two(),
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        if let Some(buffer) = &self.vertex_buffer {
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&self.vertices));
+        }
+        self.vertices.clear();
+    }
+
+    /// Draw the uploaded lines, last in the main pass. Returns the number of
+    /// draw calls.
+    pub fn draw(&self, pass: &mut RenderPass<'_>, on_top: bool) -> u32 {
+        let Some(buffer) = &self.vertex_buffer else {
+            return 0;
+        };
+        if self.vertex_count == 0 {
+            return 0;
+        }
+        pass.set_pipeline(if on_top {
+            &self.on_top_pipeline
+        } else {
+            &self.pipeline
+        });
+        pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        pass.set_vertex_buffer(0, buffer.slice(..));
+        pass.draw(0..self.vertex_count, 0..1);
+        1
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        layout: &PipelineLayout,
+        module: &ShaderModule,
+        sample_count: u32,
+        on_top: bool,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Debug Line Pipeline"),
+            layout: Some(layout),
+            vertex: VertexState {
+                module,
+                entry_point: Some("vs_line"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[LineVertex::desc()],
+            },
+            fragment: Some(FragmentState {
+                module,
+                entry_point: Some("fs_line"),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // reversed depth like the rest of the main pass, lines lying on
+            // a surface pass where it wrote its depth
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: if on_top {
+                    CompareFunction::Always
+                } else {
+                    CompareFunction::GreaterEqual
+                },
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+}
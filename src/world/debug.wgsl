@@ -0,0 +1,27 @@
+// Colored lines of the debug overlay, already in world space, see
+// world/debug.rs.
+struct VertexInput {
+    @location(0) position: vec3f,
+    @location(1) color: vec4f,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) color: vec4f,
+};
+
+@group(0) @binding(0)
+var<uniform> view_proj: mat4x4f;
+
+@vertex
+fn vs_line(input: VertexInput) -> VertexOutput {
+    var result: VertexOutput;
+    result.position = view_proj * vec4(input.position, 1.0);
+    result.color = input.color;
+    return result;
+}
+
+@fragment
+fn fs_line(vertex: VertexOutput) -> @location(0) vec4f {
+    return vertex.color;
+}
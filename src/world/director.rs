@@ -0,0 +1,400 @@
+use crate::world::Camera;
+use glam::Vec3;
+use rand::Rng;
+use splines::{Interpolation, Key, Spline};
+
+/// Dragon size on screen, as a fraction of the screen height, that the
+/// auto-director considers the best framing.
+const IDEAL_SCREEN_SIZE: f32 = 0.35;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookAt {
+    DragonHead,
+    Origin,
+    Light(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    Cut,
+    /// Blend from the previous shot over the given number of seconds
+    Ease(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    /// Rail points are world positions
+    World,
+    /// Rail points are offsets from the dragon's head when the shot starts
+    Dragon,
+}
+
+pub struct Shot {
+    pub name: &'static str,
+    pub rail: Spline<f32, Vec3>,
+    pub anchor: Anchor,
+    pub look_at: LookAt,
+    /// Length of the shot in seconds
+    pub duration: f32,
+    pub transition: Transition,
+}
+
+impl Shot {
+    /// Build a shot whose camera travels through `points` over `duration`
+    /// seconds along a Catmull-Rom rail.
+    pub fn new(
+        name: &'static str,
+        points: &[Vec3],
+        anchor: Anchor,
+        look_at: LookAt,
+        duration: f32,
+        transition: Transition,
+    ) -> Self {
+        let n = points.len().max(2) - 1;
+        let step = 1.0 / n as f32;
+        // pad both ends so Catmull-Rom has neighbours for the first and last segment
+        let first = points.first().copied().unwrap_or_default();
+        let last = points.last().copied().unwrap_or_default();
+        let keys = std::iter::once(Key::new(-step, first, Interpolation::CatmullRom))
+            .chain(
+                points
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| Key::new(i as f32 * step, *p, Interpolation::CatmullRom)),
+            )
+            .chain(std::iter::once(Key::new(1.0 + step, last, Interpolation::CatmullRom)));
+        Self {
+            name,
+            rail: Spline::from_iter(keys),
+            anchor,
+            look_at,
+            duration,
+            transition,
+        }
+    }
+
+    fn sample_rail(&self, t: f32) -> Vec3 {
+        self.rail.clamped_sample(t.clamp(0.0, 1.0)).unwrap_or_default()
+    }
+}
+
+/// Positions the director can frame, gathered from the scene each update.
+pub struct ShotTargets {
+    pub head: Vec3,
+    pub dragon_radius: f32,
+    pub lights: Vec<Vec3>,
+}
+
+impl ShotTargets {
+    fn resolve(&self, look_at: LookAt) -> Vec3 {
+        match look_at {
+            LookAt::DragonHead => self.head,
+            LookAt::Origin => Vec3::ZERO,
+            LookAt::Light(i) => self.lights.get(i).copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// Plays camera rail shots back to back. In auto mode the next shot is
+/// picked by how well it would frame the dragon, otherwise shots run in order.
+pub struct Director {
+    pub shots: Vec<Shot>,
+    pub enabled: bool,
+    pub auto: bool,
+    current: usize,
+    elapsed: f32,
+    anchor: Vec3,
+    blend_from: Option<(Vec3, Vec3)>,
+    last_pose: (Vec3, Vec3),
+    /// Start over from the first shot on the next update
+    restart: bool,
+}
+
+impl Default for Director {
+    fn default() -> Self {
+        Self {
+            shots: Self::default_shots(),
+            enabled: false,
+            auto: true,
+            current: 0,
+            elapsed: 0.0,
+            anchor: Vec3::ZERO,
+            blend_from: None,
+            last_pose: (Vec3::ONE, Vec3::ZERO),
+            restart: false,
+        }
+    }
+}
+
+impl Director {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn default_shots() -> Vec<Shot> {
+        vec![
+            Shot::new(
+                "Crane",
+                &[
+                    Vec3::new(-40.0, -60.0, 120.0),
+                    Vec3::new(0.0, -90.0, 60.0),
+                    Vec3::new(50.0, -70.0, 20.0),
+                ],
+                Anchor::World,
+                LookAt::Origin,
+                9.0,
+                Transition::Ease(2.0),
+            ),
+            Shot::new(
+                "Fly-by",
+                &[
+                    Vec3::new(30.0, 25.0, 5.0),
+                    Vec3::new(0.0, 30.0, 10.0),
+                    Vec3::new(-40.0, 20.0, 5.0),
+                ],
+                Anchor::Dragon,
+                LookAt::DragonHead,
+                5.0,
+                Transition::Cut,
+            ),
+            Shot::new(
+                "Wide orbit",
+                &[
+                    Vec3::new(120.0, 0.0, 40.0),
+                    Vec3::new(0.0, 120.0, 50.0),
+                    Vec3::new(-120.0, 0.0, 40.0),
+                    Vec3::new(0.0, -120.0, 30.0),
+                ],
+                Anchor::World,
+                LookAt::DragonHead,
+                12.0,
+                Transition::Ease(1.5),
+            ),
+            Shot::new(
+                "Low angle",
+                &[Vec3::new(-20.0, -50.0, -60.0), Vec3::new(20.0, -50.0, -55.0)],
+                Anchor::World,
+                LookAt::DragonHead,
+                6.0,
+                Transition::Cut,
+            ),
+            Shot::new(
+                "Light watch",
+                &[Vec3::new(-15.0, -35.0, 15.0), Vec3::new(15.0, -35.0, 20.0)],
+                Anchor::Dragon,
+                LookAt::Light(0),
+                5.0,
+                Transition::Ease(1.0),
+            ),
+        ]
+    }
+
+    /// Turn the director on or off. When turned on, the first shot starts
+    /// on the next update, eased in from the camera's current view.
+    pub fn set_enabled(&mut self, enabled: bool, camera: &Camera) {
+        if enabled && !self.enabled {
+            self.last_pose = (camera.get_eye_position(), camera.target);
+            self.restart = true;
+        }
+        self.enabled = enabled;
+    }
+
+    pub fn current_shot(&self) -> Option<&Shot> {
+        self.shots.get(self.current)
+    }
+
+    /// End the current shot on the next update.
+    pub fn skip(&mut self) {
+        if let Some(shot) = self.current_shot() {
+            self.elapsed = shot.duration;
+        }
+    }
+
+    /// Advance by `dt` seconds and move the camera along the current rail.
+    pub fn update(
+        &mut self,
+        camera: &mut Camera,
+        targets: &ShotTargets,
+        aspect_ratio: f32,
+        dt: f32,
+    ) {
+        if self.shots.is_empty() {
+            return;
+        }
+        if self.restart {
+            self.restart = false;
+            self.start(0, targets);
+        } else {
+            self.elapsed += dt;
+        }
+        if self.elapsed >= self.shots[self.current].duration {
+            let next = if self.auto {
+                self.pick_next(camera, targets, aspect_ratio)
+            } else {
+                (self.current + 1) % self.shots.len()
+            };
+            self.start(next, targets);
+        }
+        let shot = &self.shots[self.current];
+        let offset = match shot.anchor {
+            Anchor::World => Vec3::ZERO,
+            Anchor::Dragon => self.anchor,
+        };
+        let t = self.elapsed / shot.duration;
+        let mut eye = offset + shot.sample_rail(t);
+        let mut target = targets.resolve(shot.look_at);
+        if let (Some((from_eye, from_target)), Transition::Ease(blend)) =
+            (self.blend_from, shot.transition)
+        {
+            let k = (self.elapsed / blend).clamp(0.0, 1.0);
+            let k = k * k * (3.0 - 2.0 * k);
+            eye = from_eye.lerp(eye, k);
+            target = from_target.lerp(target, k);
+            if k >= 1.0 {
+                self.blend_from = None;
+            }
+        }
+        self.last_pose = (eye, target);
+        camera.look_from(eye, target);
+    }
+
+    fn start(&mut self, index: usize, targets: &ShotTargets) {
+        self.current = index;
+        self.elapsed = 0.0;
+        self.anchor = targets.head;
+        self.blend_from = Some(self.last_pose);
+    }
+
+    /// Pick the shot after the current one, the best scored of the others
+    /// with a little randomness for variety.
+    fn pick_next(&self, camera: &Camera, targets: &ShotTargets, aspect_ratio: f32) -> usize {
+        let mut rng = rand::rng();
+        self.shots
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != self.current || self.shots.len() == 1)
+            .map(|(i, shot)| {
+                let score = Self::score(shot, camera, targets, aspect_ratio);
+                (i, score + rng.random_range(0.0..0.3))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    /// How large and how centered the dragon would appear at the first
+    /// frame of `shot`, from 0 when out of view to 1 at the ideal size.
+    fn score(shot: &Shot, camera: &Camera, targets: &ShotTargets, aspect_ratio: f32) -> f32 {
+        let half_fov = camera.fov * 0.5;
+        let offset = match shot.anchor {
+            Anchor::World => Vec3::ZERO,
+            Anchor::Dragon => targets.head,
+        };
+        let eye = offset + shot.sample_rail(0.0);
+        let forward = (targets.resolve(shot.look_at) - eye).normalize_or(Vec3::X);
+        let to_dragon = targets.head - eye;
+        let distance = to_dragon.length().max(1.0);
+        let off_center = forward.angle_between(to_dragon / distance);
+        let half_view = half_fov.max((half_fov.tan() * aspect_ratio).atan());
+        let visibility = (1.0 - off_center / half_view).max(0.0);
+        let screen_size = targets.dragon_radius / (distance * half_fov.tan());
+        let framing = 1.0 - (screen_size / IDEAL_SCREEN_SIZE).ln().abs().min(1.0);
+        visibility * framing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRAGON_RADIUS: f32 = 10.0;
+
+    fn targets() -> ShotTargets {
+        ShotTargets {
+            head: Vec3::new(0.0, 0.0, 20.0),
+            dragon_radius: DRAGON_RADIUS,
+            lights: vec![Vec3::new(100.0, 0.0, 0.0)],
+        }
+    }
+
+    /// A still shot from `eye`, relative to the dragon's head.
+    fn shot(name: &'static str, eye: Vec3, look_at: LookAt) -> Shot {
+        Shot::new(name, &[eye, eye], Anchor::Dragon, look_at, 5.0, Transition::Cut)
+    }
+
+    /// Distance at which the dragon fills `IDEAL_SCREEN_SIZE` of the view.
+    fn ideal_distance(camera: &Camera) -> f32 {
+        DRAGON_RADIUS / (IDEAL_SCREEN_SIZE * (camera.fov * 0.5).tan())
+    }
+
+    #[test]
+    fn ideal_framing_scores_highest() {
+        let camera = Camera::new();
+        let eye = Vec3::new(0.0, -ideal_distance(&camera), 0.0);
+        let ideal = shot("Ideal", eye, LookAt::DragonHead);
+        let score = Director::score(&ideal, &camera, &targets(), 1.5);
+        assert!((score - 1.0).abs() < 1e-3, "{score}");
+        let far = shot("Far", eye * 4.0, LookAt::DragonHead);
+        assert!(Director::score(&far, &camera, &targets(), 1.5) < score);
+        let near = shot("Near", eye * 0.25, LookAt::DragonHead);
+        assert!(Director::score(&near, &camera, &targets(), 1.5) < score);
+    }
+
+    #[test]
+    fn dragon_out_of_view_scores_zero() {
+        let camera = Camera::new();
+        let eye = Vec3::new(0.0, -ideal_distance(&camera), 0.0);
+        // looking at the light with the dragon behind
+        let away = shot("Away", Vec3::new(ideal_distance(&camera), 0.0, 0.0), LookAt::Light(0));
+        assert_eq!(Director::score(&away, &camera, &targets(), 1.5), 0.0);
+        let sideways = shot("Sideways", eye, LookAt::Origin);
+        let sideways_score = Director::score(&sideways, &camera, &targets(), 1.5);
+        let centered = shot("Centered", eye, LookAt::DragonHead);
+        assert!(sideways_score < Director::score(&centered, &camera, &targets(), 1.5));
+    }
+
+    #[test]
+    fn pick_next_prefers_framing_the_dragon() {
+        let camera = Camera::new();
+        let eye = Vec3::new(0.0, -ideal_distance(&camera), 0.0);
+        let away = Vec3::new(ideal_distance(&camera), 0.0, 0.0);
+        let mut director = Director::new();
+        director.shots = vec![
+            shot("Away", away, LookAt::Light(0)),
+            shot("Ideal", eye, LookAt::DragonHead),
+            shot("Away again", away, LookAt::Light(0)),
+        ];
+        // the random bonus is smaller than the gap between the scores
+        for _ in 0..20 {
+            assert_eq!(director.pick_next(&camera, &targets(), 1.5), 1);
+        }
+    }
+
+    #[test]
+    fn pick_next_never_repeats_the_current_shot() {
+        let camera = Camera::new();
+        let eye = Vec3::new(0.0, -ideal_distance(&camera), 0.0);
+        let mut director = Director::new();
+        director.shots = vec![
+            shot("Ideal", eye, LookAt::DragonHead),
+            shot("Far", eye * 4.0, LookAt::DragonHead),
+        ];
+        for _ in 0..20 {
+            assert_eq!(director.pick_next(&camera, &targets(), 1.5), 1);
+        }
+        director.shots.truncate(1);
+        assert_eq!(director.pick_next(&camera, &targets(), 1.5), 0);
+    }
+
+    #[test]
+    fn enabling_starts_at_the_first_shot() {
+        let mut camera = Camera::new();
+        let mut director = Director::new();
+        director.current = 2;
+        director.elapsed = 1.0;
+        director.set_enabled(true, &camera);
+        director.update(&mut camera, &targets(), 1.5, 0.5);
+        assert_eq!(director.current_shot().map(|shot| shot.name), Some("Crane"));
+        assert_eq!(director.elapsed, 0.0);
+    }
+}
@@ -0,0 +1,887 @@
+use crate::world::{HDR_FORMAT, Sun};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3, Vec4};
+use half::f16;
+use image::imageops::{self, FilterType};
+use image::{ImageError, Rgba32FImage};
+use std::borrow::Cow;
+use std::mem::size_of;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use wgpu::util::{BufferInitDescriptor, DeviceExt, align_to};
+use wgpu::{
+    AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer,
+    BufferAddress, BufferBinding, BufferBindingType, BufferDescriptor, BufferSize, BufferUsages,
+    Color, CommandEncoder, CommandEncoderDescriptor, CompareFunction, DepthBiasState,
+    DepthStencilState, Device, DynamicOffset, Extent3d, FilterMode, FragmentState, LoadOp,
+    MultisampleState, Operations, Origin3d, PipelineCompilationOptions, PipelineLayout,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType,
+    SamplerDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages,
+    StencilState, StoreOp, TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+    TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+/// Texels along each side of a face of the environment cube map. Loaded
+/// maps are resampled to this size, so the bind groups never change.
+const ENVIRONMENT_SIZE: u32 = 256;
+/// Down to a single texel per face
+const ENVIRONMENT_MIPS: u32 = ENVIRONMENT_SIZE.ilog2() + 1;
+/// Irradiance varies slowly with the normal, a few texels per face will do
+const IRRADIANCE_SIZE: u32 = 32;
+const SPECULAR_SIZE: u32 = 128;
+/// Mips of the prefiltered specular map, for roughness from 0 to 1
+const SPECULAR_MIPS: u32 = 6;
+const BRDF_LUT_SIZE: u32 = 128;
+const BRDF_LUT_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+const FACE_COUNT: usize = 6;
+/// File names of the faces of a cube map directory, in +x -x +y -y +z -z order
+#[cfg(not(target_arch = "wasm32"))]
+const FACE_NAMES: [&str; FACE_COUNT] = ["px", "nx", "py", "ny", "pz", "nz"];
+/// Loaded maps are clamped to stay finite in half floats
+const MAX_RADIANCE: f32 = 65000.0;
+
+/// Procedural sky, a gradient from the ground through the horizon to the
+/// zenith with a glow and a disk where the sun is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sky {
+    pub zenith_color: Vec3,
+    pub horizon_color: Vec3,
+    pub ground_color: Vec3,
+    /// Angular radius of the sun disk, in degrees
+    pub sun_size: f32,
+    /// Radiance of the sun disk relative to the sun's light
+    pub sun_disk_intensity: f32,
+    /// Haze around the sun relative to the sun's light
+    pub sun_glow: f32,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self {
+            zenith_color: Vec3::new(0.10, 0.22, 0.50),
+            horizon_color: Vec3::new(0.55, 0.62, 0.70),
+            ground_color: Vec3::new(0.12, 0.10, 0.09),
+            sun_size: 1.0,
+            sun_disk_intensity: 25.0,
+            sun_glow: 0.3,
+        }
+    }
+}
+
+/// Where the environment cube map comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvironmentSource {
+    Sky,
+    /// A map loaded from this file or directory
+    Map(String),
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Pod, Zeroable)]
+struct SkyUniforms {
+    zenith_color: Vec4,
+    horizon_color: Vec4,
+    ground_color: Vec4,
+    // xyz: direction towards the sun
+    sun_direction: Vec4,
+    // rgb: haze around the sun
+    sun_glow: Vec4,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct EnvironmentUniforms {
+    inverse_view_proj: Mat4,
+    // xyz: direction towards the sun, w: cosine of the disk's angular radius
+    sun_disk: Vec4,
+    // rgb: radiance of the disk, black for loaded maps which have their own
+    sun_disk_color: Vec4,
+    intensity: f32,
+    // mip level of the specular map prefiltered for roughness 1
+    specular_mip: f32,
+    _padding: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct FaceUniforms {
+    face: u32,
+    // roughness the specular map is prefiltered for at this face's level
+    roughness: f32,
+}
+
+/// Cube map surrounding the scene, drawn as the background of the main pass
+/// and lighting the scene through image based lighting: an irradiance map
+/// for diffuse light, a specular map prefiltered per roughness level and a
+/// lookup table of the specular BRDF, all computed on the GPU.
+///
+/// Lit pipelines find them in group 2 at the bindings of
+/// [`Environment::layout_entries`], after the shadow maps.
+pub struct Environment {
+    /// Scales the light of the environment, on the background and on surfaces
+    pub intensity: f32,
+    source: EnvironmentSource,
+    /// Render target views, per mip level and face
+    face_views: Vec<Vec<TextureView>>,
+    /// `mip_bind_groups[i]` samples mip `i` as a cube
+    mip_bind_groups: Vec<BindGroup>,
+    /// Samples every mip of the environment, for the convolutions
+    environment_bind_group: BindGroup,
+    irradiance_view: TextureView,
+    irradiance_face_views: Vec<TextureView>,
+    specular_view: TextureView,
+    specular_face_views: Vec<Vec<TextureView>>,
+    brdf_lut_view: TextureView,
+    sampler: Sampler,
+    uniform_buffer: Buffer,
+    sky_buffer: Buffer,
+    face_stride: BufferAddress,
+    face_bind_group: BindGroup,
+    equirect_bind_group_layout: BindGroupLayout,
+    cube_bind_group_layout: BindGroupLayout,
+    sky_pipeline: RenderPipeline,
+    equirect_pipeline: RenderPipeline,
+    cube_pipeline: RenderPipeline,
+    downsample_pipeline: RenderPipeline,
+    irradiance_pipeline: RenderPipeline,
+    specular_pipeline: RenderPipeline,
+    background_bind_group: BindGroup,
+    background_layout: PipelineLayout,
+    background_pipeline: RenderPipeline,
+    module: ShaderModule,
+    sample_count: u32,
+    /// Sky the cube map was last rendered with, `None` when it needs rendering
+    rendered_sky: Option<SkyUniforms>,
+}
+
+impl Environment {
+    pub fn new(device: &Device, queue: &Queue, sample_count: u32) -> Self {
+        let texture =
+            Self::create_cube(device, "Environment Map", ENVIRONMENT_SIZE, ENVIRONMENT_MIPS);
+        let cube_view = |texture: &wgpu::Texture, base_mip_level, mip_level_count| {
+            texture.create_view(&TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::Cube),
+                base_mip_level,
+                mip_level_count,
+                ..Default::default()
+            })
+        };
+        let view = cube_view(&texture, 0, None);
+        let face_views = Self::create_face_views(&texture);
+        let irradiance = Self::create_cube(device, "Irradiance Map", IRRADIANCE_SIZE, 1);
+        let irradiance_view = cube_view(&irradiance, 0, None);
+        let irradiance_face_views = Self::create_face_views(&irradiance).remove(0);
+        let specular = Self::create_cube(device, "Specular Map", SPECULAR_SIZE, SPECULAR_MIPS);
+        let specular_view = cube_view(&specular, 0, None);
+        let specular_face_views = Self::create_face_views(&specular);
+        let brdf_lut = device.create_texture(&TextureDescriptor {
+            label: Some("BRDF Lookup Table"),
+            size: Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: BRDF_LUT_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let brdf_lut_view = brdf_lut.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Environment Uniforms"),
+            size: size_of::<EnvironmentUniforms>() as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sky_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Sky Uniforms"),
+            size: size_of::<SkyUniforms>() as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // one face per dynamic offset, for each level of the specular map
+        let face_stride = align_to(
+            size_of::<FaceUniforms>() as BufferAddress,
+            device.limits().min_uniform_buffer_offset_alignment as BufferAddress,
+        );
+        let face_count = FACE_COUNT * SPECULAR_MIPS as usize;
+        let mut faces = vec![0; face_stride as usize * face_count];
+        for (i, chunk) in faces.chunks_exact_mut(face_stride as usize).enumerate() {
+            let level = i / FACE_COUNT;
+            let uniforms = FaceUniforms {
+                face: (i % FACE_COUNT) as u32,
+                roughness: level as f32 / (SPECULAR_MIPS - 1) as f32,
+            };
+            chunk[..size_of::<FaceUniforms>()].copy_from_slice(bytemuck::bytes_of(&uniforms));
+        }
+        let face_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Environment Faces"),
+            contents: &faces,
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let uniform_entry = |binding, size: usize, has_dynamic_offset| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset,
+                min_binding_size: BufferSize::new(size as u64),
+            },
+            count: None,
+        };
+        let texture_entry = |binding, view_dimension| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = BindGroupLayoutEntry {
+            binding: 2,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        };
+        let face_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Environment Face Bind Group Layout"),
+            entries: &[
+                uniform_entry(0, size_of::<FaceUniforms>(), true),
+                uniform_entry(1, size_of::<SkyUniforms>(), false),
+                sampler_entry,
+            ],
+        });
+        let equirect_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Environment Equirect Bind Group Layout"),
+                entries: &[texture_entry(0, TextureViewDimension::D2)],
+            });
+        let cube_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Environment Cube Bind Group Layout"),
+            entries: &[texture_entry(1, TextureViewDimension::Cube)],
+        });
+        let background_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Background Bind Group Layout"),
+                entries: &[
+                    sampler_entry,
+                    texture_entry(3, TextureViewDimension::Cube),
+                    uniform_entry(4, size_of::<EnvironmentUniforms>(), false),
+                ],
+            });
+
+        let face_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Environment Face Bind Group"),
+            layout: &face_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &face_buffer,
+                        offset: 0,
+                        size: BufferSize::new(size_of::<FaceUniforms>() as u64),
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: sky_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        let mip_bind_groups = (0..ENVIRONMENT_MIPS)
+            .map(|level| {
+                let view = cube_view(&texture, level, Some(1));
+                Self::create_cube_bind_group(device, &cube_bind_group_layout, &view)
+            })
+            .collect();
+        let environment_bind_group =
+            Self::create_cube_bind_group(device, &cube_bind_group_layout, &view);
+        let background_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Background Bind Group"),
+            layout: &background_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(&sampler),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&view),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Environment Shader"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("environment.wgsl"))),
+        });
+        let create_pipeline = |entry_point,
+                               source_layout: Option<&BindGroupLayout>,
+                               format: TextureFormat| {
+            let mut bind_group_layouts = vec![&face_bind_group_layout];
+            bind_group_layouts.extend(source_layout);
+            let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                vertex: VertexState {
+                    module: &module,
+                    entry_point: Some("vs_face"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    buffers: &[],
+                },
+                fragment: Some(FragmentState {
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    targets: &[Some(format.into())],
+                }),
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let cube_layout = Some(&cube_bind_group_layout);
+        let sky_pipeline = create_pipeline("fs_sky", None, HDR_FORMAT);
+        let equirect_pipeline =
+            create_pipeline("fs_equirect", Some(&equirect_bind_group_layout), HDR_FORMAT);
+        let cube_pipeline = create_pipeline("fs_cube", cube_layout, HDR_FORMAT);
+        let downsample_pipeline = create_pipeline("fs_downsample", cube_layout, HDR_FORMAT);
+        let irradiance_pipeline = create_pipeline("fs_irradiance", cube_layout, HDR_FORMAT);
+        let specular_pipeline = create_pipeline("fs_specular", cube_layout, HDR_FORMAT);
+        let brdf_pipeline = create_pipeline("fs_brdf", None, BRDF_LUT_FORMAT);
+        let background_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&background_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let background_pipeline =
+            Self::create_background_pipeline(device, &background_layout, &module, sample_count);
+        let environment = Self {
+            intensity: 1.0,
+            source: EnvironmentSource::Sky,
+            face_views,
+            mip_bind_groups,
+            environment_bind_group,
+            irradiance_view,
+            irradiance_face_views,
+            specular_view,
+            specular_face_views,
+            brdf_lut_view,
+            sampler,
+            uniform_buffer,
+            sky_buffer,
+            face_stride,
+            face_bind_group,
+            equirect_bind_group_layout,
+            cube_bind_group_layout,
+            sky_pipeline,
+            equirect_pipeline,
+            cube_pipeline,
+            downsample_pipeline,
+            irradiance_pipeline,
+            specular_pipeline,
+            background_bind_group,
+            background_layout,
+            background_pipeline,
+            module,
+            sample_count,
+            rendered_sky: None,
+        };
+        // the lookup table only depends on the BRDF, render it once
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("BRDF Lookup Table Encoder"),
+        });
+        let faces = std::slice::from_ref(&environment.brdf_lut_view);
+        environment.render_faces(&mut encoder, faces, 0, &brdf_pipeline, None);
+        queue.submit(Some(encoder.finish()));
+        environment
+    }
+
+    /// Entries 4 to 8 of the scene bind group layout: the irradiance map,
+    /// the sampler, the environment uniforms, the specular map and the BRDF
+    /// lookup table.
+    pub fn layout_entries() -> [BindGroupLayoutEntry; 5] {
+        let texture_entry = |binding, view_dimension| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        [
+            texture_entry(4, TextureViewDimension::Cube), // irradiance map
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 6, // environment uniforms
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(size_of::<EnvironmentUniforms>() as u64),
+                },
+                count: None,
+            },
+            texture_entry(7, TextureViewDimension::Cube), // specular map
+            texture_entry(8, TextureViewDimension::D2),   // BRDF lookup table
+        ]
+    }
+
+    pub fn bind_group_entries(&self) -> [BindGroupEntry<'_>; 5] {
+        [
+            BindGroupEntry {
+                binding: 4,
+                resource: BindingResource::TextureView(&self.irradiance_view),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: BindingResource::Sampler(&self.sampler),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 7,
+                resource: BindingResource::TextureView(&self.specular_view),
+            },
+            BindGroupEntry {
+                binding: 8,
+                resource: BindingResource::TextureView(&self.brdf_lut_view),
+            },
+        ]
+    }
+
+    pub fn source(&self) -> &EnvironmentSource {
+        &self.source
+    }
+
+    /// Go back to the procedural sky, rendered on the next frame.
+    pub fn use_sky(&mut self) {
+        self.source = EnvironmentSource::Sky;
+        self.rendered_sky = None;
+    }
+
+    /// Decode an equirectangular map, like a Radiance HDR, PNG or JPEG file
+    /// embedded with `include_bytes!`.
+    pub fn load_bytes(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        name: &str,
+        bytes: &[u8],
+    ) -> Result<(), ImageError> {
+        let image = image::load_from_memory(bytes)?.to_rgba32f();
+        self.load_equirect(device, queue, &image);
+        self.source = EnvironmentSource::Map(name.to_string());
+        Ok(())
+    }
+
+    /// Read an equirectangular map, or a cube map from a directory holding
+    /// one image per face named `px`, `nx`, `py`, `ny`, `pz` and `nz`. Not
+    /// available on the web, see [`Self::load_bytes`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_file(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        path: impl AsRef<Path>,
+    ) -> Result<(), ImageError> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            let mut faces = Vec::with_capacity(FACE_COUNT);
+            for name in FACE_NAMES {
+                let face = std::fs::read_dir(path)?
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .find(|file| file.file_stem().is_some_and(|stem| stem == name))
+                    .ok_or_else(|| {
+                        let message = format!("no {name} face in {}", path.display());
+                        std::io::Error::new(std::io::ErrorKind::NotFound, message)
+                    })?;
+                faces.push(image::open(face)?.to_rgba32f());
+            }
+            self.load_cube(device, queue, &faces)?;
+        } else {
+            let image = image::open(path)?.to_rgba32f();
+            self.load_equirect(device, queue, &image);
+        }
+        self.source = EnvironmentSource::Map(path.to_string_lossy().into_owned());
+        Ok(())
+    }
+
+    fn load_equirect(&self, device: &Device, queue: &Queue, image: &Rgba32FImage) {
+        let max_size = device.limits().max_texture_dimension_2d;
+        let (width, height) = image.dimensions();
+        let texture = if width > max_size || height > max_size {
+            let scale = max_size as f32 / width.max(height) as f32;
+            let (width, height) = (width as f32 * scale, height as f32 * scale);
+            let resized =
+                imageops::resize(image, width as u32, height as u32, FilterType::Triangle);
+            Self::upload(device, queue, "Equirect Map", &[resized])
+        } else {
+            Self::upload(device, queue, "Equirect Map", std::slice::from_ref(image))
+        };
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.equirect_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(
+                    &texture.create_view(&TextureViewDescriptor::default()),
+                ),
+            }],
+        });
+        self.render_map(device, queue, &self.equirect_pipeline, &bind_group);
+    }
+
+    fn load_cube(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        faces: &[Rgba32FImage],
+    ) -> Result<(), ImageError> {
+        let (width, height) = faces[0].dimensions();
+        if width != height || faces.iter().any(|face| face.dimensions() != (width, height)) {
+            return Err(ImageError::Parameter(image::error::ParameterError::from_kind(
+                image::error::ParameterErrorKind::DimensionMismatch,
+            )));
+        }
+        let texture = Self::upload(device, queue, "Cube Map", faces);
+        let view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let bind_group = Self::create_cube_bind_group(device, &self.cube_bind_group_layout, &view);
+        self.render_map(device, queue, &self.cube_pipeline, &bind_group);
+        Ok(())
+    }
+
+    /// Resample a loaded map into the environment cube map.
+    fn render_map(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        pipeline: &RenderPipeline,
+        source: &BindGroup,
+    ) {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Environment Encoder"),
+        });
+        self.render_faces(&mut encoder, &self.face_views[0], 0, pipeline, Some(source));
+        self.downsample(&mut encoder);
+        self.convolve(&mut encoder);
+        queue.submit(Some(encoder.finish()));
+    }
+
+    /// Upload layers of half float texels, one per image.
+    fn upload(
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+        images: &[Rgba32FImage],
+    ) -> wgpu::Texture {
+        let (width, height) = images[0].dimensions();
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: images.len() as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for (layer, image) in images.iter().enumerate() {
+            let texels: Vec<f16> = image
+                .as_raw()
+                .iter()
+                .map(|&texel| f16::from_f32(texel.clamp(0.0, MAX_RADIANCE)))
+                .collect();
+            queue.write_texture(
+                TexelCopyTextureInfo {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                bytemuck::cast_slice(&texels),
+                TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(8 * width),
+                    rows_per_image: Some(height),
+                },
+                Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        texture
+    }
+
+    fn create_cube_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        view: &TextureView,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(view),
+            }],
+        })
+    }
+
+    /// Rebuild the background pipeline for the main pass' sample count.
+    pub fn update_pipeline(&mut self, device: &Device, sample_count: u32) {
+        if sample_count != self.sample_count {
+            self.background_pipeline = Self::create_background_pipeline(
+                device,
+                &self.background_layout,
+                &self.module,
+                sample_count,
+            );
+            self.sample_count = sample_count;
+        }
+    }
+
+    /// Upload this frame's uniforms. The procedural sky is rendered into the
+    /// cube map again when it or the sun changed.
+    pub fn prepare(
+        &mut self,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        sky: &Sky,
+        sun: &Sun,
+        inverse_view_proj: Mat4,
+    ) {
+        let procedural = self.source == EnvironmentSource::Sky;
+        let towards_sun = -sun.direction.normalize_or(-Vec3::Z);
+        let sun_disk_color = if procedural && sun.enabled {
+            sun.color * sun.intensity * sky.sun_disk_intensity
+        } else {
+            Vec3::ZERO
+        };
+        let uniforms = EnvironmentUniforms {
+            inverse_view_proj,
+            sun_disk: towards_sun.extend(sky.sun_size.to_radians().cos()),
+            sun_disk_color: sun_disk_color.extend(0.0),
+            intensity: self.intensity,
+            specular_mip: (SPECULAR_MIPS - 1) as f32,
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        if !procedural {
+            return;
+        }
+        let sun_glow = if sun.enabled {
+            sun.color * sun.intensity * sky.sun_glow
+        } else {
+            Vec3::ZERO
+        };
+        let sky = SkyUniforms {
+            zenith_color: sky.zenith_color.extend(1.0),
+            horizon_color: sky.horizon_color.extend(1.0),
+            ground_color: sky.ground_color.extend(1.0),
+            sun_direction: towards_sun.extend(0.0),
+            sun_glow: sun_glow.extend(0.0),
+        };
+        if self.rendered_sky != Some(sky) {
+            queue.write_buffer(&self.sky_buffer, 0, bytemuck::bytes_of(&sky));
+            self.render_faces(encoder, &self.face_views[0], 0, &self.sky_pipeline, None);
+            self.downsample(encoder);
+            self.convolve(encoder);
+            self.rendered_sky = Some(sky);
+        }
+    }
+
+    /// Draw the environment where nothing else was, must come after the
+    /// scene in the main pass.
+    pub fn draw_background<'a>(&'a self, pass: &mut RenderPass<'a>) {
+        pass.set_pipeline(&self.background_pipeline);
+        pass.set_bind_group(0, &self.background_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    /// Render each face in `faces` with `pipeline`, which samples `source`
+    /// if it has one. `level` picks the roughness the face uniforms hold.
+    fn render_faces(
+        &self,
+        encoder: &mut CommandEncoder,
+        faces: &[TextureView],
+        level: usize,
+        pipeline: &RenderPipeline,
+        source: Option<&BindGroup>,
+    ) {
+        for (face, view) in faces.iter().enumerate() {
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Environment Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::BLACK),
+                        store: StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                ..Default::default()
+            });
+            pass.set_pipeline(pipeline);
+            let index = level * FACE_COUNT + face;
+            let offset = (self.face_stride * index as BufferAddress) as DynamicOffset;
+            pass.set_bind_group(0, &self.face_bind_group, &[offset]);
+            if let Some(source) = source {
+                pass.set_bind_group(1, source, &[]);
+            }
+            pass.draw(0..3, 0..1);
+        }
+    }
+
+    /// Fill in the mip levels below the first one.
+    fn downsample(&self, encoder: &mut CommandEncoder) {
+        for level in 1..self.face_views.len() {
+            let faces = &self.face_views[level];
+            let source = Some(&self.mip_bind_groups[level - 1]);
+            self.render_faces(encoder, faces, 0, &self.downsample_pipeline, source);
+        }
+    }
+
+    /// Convolve the environment into the irradiance and specular maps.
+    fn convolve(&self, encoder: &mut CommandEncoder) {
+        let source = Some(&self.environment_bind_group);
+        let faces = &self.irradiance_face_views;
+        self.render_faces(encoder, faces, 0, &self.irradiance_pipeline, source);
+        for (level, faces) in self.specular_face_views.iter().enumerate() {
+            self.render_faces(encoder, faces, level, &self.specular_pipeline, source);
+        }
+    }
+
+    fn create_cube(device: &Device, label: &str, size: u32, mip_level_count: u32) -> wgpu::Texture {
+        device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: FACE_COUNT as u32,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+    }
+
+    /// Render target views of a cube texture, per mip level and face.
+    fn create_face_views(texture: &wgpu::Texture) -> Vec<Vec<TextureView>> {
+        (0..texture.mip_level_count())
+            .map(|level| {
+                (0..FACE_COUNT as u32)
+                    .map(|face| {
+                        texture.create_view(&TextureViewDescriptor {
+                            dimension: Some(TextureViewDimension::D2),
+                            base_mip_level: level,
+                            mip_level_count: Some(1),
+                            base_array_layer: face,
+                            array_layer_count: Some(1),
+                            ..Default::default()
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn create_background_pipeline(
+        device: &Device,
+        layout: &PipelineLayout,
+        module: &ShaderModule,
+        sample_count: u32,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Background Pipeline"),
+            layout: Some(layout),
+            vertex: VertexState {
+                module,
+                entry_point: Some("vs_background"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module,
+                entry_point: Some("fs_background"),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(HDR_FORMAT.into())],
+            }),
+            primitive: PrimitiveState::default(),
+            // drawn at the far plane, so only passes where the depth is
+            // still cleared to it
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+}
@@ -0,0 +1,271 @@
+// Renders the environment cube map one face at a time, from the procedural
+// sky, an equirectangular map or another cube map, and draws it as the
+// background of the main pass. The cube map is then convolved into the
+// irradiance and prefiltered specular maps of image based lighting, which
+// come with a lookup table of the specular BRDF.
+const PI = 3.14159265;
+const TAU = 6.2831853;
+const IRRADIANCE_SAMPLES = 256u;
+const SPECULAR_SAMPLES = 64u;
+const BRDF_SAMPLES = 512u;
+// matches pbr.wgsl
+const MIN_ROUGHNESS = 0.045;
+
+struct SkyUniforms {
+    zenith_color: vec4f,
+    horizon_color: vec4f,
+    ground_color: vec4f,
+    // xyz: direction towards the sun
+    sun_direction: vec4f,
+    // rgb: haze around the sun
+    sun_glow: vec4f,
+};
+
+struct EnvironmentUniforms {
+    inverse_view_proj: mat4x4f,
+    // xyz: direction towards the sun, w: cosine of the disk's angular radius
+    sun_disk: vec4f,
+    sun_disk_color: vec4f,
+    intensity: f32,
+    specular_mip: f32,
+};
+
+struct Face {
+    // in +x -x +y -y +z -z order
+    index: u32,
+    // roughness the specular map is prefiltered for
+    roughness: f32,
+};
+
+struct FaceOutput {
+    @builtin(position) position: vec4f,
+    @location(0) uv: vec2f,
+};
+
+struct BackgroundOutput {
+    @builtin(position) position: vec4f,
+    @location(0) ndc: vec2f,
+};
+
+@group(0) @binding(0)
+var<uniform> face: Face;
+@group(0) @binding(1)
+var<uniform> sky: SkyUniforms;
+@group(0) @binding(2)
+var linear_sampler: sampler;
+@group(0) @binding(3)
+var environment_map: texture_cube<f32>;
+@group(0) @binding(4)
+var<uniform> environment: EnvironmentUniforms;
+@group(1) @binding(0)
+var source_equirect: texture_2d<f32>;
+@group(1) @binding(1)
+var source_cube: texture_cube<f32>;
+
+@vertex
+fn vs_face(@builtin(vertex_index) index: u32) -> FaceOutput {
+    var result: FaceOutput;
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+    result.position = vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    result.uv = uv;
+    return result;
+}
+
+// world direction through `uv` of the current face
+fn face_direction(uv: vec2f) -> vec3f {
+    let s = uv.x * 2.0 - 1.0;
+    let t = uv.y * 2.0 - 1.0;
+    switch face.index {
+        case 0u: { return normalize(vec3(1.0, -t, -s)); }
+        case 1u: { return normalize(vec3(-1.0, -t, s)); }
+        case 2u: { return normalize(vec3(s, 1.0, t)); }
+        case 3u: { return normalize(vec3(s, -1.0, -t)); }
+        case 4u: { return normalize(vec3(s, -t, 1.0)); }
+        default: { return normalize(vec3(-s, -t, -1.0)); }
+    }
+}
+
+// Loaded maps are authored y up, the world is z up.
+fn source_direction(direction: vec3f) -> vec3f {
+    return vec3(direction.x, direction.z, -direction.y);
+}
+
+@fragment
+fn fs_sky(vertex: FaceOutput) -> @location(0) vec4f {
+    let direction = face_direction(vertex.uv);
+    let up = direction.z;
+    var color: vec3f;
+    if up >= 0.0 {
+        color = mix(sky.horizon_color.rgb, sky.zenith_color.rgb, sqrt(up));
+    } else {
+        color = mix(sky.horizon_color.rgb, sky.ground_color.rgb, saturate(-up * 8.0));
+    }
+    let cos_sun = max(dot(direction, sky.sun_direction.xyz), 0.0);
+    color += sky.sun_glow.rgb * pow(cos_sun, 8.0);
+    return vec4(color, 1.0);
+}
+
+@fragment
+fn fs_equirect(vertex: FaceOutput) -> @location(0) vec4f {
+    let direction = source_direction(face_direction(vertex.uv));
+    let u = atan2(direction.x, -direction.z) / TAU + 0.5;
+    let v = acos(clamp(direction.y, -1.0, 1.0)) / PI;
+    return vec4(textureSampleLevel(source_equirect, linear_sampler, vec2(u, v), 0.0).rgb, 1.0);
+}
+
+@fragment
+fn fs_cube(vertex: FaceOutput) -> @location(0) vec4f {
+    let direction = source_direction(face_direction(vertex.uv));
+    return vec4(textureSampleLevel(source_cube, linear_sampler, direction, 0.0).rgb, 1.0);
+}
+
+// Averages the level above, sampled as a whole cube so filtering crosses
+// face edges.
+@fragment
+fn fs_downsample(vertex: FaceOutput) -> @location(0) vec4f {
+    let direction = face_direction(vertex.uv);
+    return vec4(textureSampleLevel(source_cube, linear_sampler, direction, 0.0).rgb, 1.0);
+}
+
+// Low discrepancy point set in the unit square
+fn hammersley(i: u32, count: u32) -> vec2f {
+    var bits = (i << 16u) | (i >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2(f32(i) / f32(count), f32(bits) * 2.3283064365386963e-10);
+}
+
+// columns are a tangent, a bitangent and `n`
+fn tangent_basis(n: vec3f) -> mat3x3f {
+    let up = select(vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), abs(n.z) < 0.999);
+    let t = normalize(cross(up, n));
+    return mat3x3(t, cross(n, t), n);
+}
+
+// half vector around +z, distributed like GGX times n.h
+fn ggx_half_vector(xi: vec2f, alpha: f32) -> vec3f {
+    let phi = TAU * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith G2 divided by 4 n.l n.v, as in pbr.wgsl
+fn visibility_smith(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+    let l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+    return 0.5 / max(v + l, 1e-5);
+}
+
+// Mip level of the source where a texel covers about as much of the sphere
+// as one of `count` samples taken with density `pdf`, which keeps few
+// samples from aliasing.
+fn sample_level(pdf: f32, count: u32) -> f32 {
+    let size = f32(textureDimensions(source_cube).x);
+    let texel_solid_angle = 4.0 * PI / (6.0 * size * size);
+    let sample_solid_angle = 1.0 / (f32(count) * pdf + 1e-4);
+    return max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+}
+
+// Cosine weighted average of the radiance over the hemisphere around the
+// face direction, diffuse surfaces reflect it times their albedo.
+@fragment
+fn fs_irradiance(vertex: FaceOutput) -> @location(0) vec4f {
+    let n = face_direction(vertex.uv);
+    let basis = tangent_basis(n);
+    var color = vec3(0.0);
+    for (var i = 0u; i < IRRADIANCE_SAMPLES; i++) {
+        let xi = hammersley(i, IRRADIANCE_SAMPLES);
+        let phi = TAU * xi.x;
+        let cos_theta = sqrt(1.0 - xi.y);
+        let sin_theta = sqrt(xi.y);
+        let l = basis * vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+        let level = sample_level(cos_theta / PI, IRRADIANCE_SAMPLES);
+        color += textureSampleLevel(source_cube, linear_sampler, l, level).rgb;
+    }
+    return vec4(color / f32(IRRADIANCE_SAMPLES), 1.0);
+}
+
+// Radiance convolved with the GGX lobe of `face.roughness`, assuming the
+// view and reflection directions both equal the normal.
+@fragment
+fn fs_specular(vertex: FaceOutput) -> @location(0) vec4f {
+    let n = face_direction(vertex.uv);
+    if face.roughness == 0.0 {
+        return vec4(textureSampleLevel(source_cube, linear_sampler, n, 0.0).rgb, 1.0);
+    }
+    let alpha = face.roughness * face.roughness;
+    let basis = tangent_basis(n);
+    var color = vec3(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < SPECULAR_SAMPLES; i++) {
+        let h = basis * ggx_half_vector(hammersley(i, SPECULAR_SAMPLES), alpha);
+        let l = reflect(-n, h);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            let pdf = distribution_ggx(saturate(dot(n, h)), alpha) * 0.25;
+            let level = sample_level(pdf, SPECULAR_SAMPLES);
+            color += textureSampleLevel(source_cube, linear_sampler, l, level).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4(color / max(weight, 1e-4), 1.0);
+}
+
+// Scale and bias to f0 of the specular BRDF integrated over the hemisphere,
+// by n.v along u and roughness along v.
+@fragment
+fn fs_brdf(vertex: FaceOutput) -> @location(0) vec4f {
+    let n_dot_v = max(vertex.uv.x, 1e-4);
+    let roughness = max(vertex.uv.y, MIN_ROUGHNESS);
+    let alpha = roughness * roughness;
+    let v = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < BRDF_SAMPLES; i++) {
+        let h = ggx_half_vector(hammersley(i, BRDF_SAMPLES), alpha);
+        let l = reflect(-v, h);
+        if l.z > 0.0 {
+            let v_dot_h = saturate(dot(v, h));
+            // BRDF times n.l over the density of `l`, fresnel left out
+            let g = visibility_smith(n_dot_v, l.z, alpha) * 4.0 * l.z * v_dot_h / max(h.z, 1e-4);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * g;
+            bias += fresnel * g;
+        }
+    }
+    return vec4(scale, bias, 0.0, 1.0) / f32(BRDF_SAMPLES);
+}
+
+// Fullscreen triangle on the far plane, depth 0 as depth is reversed.
+@vertex
+fn vs_background(@builtin(vertex_index) index: u32) -> BackgroundOutput {
+    var result: BackgroundOutput;
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+    result.ndc = uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0);
+    result.position = vec4(result.ndc, 0.0, 1.0);
+    return result;
+}
+
+@fragment
+fn fs_background(vertex: BackgroundOutput) -> @location(0) vec4f {
+    // two points along the view ray, also right for orthographic cameras
+    let near = environment.inverse_view_proj * vec4(vertex.ndc, 1.0, 1.0);
+    let middle = environment.inverse_view_proj * vec4(vertex.ndc, 0.5, 1.0);
+    let direction = normalize(middle.xyz / middle.w - near.xyz / near.w);
+    var color = textureSampleLevel(environment_map, linear_sampler, direction, 0.0).rgb;
+    let cos_sun = dot(direction, environment.sun_disk.xyz);
+    let edge = fwidth(cos_sun);
+    let disk = smoothstep(environment.sun_disk.w - edge, environment.sun_disk.w + edge, cos_sun);
+    color += environment.sun_disk_color.rgb * disk;
+    return vec4(color * environment.intensity, 1.0);
+}
@@ -0,0 +1,206 @@
+use glam::{Mat4, Quat, Vec3};
+use std::collections::HashSet;
+use winit::keyboard::KeyCode;
+
+const LOOK_SPEED: f32 = 0.003;
+const BOOST: f32 = 4.0;
+
+/// First person camera flown with WASD, Q/E for down/up, Z/C to roll when
+/// the roll lock is off, and mouse to look around. Z is up.
+pub struct FlyCamera {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub roll: f32,
+    /// Top speed in units per second
+    pub speed: f32,
+    /// How quickly velocity catches up with input, per second
+    pub acceleration: f32,
+    pub roll_lock: bool,
+    velocity: Vec3,
+    keys: HashSet<KeyCode>,
+}
+
+impl Default for FlyCamera {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            yaw: 0.0,
+            pitch: 0.0,
+            roll: 0.0,
+            speed: 30.0,
+            acceleration: 8.0,
+            roll_lock: true,
+            velocity: Vec3::ZERO,
+            keys: HashSet::new(),
+        }
+    }
+}
+
+impl FlyCamera {
+    /// Start flying from `eye`, facing `target`.
+    pub fn place(&mut self, eye: Vec3, target: Vec3) {
+        let forward = (target - eye).normalize_or(Vec3::X);
+        self.position = eye;
+        self.yaw = forward.y.atan2(forward.x);
+        self.pitch = forward.z.asin();
+        self.roll = 0.0;
+        self.velocity = Vec3::ZERO;
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        Vec3::new(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+        )
+    }
+
+    pub fn up(&self) -> Vec3 {
+        if self.roll_lock {
+            Vec3::Z
+        } else {
+            Quat::from_axis_angle(self.forward(), self.roll) * Vec3::Z
+        }
+    }
+
+    pub fn get_view_matrix(&self) -> Mat4 {
+        Mat4::look_to_rh(self.position, self.forward(), self.up())
+    }
+
+    /// Turn by a mouse delta in pixels.
+    pub fn look(&mut self, dx: f32, dy: f32) {
+        self.yaw -= dx * LOOK_SPEED;
+        self.pitch = (self.pitch - dy * LOOK_SPEED).clamp(-1.5, 1.5);
+    }
+
+    pub fn set_key(&mut self, key: KeyCode, pressed: bool) {
+        if pressed {
+            self.keys.insert(key);
+        } else {
+            self.keys.remove(&key);
+        }
+    }
+
+    pub fn release_keys(&mut self) {
+        self.keys.clear();
+    }
+
+    /// Integrate movement over `dt` seconds of real time.
+    pub fn update(&mut self, dt: f32) {
+        let axis = |positive: KeyCode, negative: KeyCode| {
+            self.keys.contains(&positive) as i32 as f32 - self.keys.contains(&negative) as i32 as f32
+        };
+        let forward = self.forward();
+        let right = forward.cross(Vec3::Z).normalize_or(Vec3::Y);
+        let direction = forward * axis(KeyCode::KeyW, KeyCode::KeyS)
+            + right * axis(KeyCode::KeyD, KeyCode::KeyA)
+            + Vec3::Z * axis(KeyCode::KeyE, KeyCode::KeyQ);
+        let boost = if self.keys.contains(&KeyCode::ShiftLeft) {
+            BOOST
+        } else {
+            1.0
+        };
+        let desired = direction.normalize_or_zero() * self.speed * boost;
+        self.velocity += (desired - self.velocity) * (1.0 - (-self.acceleration * dt).exp());
+        self.position += self.velocity * dt;
+        if self.roll_lock {
+            self.roll = 0.0;
+        } else {
+            self.roll += axis(KeyCode::KeyC, KeyCode::KeyZ) * dt;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fly(keys: &[KeyCode], seconds: f32) -> FlyCamera {
+        let mut camera = FlyCamera::default();
+        for &key in keys {
+            camera.set_key(key, true);
+        }
+        for _ in 0..(seconds * 60.0) as u32 {
+            camera.update(1.0 / 60.0);
+        }
+        camera
+    }
+
+    #[test]
+    fn place_faces_target() {
+        let mut camera = FlyCamera::default();
+        let eye = Vec3::new(10.0, -20.0, 5.0);
+        let target = Vec3::new(-3.0, 4.0, -1.0);
+        camera.place(eye, target);
+        assert_eq!(camera.position, eye);
+        assert!(camera.forward().distance((target - eye).normalize()) < 1e-5);
+    }
+
+    #[test]
+    fn forward_flies_along_view() {
+        let camera = fly(&[KeyCode::KeyW], 2.0);
+        assert!(camera.position.x > 0.0);
+        assert!(camera.position.y.abs() < 1e-4 && camera.position.z.abs() < 1e-4);
+    }
+
+    #[test]
+    fn strafe_and_climb() {
+        let camera = fly(&[KeyCode::KeyD, KeyCode::KeyE], 1.0);
+        // facing +x with z up, right is -y
+        assert!(camera.position.y < 0.0);
+        assert!(camera.position.z > 0.0);
+        assert!(camera.position.x.abs() < 1e-4);
+    }
+
+    #[test]
+    fn opposite_keys_cancel() {
+        let camera = fly(&[KeyCode::KeyW, KeyCode::KeyS], 1.0);
+        assert_eq!(camera.position, Vec3::ZERO);
+    }
+
+    #[test]
+    fn velocity_approaches_speed() {
+        let slow = fly(&[KeyCode::KeyW], 5.0);
+        assert!((slow.velocity.length() - slow.speed).abs() < 0.01);
+        let boosted = fly(&[KeyCode::KeyW, KeyCode::ShiftLeft], 5.0);
+        assert!((boosted.velocity.length() - slow.speed * BOOST).abs() < 0.05);
+        // diagonals are not faster
+        let diagonal = fly(&[KeyCode::KeyW, KeyCode::KeyD], 5.0);
+        assert!((diagonal.velocity.length() - slow.speed).abs() < 0.01);
+    }
+
+    #[test]
+    fn coasts_to_a_stop_after_release() {
+        let mut camera = fly(&[KeyCode::KeyW], 1.0);
+        camera.release_keys();
+        for _ in 0..300 {
+            camera.update(1.0 / 60.0);
+        }
+        assert!(camera.velocity.length() < 1e-3);
+    }
+
+    #[test]
+    fn pitch_is_clamped() {
+        let mut camera = FlyCamera::default();
+        camera.look(0.0, -10_000.0);
+        assert_eq!(camera.pitch, 1.5);
+        camera.look(0.0, 10_000.0);
+        assert_eq!(camera.pitch, -1.5);
+    }
+
+    #[test]
+    fn roll_only_without_lock() {
+        let locked = fly(&[KeyCode::KeyC], 1.0);
+        assert_eq!(locked.roll, 0.0);
+        assert_eq!(locked.up(), Vec3::Z);
+        let mut camera = FlyCamera {
+            roll_lock: false,
+            ..Default::default()
+        };
+        camera.set_key(KeyCode::KeyC, true);
+        camera.update(0.5);
+        assert_eq!(camera.roll, 0.5);
+        assert!(camera.up().distance(Vec3::Z) > 0.1);
+    }
+}
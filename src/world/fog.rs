@@ -0,0 +1,112 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec3, Vec4};
+use std::mem::size_of;
+use wgpu::{
+    BindGroupEntry, BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType,
+    BufferDescriptor, BufferSize, BufferUsages, Device, Queue, ShaderStages,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct FogSettings {
+    pub enabled: bool,
+    pub color: Vec3,
+    /// How much of the sky behind a surface replaces `color`, from 0 to 1
+    pub sky_blend: f32,
+    /// Extinction per unit of distance, everywhere
+    pub density: f32,
+    /// Extinction per unit of distance at `base_height`
+    pub height_density: f32,
+    /// How fast the height fog thins out going up, per unit of height
+    pub height_falloff: f32,
+    /// World z where the height fog has `height_density`
+    pub base_height: f32,
+    /// Scattering of blue light per unit of distance, red and green scatter less
+    pub aerial_density: f32,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            color: Vec3::new(0.55, 0.62, 0.70),
+            sky_blend: 0.5,
+            density: 0.0005,
+            height_density: 0.01,
+            height_falloff: 0.04,
+            base_height: -80.0,
+            aerial_density: 0.001,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct FogUniforms {
+    // rgb: fog color, a: sky blend
+    color: Vec4,
+    // xyz: camera position, w: 1 when enabled
+    eye_position: Vec4,
+    density: f32,
+    height_density: f32,
+    height_falloff: f32,
+    base_height: f32,
+    aerial_density: f32,
+    _padding: [f32; 3],
+}
+
+/// Distance fog, height fog and aerial perspective, applied by every scene
+/// pipeline to the surfaces it draws. The sky color comes from the
+/// environment, so the background is left as is and far surfaces fade into it.
+///
+/// Scene pipelines find the uniforms in group 2 at the binding of
+/// [`Fog::layout_entries`], after the environment.
+pub struct Fog {
+    uniform_buffer: Buffer,
+}
+
+impl Fog {
+    pub fn new(device: &Device) -> Self {
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Fog Uniform Buffer"),
+            size: size_of::<FogUniforms>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self { uniform_buffer }
+    }
+
+    /// Entry 9 of the scene bind group layout: the fog uniforms.
+    pub fn layout_entries() -> [BindGroupLayoutEntry; 1] {
+        [BindGroupLayoutEntry {
+            binding: 9, // fog uniforms
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(size_of::<FogUniforms>() as u64),
+            },
+            count: None,
+        }]
+    }
+
+    pub fn bind_group_entries(&self) -> [BindGroupEntry<'_>; 1] {
+        [BindGroupEntry {
+            binding: 9,
+            resource: self.uniform_buffer.as_entire_binding(),
+        }]
+    }
+
+    pub fn prepare(&self, queue: &Queue, settings: &FogSettings, eye_position: Vec3) {
+        let uniforms = FogUniforms {
+            color: settings.color.extend(settings.sky_blend.clamp(0.0, 1.0)),
+            eye_position: eye_position.extend(if settings.enabled { 1.0 } else { 0.0 }),
+            density: settings.density,
+            height_density: settings.height_density,
+            height_falloff: settings.height_falloff,
+            base_height: settings.base_height,
+            aerial_density: settings.aerial_density,
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+}
@@ -0,0 +1,297 @@
+use crate::material::Material;
+use crate::world::{Light, MAX_LIGHT};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec4};
+use std::mem::size_of;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferAddress, BufferBindingType, BufferDescriptor,
+    BufferSize, BufferSlice, BufferUsages, Device, Limits, Queue, ShaderStages, VertexAttribute,
+    VertexBufferLayout, VertexStepMode, vertex_attr_array,
+};
+
+/// Instances the buffer holds at first, it doubles whenever a frame needs
+/// more
+const MIN_INSTANCES: u64 = 256;
+
+/// What every entity of a frame shares. Must match `Frame` in
+/// material/frame.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, Debug)]
+pub struct FrameUniforms {
+    pub view_proj: Mat4,
+    pub view: Mat4,
+    // xyz: camera position
+    pub eye_position: Vec4,
+    pub time: f32,
+    pub light_count: u32,
+    pub _padding: [u32; 2],
+}
+
+/// Per instance vertex attributes of scene shaders, in vertex buffer
+/// slot 1. Must match `InstanceInput` in material/pbr.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, Debug)]
+pub struct NodeInstance {
+    pub world: Mat4,
+    /// Columns of the rotation applied to normals
+    pub rotation: [Vec4; 3],
+    pub material: Material,
+}
+
+impl NodeInstance {
+    pub fn new(world: Mat4, rotation: Mat4, material: Material) -> Self {
+        Self {
+            world,
+            rotation: [rotation.x_axis, rotation.y_axis, rotation.z_axis],
+            material,
+        }
+    }
+
+    /// Locations 5 to 14 follow those of `Vertex::desc`.
+    pub fn desc() -> VertexBufferLayout<'static> {
+        const ATTRIBS: [VertexAttribute; 10] = vertex_attr_array![
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+            9 => Float32x4,
+            10 => Float32x4,
+            11 => Float32x4,
+            12 => Float32x4,
+            13 => Float32x4,
+            14 => Float32x4
+        ];
+        VertexBufferLayout {
+            array_stride: size_of::<NodeInstance>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &ATTRIBS,
+        }
+    }
+}
+
+/// Uniforms uploaded once per frame for all scene pipelines.
+///
+/// Group 1 holds the frame uniforms and the lights, the same bind group for
+/// every pipeline of the main pass. The instances of all entities are
+/// staged into one vertex buffer and uploaded together; entities sharing a
+/// mesh and a shader sit next to each other, so each run of them is a
+/// single instanced draw over its range, see [`Frame::write_instances`].
+/// The instance buffer grows with the scene, up to the largest buffer the
+/// device allows.
+pub struct Frame {
+    pub layout: BindGroupLayout,
+    pub bind_group: BindGroup,
+    uniform_buffer: Buffer,
+    light_buffer: Buffer,
+    instance_buffer: Buffer,
+    max_instances: u64,
+    staging: Vec<NodeInstance>,
+}
+
+impl Frame {
+    pub fn new(device: &Device) -> Self {
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Frame Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0, // frame uniforms
+                    visibility: ShaderStages::VERTEX_FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(size_of::<FrameUniforms>() as u64),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1, // lights
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(0),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Frame Uniform Buffer"),
+            size: size_of::<FrameUniforms>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let light_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Light Buffer"),
+            size: MAX_LIGHT * size_of::<Light>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Frame Bind Group"),
+            layout: &layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: light_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let max_instances = Self::instance_limit(&device.limits());
+        Self {
+            layout,
+            bind_group,
+            uniform_buffer,
+            light_buffer,
+            instance_buffer: Self::create_instance_buffer(device, MIN_INSTANCES.min(max_instances)),
+            max_instances,
+            staging: Vec::new(),
+        }
+    }
+
+    fn create_instance_buffer(device: &Device, capacity: u64) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: capacity * size_of::<NodeInstance>() as u64,
+            // GPU culling reads it as storage
+            usage: BufferUsages::VERTEX | BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Most instances one buffer can hold on a device with `limits`. GPU
+    /// culling binds the whole buffer as storage, so it is bound by both the
+    /// buffer and the storage binding size.
+    fn instance_limit(limits: &Limits) -> u64 {
+        let max_size = limits.max_buffer_size.min(limits.max_storage_buffer_binding_size as u64);
+        max_size / size_of::<NodeInstance>() as u64
+    }
+
+    /// Instances a buffer holding `capacity` has to grow to for `count`,
+    /// the next power of two up to `max_instances`. None if they fit.
+    fn grown_capacity(count: u64, capacity: u64, max_instances: u64) -> Option<u64> {
+        (count > capacity).then(|| count.next_power_of_two().min(max_instances))
+    }
+
+    /// The most instances the device can hold in one buffer, entities past
+    /// it cannot be drawn.
+    pub fn max_instances(&self) -> usize {
+        self.max_instances.min(u32::MAX as u64) as usize
+    }
+
+    /// Bytes of all buffers the frame allocated.
+    pub fn memory_usage(&self) -> u64 {
+        self.uniform_buffer.size() + self.light_buffer.size() + self.instance_buffer.size()
+    }
+
+    /// The instances uploaded by `write_instances`, for vertex buffer slot 1.
+    pub fn instances(&self) -> BufferSlice<'_> {
+        self.instance_buffer.slice(..)
+    }
+
+    /// Replaced when it grows.
+    pub fn instance_buffer(&self) -> &Buffer {
+        &self.instance_buffer
+    }
+
+    /// Upload the frame uniforms and the lights, at most `MAX_LIGHT` of them.
+    /// Returns the number of buffer writes.
+    pub fn prepare(&self, queue: &Queue, uniforms: FrameUniforms, lights: &[Light]) -> u32 {
+        let lights = &lights[..lights.len().min(MAX_LIGHT as usize)];
+        let uniforms = FrameUniforms {
+            light_count: lights.len() as u32,
+            ..uniforms
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        if lights.is_empty() {
+            return 1;
+        }
+        queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(lights));
+        2
+    }
+
+    /// Stage the instances of all entities in draw order and upload them in
+    /// one write, growing the buffer if they do not fit. The `i`th is drawn
+    /// as instance `i`. There must be at most `max_instances` of them.
+    /// Returns the number of buffer writes, none without instances.
+    pub fn write_instances(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        instances: impl Iterator<Item = NodeInstance>,
+    ) -> u32 {
+        self.staging.clear();
+        self.staging.extend(instances);
+        let count = self.staging.len() as u64;
+        assert!(
+            count <= self.max_instances,
+            "{count} instances do not fit the largest buffer of the device, {} instances",
+            self.max_instances
+        );
+        let instance_size = size_of::<NodeInstance>() as u64;
+        let capacity = self.instance_buffer.size() / instance_size;
+        if let Some(capacity) = Self::grown_capacity(count, capacity, self.max_instances) {
+            self.instance_buffer = Self::create_instance_buffer(device, capacity);
+            log::info!(
+                "grew the instance buffer to {capacity} instances, {} KiB",
+                capacity * instance_size / 1024
+            );
+        }
+        if self.staging.is_empty() {
+            return 0;
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&self.staging));
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_that_fit_keep_the_buffer() {
+        assert_eq!(Frame::grown_capacity(0, MIN_INSTANCES, 1 << 20), None);
+        assert_eq!(Frame::grown_capacity(MIN_INSTANCES, MIN_INSTANCES, 1 << 20), None);
+    }
+
+    #[test]
+    fn buffer_grows_to_the_next_power_of_two() {
+        let max = 1 << 20;
+        assert_eq!(Frame::grown_capacity(MIN_INSTANCES + 1, MIN_INSTANCES, max), Some(512));
+        assert_eq!(Frame::grown_capacity(1000, 512, max), Some(1024));
+        assert_eq!(Frame::grown_capacity(1024 * 3, 1024, max), Some(4096));
+    }
+
+    #[test]
+    fn buffer_grows_at_most_to_the_device_limit() {
+        assert_eq!(Frame::grown_capacity(700, 256, 600), Some(600));
+        assert_eq!(Frame::grown_capacity(600, 256, 600), Some(600));
+    }
+
+    #[test]
+    fn buffer_grows_at_most_to_the_storage_binding_limit() {
+        let instance_size = size_of::<NodeInstance>() as u64;
+        let limits = Limits {
+            max_buffer_size: 1 << 30,
+            max_storage_buffer_binding_size: (600 * instance_size) as u32,
+            ..Limits::default()
+        };
+        let max = Frame::instance_limit(&limits);
+        assert_eq!(max, 600);
+        assert_eq!(Frame::grown_capacity(700, 512, max), Some(600));
+    }
+
+    #[test]
+    fn instance_layout_matches_the_struct() {
+        let layout = NodeInstance::desc();
+        assert_eq!(layout.array_stride, size_of::<NodeInstance>() as BufferAddress);
+        let last = layout.attributes.last().unwrap();
+        assert_eq!(last.offset + 16, layout.array_stride);
+    }
+}
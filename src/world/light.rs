@@ -6,4 +6,8 @@ pub struct Light {
     pub position: Vec3,
     pub radius: f32,
     pub color: Vec4,
+    /// Index into the point shadow maps, -1 when the light casts no shadow
+    pub shadow: i32,
+    pub intensity: f32,
+    pub _padding: [u32; 2],
 }
@@ -1,11 +1,45 @@
+mod anti_aliasing;
+mod bloom;
 mod camera;
+mod camera_input;
+mod camera_rig;
+mod clock;
+mod culling;
+mod debug;
+mod director;
+mod environment;
+mod fly_camera;
+mod fog;
+mod frame;
 mod light;
 mod node;
+mod particles;
+mod post;
+mod profiler;
 mod renderer;
-pub use camera::Camera;
+mod shadow;
+mod trail;
+pub use anti_aliasing::AntiAliasing;
+pub use bloom::{Bloom, BloomSettings};
+pub use camera::{Camera, Projection};
+pub use camera_input::CameraInput;
+pub use camera_rig::{CameraMode, CameraRig};
+pub use clock::Clock;
+pub use culling::{CullBatch, DRAW_ARGS_SIZE, GpuCulling};
+pub use debug::{DebugDraw, DebugSettings};
+pub use director::{Director, ShotTargets};
+pub use environment::{Environment, EnvironmentSource, Sky};
+pub use fly_camera::FlyCamera;
+pub use fog::{Fog, FogSettings};
+pub use frame::{Frame, FrameUniforms, NodeInstance};
 pub use light::Light;
 pub use node::Node;
 pub use node::NodeRef;
-pub use renderer::MAX_ENTITY;
+pub use particles::{EmitterRef, EmitterSettings, ParticleBlend, ParticleEmitter, ParticleSystem};
+pub use post::{HDR_FORMAT, PostProcess, ToneMapping, Tonemapper};
+pub use profiler::{PROFILE_HISTORY, ProfileReport, Profiler};
+pub use renderer::DEPTH_COMPARE;
 pub use renderer::MAX_LIGHT;
 pub use renderer::Renderer;
+pub use shadow::{MAX_SHADOW_LIGHTS, Shadows, Sun};
+pub use trail::{Trail, TrailRef, TrailSettings, Trails};
@@ -1,21 +1,24 @@
-use crate::geometry::Mesh;
-use crate::material::Shader;
+use crate::geometry::{Aabb, BoundingSphere, Mesh};
+use crate::material::{BlendMode, Material, Shader, TextureSet};
+use crate::world::{EmitterRef, TrailRef};
 use glam::{EulerRot, Mat4, Vec3, f32::Quat};
 use std::{cell::RefCell, rc::Rc};
 use wgpu::Color;
 
+#[derive(Default)]
 pub enum Variant {
     Entity(Rc<Mesh>, Rc<dyn Shader>),
-    Light(Color, f32),
+    /// Color, radius and intensity. Light falls off with the inverse square
+    /// of the distance and fades out completely at the radius.
+    Light(Color, f32, f32),
+    /// Spawns particles where the node is
+    Emitter(EmitterRef),
+    /// Ribbon through the trail's points, in the node's space
+    Trail(TrailRef),
+    #[default]
     Group,
 }
 
-impl Default for Variant {
-    fn default() -> Self {
-        Self::Group
-    }
-}
-
 pub type NodeRef = Rc<RefCell<Node>>;
 
 pub struct Node {
@@ -25,6 +28,21 @@ pub struct Node {
     pub variant: Variant,
     pub children: Vec<NodeRef>,
     pub parent: Option<NodeRef>,
+    /// Entities are drawn into shadow maps, lights render shadow maps
+    pub cast_shadow: bool,
+    /// Surface of an entity, unlit shaders only take its base and emissive
+    /// colors
+    pub material: Material,
+    /// How the surface of an entity combines with what is behind it. Kept
+    /// on the node rather than in `material`, which is uploaded with the
+    /// instance, as it picks the pipeline and the pass the entity is drawn
+    /// in. Each entity has a material of its own, none are shared.
+    pub blend: BlendMode,
+    /// Base color and normal maps, the renderer binds plain defaults if unset
+    pub textures: Option<Rc<TextureSet>>,
+    /// World space bounds of the entities of this node and all below it, as
+    /// of the last `update_bounds`. None where there are none.
+    pub bounds: Option<(Aabb, BoundingSphere)>,
 }
 
 impl Default for Node {
@@ -36,6 +54,11 @@ impl Default for Node {
             variant: Variant::default(),
             children: Vec::new(),
             parent: None,
+            cast_shadow: true,
+            material: Material::default(),
+            blend: BlendMode::default(),
+            textures: None,
+            bounds: None,
         }
     }
 }
@@ -45,9 +68,23 @@ impl Node {
         Rc::new(RefCell::new(Node::default()))
     }
 
-    pub fn new_light(color: Color, radius: f32) -> NodeRef {
+    pub fn new_light(color: Color, radius: f32, intensity: f32) -> NodeRef {
+        Rc::new(RefCell::new(Node {
+            variant: Variant::Light(color, radius, intensity),
+            ..Default::default()
+        }))
+    }
+
+    pub fn new_emitter(emitter: EmitterRef) -> NodeRef {
         Rc::new(RefCell::new(Node {
-            variant: Variant::Light(color, radius),
+            variant: Variant::Emitter(emitter),
+            ..Default::default()
+        }))
+    }
+
+    pub fn new_trail(trail: TrailRef) -> NodeRef {
+        Rc::new(RefCell::new(Node {
+            variant: Variant::Trail(trail),
             ..Default::default()
         }))
     }
@@ -80,4 +117,40 @@ impl Node {
     pub fn add_child(&mut self, child: NodeRef) {
         self.children.push(child);
     }
+    /// World space bounds of an entity drawn with `world`, the transforms
+    /// of its ancestors and its own combined: a sphere for a quick test and
+    /// a box for a tighter one. None for nodes drawing nothing.
+    pub fn world_bounds(&self, world: &Mat4) -> Option<(Aabb, BoundingSphere)> {
+        let Variant::Entity(geometry, shader) = &self.variant else {
+            return None;
+        };
+        let (aabb, sphere) = Self::entity_bounds(geometry, shader.as_ref());
+        Some((aabb.transform(world), sphere.transform(world)))
+    }
+    /// Fold the world bounds of the entities below `node`, whose transform
+    /// combined with its ancestors' is `world`, into the `bounds` of every
+    /// node on the way up.
+    pub fn update_bounds(node: &NodeRef, world: &Mat4) -> Option<(Aabb, BoundingSphere)> {
+        let node_ref = node.borrow();
+        let mut bounds = node_ref.world_bounds(world);
+        for child in &node_ref.children {
+            let child_world = *world * child.borrow().calculate_transform();
+            bounds = match (bounds, Self::update_bounds(child, &child_world)) {
+                (Some((aabb, sphere)), Some((child_aabb, child_sphere))) => {
+                    Some((aabb.union(child_aabb), sphere.union(child_sphere)))
+                }
+                (bounds, child_bounds) => bounds.or(child_bounds),
+            };
+        }
+        drop(node_ref);
+        node.borrow_mut().bounds = bounds;
+        bounds
+    }
+    /// Bounds of `geometry` in the node's space once `shader` drew it.
+    pub fn entity_bounds(geometry: &Mesh, shader: &dyn Shader) -> (Aabb, BoundingSphere) {
+        match shader.deformed_bounds(geometry) {
+            Some(aabb) => (aabb, BoundingSphere::from_aabb(&aabb)),
+            None => (geometry.aabb, geometry.sphere),
+        }
+    }
 }
@@ -0,0 +1,463 @@
+use crate::world::{DEPTH_COMPARE, HDR_FORMAT};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3, Vec4};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::mem::size_of;
+use std::rc::Rc;
+use wgpu::{
+    Adapter, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendComponent, BlendFactor,
+    BlendOperation, BlendState, Buffer, BufferBindingType, BufferDescriptor, BufferSize,
+    BufferUsages, ColorTargetState, ColorWrites, CommandEncoder, ComputePassDescriptor,
+    ComputePipeline, ComputePipelineDescriptor, DepthBiasState, DepthStencilState, Device,
+    DownlevelFlags, FragmentState, MultisampleState, PipelineCompilationOptions, PipelineLayout,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages,
+    StencilState, TextureFormat, VertexState,
+};
+
+/// Matches `WORKGROUP_SIZE` in particles.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+/// Position, age, velocity and lifetime
+const PARTICLE_SIZE: u64 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParticleBlend {
+    /// Adds light, for sparks and glows. Order doesn't matter.
+    Additive,
+    /// Covers what is behind by alpha, for smoke and clouds
+    Alpha,
+}
+
+impl fmt::Display for ParticleBlend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParticleBlend::Additive => write!(f, "Additive"),
+            ParticleBlend::Alpha => write!(f, "Alpha"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EmitterSettings {
+    pub enabled: bool,
+    /// Particles spawned per second
+    pub rate: f32,
+    /// Seconds a particle lives
+    pub lifetime: f32,
+    /// Spawn speed, in units per second
+    pub speed: f32,
+    /// Spawn direction in the emitter node's space
+    pub direction: Vec3,
+    /// Angle around `direction` particles spawn within, in degrees
+    pub spread: f32,
+    /// Particles spawn within this distance of the emitter
+    pub spawn_radius: f32,
+    /// Share of the velocity lost per second, exponentially
+    pub drag: f32,
+    /// Acceleration in world space
+    pub gravity: Vec3,
+    /// Linear color and alpha at birth, fading to `end_color` at death
+    pub start_color: Vec4,
+    pub end_color: Vec4,
+    /// Scales both colors, above 1 particles bloom
+    pub intensity: f32,
+    /// Width of the quad at birth, growing or shrinking to `end_size`
+    pub start_size: f32,
+    pub end_size: f32,
+    /// Share of the lifetime alpha takes to ramp up from 0, so particles
+    /// don't pop in
+    pub fade_in: f32,
+    pub blend: ParticleBlend,
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rate: 100.0,
+            lifetime: 2.0,
+            speed: 10.0,
+            direction: Vec3::Z,
+            spread: 30.0,
+            spawn_radius: 0.0,
+            drag: 0.5,
+            gravity: Vec3::ZERO,
+            start_color: Vec4::ONE,
+            end_color: Vec4::new(1.0, 1.0, 1.0, 0.0),
+            intensity: 1.0,
+            start_size: 1.0,
+            end_size: 1.0,
+            fade_in: 0.0,
+            blend: ParticleBlend::Additive,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct EmitterUniforms {
+    // xyz: position last frame, w: spawn radius
+    previous_position: Vec4,
+    // xyz: position, w: speed
+    position: Vec4,
+    // xyz: direction in world space, w: cosine of the spread
+    direction: Vec4,
+    // xyz: gravity, w: drag
+    gravity: Vec4,
+    start_color: Vec4,
+    end_color: Vec4,
+    spawn_start: u32,
+    spawn_count: u32,
+    capacity: u32,
+    seed: u32,
+    delta_time: f32,
+    lifetime: f32,
+    start_size: f32,
+    end_size: f32,
+    fade_in: f32,
+    additive: u32,
+    _padding: [u32; 2],
+}
+
+struct EmitterBuffers {
+    uniform_buffer: Buffer,
+    /// Reads the particles, for drawing
+    bind_group: BindGroup,
+    /// Writes the particles, for the compute pass
+    update_bind_group: BindGroup,
+}
+
+pub type EmitterRef = Rc<RefCell<ParticleEmitter>>;
+
+/// Spawns particles where its node is. The particles live on the GPU in a
+/// ring of `capacity`, new ones replace the oldest, so it should hold at
+/// least `rate * lifetime` of them.
+pub struct ParticleEmitter {
+    pub settings: EmitterSettings,
+    capacity: u32,
+    next_spawn: u32,
+    /// Fraction of a particle carried over to the next frame
+    spawn_accumulator: f32,
+    previous_position: Option<Vec3>,
+    seed: u32,
+    /// Created by the particle system the first time it sees the emitter
+    buffers: Option<EmitterBuffers>,
+}
+
+impl ParticleEmitter {
+    pub fn new(settings: EmitterSettings, capacity: u32) -> EmitterRef {
+        Rc::new(RefCell::new(Self {
+            settings,
+            capacity: capacity.max(1),
+            next_spawn: 0,
+            spawn_accumulator: 0.0,
+            previous_position: None,
+            seed: 0,
+            buffers: None,
+        }))
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+}
+
+/// Simulates emitters' particles in a compute pass and draws them as
+/// billboards at the end of the main pass, after the background. Needs
+/// compute shaders and storage buffers in vertex shaders, see
+/// [`ParticleSystem::supported`].
+pub struct ParticleSystem {
+    emitter_layout: BindGroupLayout,
+    update_layout: BindGroupLayout,
+    update_pipeline: ComputePipeline,
+    render_layout: PipelineLayout,
+    module: ShaderModule,
+    additive_pipeline: RenderPipeline,
+    alpha_pipeline: RenderPipeline,
+    frame: u32,
+}
+
+impl ParticleSystem {
+    pub fn supported(adapter: &Adapter) -> bool {
+        let flags = adapter.get_downlevel_capabilities().flags;
+        flags.contains(DownlevelFlags::COMPUTE_SHADERS | DownlevelFlags::VERTEX_STORAGE)
+    }
+
+    /// `frame_layout` and `scene_layout` are the renderer's groups 1 and 2,
+    /// for the camera and the fog.
+    pub fn new(
+        device: &Device,
+        frame_layout: &BindGroupLayout,
+        scene_layout: &BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let uniform_entry = |binding, visibility, size| BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(size as u64),
+            },
+            count: None,
+        };
+        let particles_entry = |binding, visibility, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(PARTICLE_SIZE),
+            },
+            count: None,
+        };
+        let emitter_size = size_of::<EmitterUniforms>();
+        let emitter_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Particle Emitter Bind Group Layout"),
+            entries: &[
+                uniform_entry(0, ShaderStages::VERTEX_FRAGMENT, emitter_size),
+                particles_entry(1, ShaderStages::VERTEX, true),
+            ],
+        });
+        let update_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Particle Update Bind Group Layout"),
+            entries: &[
+                uniform_entry(0, ShaderStages::COMPUTE, emitter_size),
+                particles_entry(2, ShaderStages::COMPUTE, false),
+            ],
+        });
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Particle Shader"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(concat!(
+                include_str!("../material/frame.wgsl"),
+                include_str!("../material/environment.wgsl"),
+                include_str!("../material/fog.wgsl"),
+                include_str!("particles.wgsl")
+            ))),
+        });
+        let update_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Particle Update Pipeline"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&update_layout],
+                push_constant_ranges: &[],
+            })),
+            module: &module,
+            entry_point: Some("cs_update"),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        });
+        let render_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&emitter_layout, frame_layout, scene_layout],
+            push_constant_ranges: &[],
+        });
+        let (additive_pipeline, alpha_pipeline) =
+            Self::create_pipelines(device, &render_layout, &module, sample_count);
+        Self {
+            emitter_layout,
+            update_layout,
+            update_pipeline,
+            render_layout,
+            module,
+            additive_pipeline,
+            alpha_pipeline,
+            frame: 0,
+        }
+    }
+
+    /// Rebuild the pipelines for a new sample count of the main pass.
+    pub fn update_pipelines(&mut self, device: &Device, sample_count: u32) {
+        (self.additive_pipeline, self.alpha_pipeline) =
+            Self::create_pipelines(device, &self.render_layout, &self.module, sample_count);
+    }
+
+    /// Spawn and move the particles of `emitters`, each with the world
+    /// transform of its node, by `delta_time` seconds.
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        emitters: &[(EmitterRef, Mat4)],
+        delta_time: f32,
+    ) {
+        self.frame = self.frame.wrapping_add(1);
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Particle Update Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.update_pipeline);
+        for (i, (emitter, transform)) in emitters.iter().enumerate() {
+            let mut emitter = emitter.borrow_mut();
+            if emitter.buffers.is_none() {
+                emitter.buffers = Some(self.create_buffers(device, emitter.capacity));
+            }
+            let settings = emitter.settings;
+            let position = transform.transform_point3(Vec3::ZERO);
+            let previous_position = emitter.previous_position.unwrap_or(position);
+            emitter.previous_position = Some(position);
+            let mut spawn_count = 0;
+            if settings.enabled {
+                emitter.spawn_accumulator += settings.rate.max(0.0) * delta_time;
+                let whole = emitter.spawn_accumulator.floor();
+                emitter.spawn_accumulator -= whole;
+                spawn_count = (whole as u32).min(emitter.capacity);
+            }
+            let spawn_start = emitter.next_spawn;
+            emitter.next_spawn = (spawn_start + spawn_count) % emitter.capacity;
+            emitter.seed = emitter.seed.wrapping_add(1);
+            let direction = transform.transform_vector3(settings.direction);
+            let color = |color: Vec4| (color.truncate() * settings.intensity).extend(color.w);
+            let uniforms = EmitterUniforms {
+                previous_position: previous_position.extend(settings.spawn_radius),
+                position: position.extend(settings.speed),
+                direction: direction
+                    .normalize_or(Vec3::Z)
+                    .extend(settings.spread.to_radians().cos()),
+                gravity: settings.gravity.extend(settings.drag),
+                start_color: color(settings.start_color),
+                end_color: color(settings.end_color),
+                spawn_start,
+                spawn_count,
+                capacity: emitter.capacity,
+                seed: self.frame.wrapping_mul(0x9e37_79b9) ^ i as u32 ^ emitter.seed,
+                delta_time,
+                lifetime: settings.lifetime.max(1e-3),
+                start_size: settings.start_size,
+                end_size: settings.end_size,
+                fade_in: settings.fade_in,
+                additive: (settings.blend == ParticleBlend::Additive) as u32,
+                _padding: [0; 2],
+            };
+            let Some(buffers) = &emitter.buffers else {
+                continue;
+            };
+            queue.write_buffer(&buffers.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+            if delta_time > 0.0 {
+                pass.set_bind_group(0, &buffers.update_bind_group, &[]);
+                pass.dispatch_workgroups(emitter.capacity.div_ceil(WORKGROUP_SIZE), 1, 1);
+            }
+        }
+    }
+
+    /// Draw the particles of `emitters`, must come after the background in
+    /// the main pass with the frame and scene bind groups in groups 1 and 2.
+    /// Returns the number of draw calls.
+    pub fn draw(&self, pass: &mut RenderPass<'_>, emitters: &[(EmitterRef, Mat4)]) -> u32 {
+        let mut draw_calls = 0;
+        for (emitter, _) in emitters {
+            let emitter = emitter.borrow();
+            let Some(buffers) = &emitter.buffers else {
+                continue;
+            };
+            let pipeline = match emitter.settings.blend {
+                ParticleBlend::Additive => &self.additive_pipeline,
+                ParticleBlend::Alpha => &self.alpha_pipeline,
+            };
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &buffers.bind_group, &[]);
+            pass.draw(0..6, 0..emitter.capacity);
+            draw_calls += 1;
+        }
+        draw_calls
+    }
+
+    fn create_buffers(&self, device: &Device, capacity: u32) -> EmitterBuffers {
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Particle Emitter Buffer"),
+            size: size_of::<EmitterUniforms>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // zeroed particles have an age of 0 and a lifetime of 0, so are dead
+        let particle_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Particle Buffer"),
+            size: PARTICLE_SIZE * capacity as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let create_bind_group = |layout, particles_binding| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: particles_binding,
+                        resource: particle_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        EmitterBuffers {
+            bind_group: create_bind_group(&self.emitter_layout, 1),
+            update_bind_group: create_bind_group(&self.update_layout, 2),
+            uniform_buffer,
+        }
+    }
+
+    fn create_pipelines(
+        device: &Device,
+        layout: &PipelineLayout,
+        module: &ShaderModule,
+        sample_count: u32,
+    ) -> (RenderPipeline, RenderPipeline) {
+        let create_pipeline = |label, blend| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: VertexState {
+                    module,
+                    entry_point: Some("vs_particle"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    buffers: &[],
+                },
+                fragment: Some(FragmentState {
+                    module,
+                    entry_point: Some("fs_particle"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    targets: &[Some(ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend: Some(blend),
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState::default(),
+                // tested against the scene, but particles don't hide each other
+                depth_stencil: Some(DepthStencilState {
+                    format: TextureFormat::Depth32Float,
+                    depth_write_enabled: false,
+                    depth_compare: DEPTH_COMPARE,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample: MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+        let add = BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+        let additive = BlendState {
+            color: add,
+            alpha: add,
+        };
+        (
+            create_pipeline("Additive Particle Pipeline", additive),
+            create_pipeline("Alpha Particle Pipeline", BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+        )
+    }
+}
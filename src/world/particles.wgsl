@@ -0,0 +1,160 @@
+// Prepended with material/frame.wgsl, material/environment.wgsl and
+// material/fog.wgsl, for the frame and scene bind groups in groups 1 and 2.
+// `cs_update` spawns and moves the particles of one emitter, `vs_particle`
+// and `fs_particle` draw them as camera facing quads.
+const WORKGROUP_SIZE = 64u;
+const TAU = 6.28318531;
+// spawn speed varies by this much either way
+const SPEED_JITTER = 0.25;
+
+struct Particle {
+    position: vec3f,
+    age: f32,
+    velocity: vec3f,
+    lifetime: f32,
+};
+
+struct Emitter {
+    // xyz: where the emitter was last frame, w: radius particles spawn within
+    previous_position: vec4f,
+    // xyz: where the emitter is now, w: spawn speed
+    position: vec4f,
+    // xyz: spawn direction, w: cosine of the spread around it
+    direction: vec4f,
+    // xyz: acceleration, w: drag per second
+    gravity: vec4f,
+    start_color: vec4f,
+    end_color: vec4f,
+    spawn_start: u32,
+    spawn_count: u32,
+    capacity: u32,
+    seed: u32,
+    delta_time: f32,
+    lifetime: f32,
+    start_size: f32,
+    end_size: f32,
+    fade_in: f32,
+    additive: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) color: vec4f,
+    @location(1) world_position: vec3f,
+    // -1 to 1 across the quad
+    @location(2) corner: vec2f,
+};
+
+@group(0) @binding(0)
+var<uniform> emitter: Emitter;
+@group(0) @binding(1)
+var<storage, read> particles: array<Particle>;
+// the same buffer, writable in the compute pass
+@group(0) @binding(2)
+var<storage, read_write> simulated: array<Particle>;
+
+fn hash(value: u32) -> u32 {
+    var state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// uniform in [0, 1), advancing `state`
+fn random(state: ptr<function, u32>) -> f32 {
+    *state = hash(*state);
+    return f32(*state >> 8u) / 16777216.0;
+}
+
+fn random_in_sphere(state: ptr<function, u32>) -> vec3f {
+    let z = random(state) * 2.0 - 1.0;
+    let phi = random(state) * TAU;
+    let r = sqrt(1.0 - z * z);
+    return vec3(r * cos(phi), r * sin(phi), z) * pow(random(state), 1.0 / 3.0);
+}
+
+// uniform over the cone of directions within the spread around `axis`
+fn random_in_cone(state: ptr<function, u32>, axis: vec3f, cos_spread: f32) -> vec3f {
+    let cos_theta = mix(1.0, cos_spread, random(state));
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let phi = random(state) * TAU;
+    let up = select(vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 0.0), abs(axis.z) > 0.999);
+    let tangent = normalize(cross(up, axis));
+    let bitangent = cross(axis, tangent);
+    return (tangent * cos(phi) + bitangent * sin(phi)) * sin_theta + axis * cos_theta;
+}
+
+fn integrate(particle: ptr<function, Particle>, delta_time: f32) {
+    (*particle).velocity += emitter.gravity.xyz * delta_time;
+    (*particle).velocity *= exp(-emitter.gravity.w * delta_time);
+    (*particle).position += (*particle).velocity * delta_time;
+    (*particle).age += delta_time;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_update(@builtin(global_invocation_id) id: vec3u) {
+    let index = id.x;
+    if index >= emitter.capacity {
+        return;
+    }
+    var particle = simulated[index];
+    // spawned particles replace the oldest ones, in a ring starting at spawn_start
+    let slot = (index + emitter.capacity - emitter.spawn_start) % emitter.capacity;
+    if slot < emitter.spawn_count {
+        var state = hash(index ^ hash(emitter.seed));
+        // spread the particles spawned this frame along the emitter's motion
+        let k = (f32(slot) + random(&state)) / f32(emitter.spawn_count);
+        let origin = mix(emitter.previous_position.xyz, emitter.position.xyz, k);
+        let speed = emitter.position.w * (1.0 + (random(&state) * 2.0 - 1.0) * SPEED_JITTER);
+        particle.position = origin + random_in_sphere(&state) * emitter.previous_position.w;
+        particle.velocity = random_in_cone(&state, emitter.direction.xyz, emitter.direction.w) * speed;
+        particle.age = 0.0;
+        particle.lifetime = emitter.lifetime;
+        integrate(&particle, (1.0 - k) * emitter.delta_time);
+    } else if particle.age < particle.lifetime {
+        integrate(&particle, emitter.delta_time);
+    }
+    simulated[index] = particle;
+}
+
+@vertex
+fn vs_particle(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    var result: VertexOutput;
+    let particle = particles[instance_index];
+    if particle.age >= particle.lifetime {
+        // dead, collapse the quad outside the clip volume
+        result.position = vec4(2.0, 2.0, 2.0, 1.0);
+        return result;
+    }
+    // two triangles, 0 1 2 and 2 1 3 of the quad's corners
+    let corner_index = array(0u, 1u, 2u, 2u, 1u, 3u)[vertex_index];
+    let corner = vec2(f32(corner_index & 1u), f32(corner_index >> 1u)) * 2.0 - 1.0;
+    let t = saturate(particle.age / particle.lifetime);
+    let size = mix(emitter.start_size, emitter.end_size, t) * 0.5;
+    // the rows of the view rotation are the camera's axes in world space
+    let right = vec3(frame.view[0].x, frame.view[1].x, frame.view[2].x);
+    let up = vec3(frame.view[0].y, frame.view[1].y, frame.view[2].y);
+    let offset = (right * corner.x + up * corner.y) * size;
+    result.world_position = particle.position + offset;
+    result.position = frame.view_proj * vec4(result.world_position, 1.0);
+    result.color = mix(emitter.start_color, emitter.end_color, t);
+    if emitter.fade_in > 0.0 {
+        result.color.a *= saturate(t / emitter.fade_in);
+    }
+    result.corner = corner;
+    return result;
+}
+
+// premultiplied, both blend modes add the color and the alpha one also
+// covers what is behind by alpha
+@fragment
+fn fs_particle(vertex: VertexOutput) -> @location(0) vec4f {
+    let falloff = saturate(1.0 - dot(vertex.corner, vertex.corner));
+    let alpha = vertex.color.a * falloff * falloff;
+    if emitter.additive != 0u {
+        return vec4(vertex.color.rgb * alpha * fog_visibility(vertex.world_position), 0.0);
+    }
+    return vec4(apply_fog(vertex.color.rgb, vertex.world_position) * alpha, alpha);
+}
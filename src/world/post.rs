@@ -0,0 +1,340 @@
+use crate::world::{Bloom, BloomSettings};
+use bytemuck::{Pod, Zeroable};
+use std::borrow::Cow;
+use std::mem::size_of;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferAddress, BufferBindingType,
+    BufferDescriptor, BufferSize, BufferUsages, Color, CommandEncoder, Device, Extent3d,
+    FilterMode, FragmentState, LoadOp, MultisampleState, Operations, PipelineCompilationOptions,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType,
+    SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, StoreOp,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+    TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+/// Format of the scene target, scene pipelines render into this.
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// Gamma the sRGB transfer function approximates
+const SRGB_GAMMA: f32 = 2.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+    Reinhard,
+    Aces,
+    AgX,
+}
+
+impl Tonemapper {
+    pub const ALL: [Tonemapper; 3] = [Tonemapper::Reinhard, Tonemapper::Aces, Tonemapper::AgX];
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ToneMapping {
+    pub tonemapper: Tonemapper,
+    /// In stops, each one doubles the brightness
+    pub exposure: f32,
+    /// Display gamma, 2.2 matches sRGB
+    pub gamma: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::Aces,
+            exposure: 0.0,
+            gamma: SRGB_GAMMA,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct PostUniforms {
+    exposure: f32,
+    encode_power: f32,
+    tonemapper: u32,
+    bloom_intensity: f32,
+    srgb_frame: u32,
+    _padding: [u32; 3],
+}
+
+/// HDR scene target and the passes resolving it into the surface.
+pub struct PostProcess {
+    pub hdr_view: TextureView,
+    bloom: Bloom,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    uniform_buffer: Buffer,
+    sampler: Sampler,
+    pipeline: RenderPipeline,
+    /// Tone mapped frame FXAA reads, in the output format
+    frame_view: TextureView,
+    fxaa_bind_group_layout: BindGroupLayout,
+    fxaa_bind_group: BindGroup,
+    fxaa_pipeline: RenderPipeline,
+    output_format: TextureFormat,
+}
+
+impl PostProcess {
+    pub fn new(device: &Device, width: u32, height: u32, output_format: TextureFormat) -> Self {
+        let texture_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let common_entries = [
+            BindGroupLayoutEntry {
+                binding: 0, // post uniforms
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(size_of::<PostUniforms>() as u64),
+                },
+                count: None,
+            },
+            texture_entry(1), // source
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Post Bind Group Layout"),
+            entries: &[
+                common_entries[0],
+                common_entries[1],
+                common_entries[2],
+                texture_entry(3), // bloom
+            ],
+        });
+        let fxaa_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("FXAA Bind Group Layout"),
+            entries: &common_entries,
+        });
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Post Shader"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("post.wgsl"))),
+        });
+        let create_pipeline = |label, layout: &BindGroupLayout, entry_point| {
+            let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: VertexState {
+                    module: &module,
+                    entry_point: Some("vs_main"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    buffers: &[],
+                },
+                fragment: Some(FragmentState {
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    targets: &[Some(output_format.into())],
+                }),
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let pipeline = create_pipeline("Tone Mapping Pipeline", &bind_group_layout, "fs_main");
+        let fxaa_pipeline = create_pipeline("FXAA Pipeline", &fxaa_bind_group_layout, "fs_fxaa");
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Post Uniforms"),
+            size: size_of::<PostUniforms>() as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Post Sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let hdr_view = Self::create_view(device, width, height, HDR_FORMAT);
+        let bloom = Bloom::new(device, &hdr_view, width, height);
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniform_buffer,
+            (&hdr_view, Some(bloom.view())),
+            &sampler,
+        );
+        let frame_view = Self::create_view(device, width, height, output_format);
+        let fxaa_bind_group = Self::create_bind_group(
+            device,
+            &fxaa_bind_group_layout,
+            &uniform_buffer,
+            (&frame_view, None),
+            &sampler,
+        );
+        Self {
+            hdr_view,
+            bloom,
+            bind_group_layout,
+            bind_group,
+            uniform_buffer,
+            sampler,
+            pipeline,
+            frame_view,
+            fxaa_bind_group_layout,
+            fxaa_bind_group,
+            fxaa_pipeline,
+            output_format,
+        }
+    }
+
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.hdr_view = Self::create_view(device, width, height, HDR_FORMAT);
+        self.bloom.resize(device, &self.hdr_view, width, height);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            (&self.hdr_view, Some(self.bloom.view())),
+            &self.sampler,
+        );
+        self.frame_view = Self::create_view(device, width, height, self.output_format);
+        self.fxaa_bind_group = Self::create_bind_group(
+            device,
+            &self.fxaa_bind_group_layout,
+            &self.uniform_buffer,
+            (&self.frame_view, None),
+            &self.sampler,
+        );
+    }
+
+    /// Add bloom to the HDR scene and tone map it into `target`, which must
+    /// have the output format given to [`PostProcess::new`]. With `fxaa` the
+    /// tone mapped frame is anti-aliased on its way to `target`.
+    pub fn resolve(
+        &self,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        target: &TextureView,
+        tone_mapping: &ToneMapping,
+        bloom: &BloomSettings,
+        fxaa: bool,
+    ) {
+        if bloom.enabled {
+            self.bloom.render(queue, encoder, bloom);
+        }
+        let gamma = tone_mapping.gamma.max(0.1);
+        // an sRGB target already encodes with roughly 1/2.2, only the difference is left
+        let srgb_output = self.output_format.is_srgb();
+        let encode_power = if srgb_output {
+            SRGB_GAMMA / gamma
+        } else {
+            1.0 / gamma
+        };
+        let uniforms = PostUniforms {
+            exposure: tone_mapping.exposure.exp2(),
+            encode_power,
+            tonemapper: tone_mapping.tonemapper as u32,
+            bloom_intensity: if bloom.enabled { bloom.intensity } else { 0.0 },
+            srgb_frame: srgb_output as u32,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        let tone_mapped = if fxaa { &self.frame_view } else { target };
+        Self::blit(encoder, &self.pipeline, &self.bind_group, tone_mapped);
+        if fxaa {
+            Self::blit(encoder, &self.fxaa_pipeline, &self.fxaa_bind_group, target);
+        }
+    }
+
+    fn blit(
+        encoder: &mut CommandEncoder,
+        pipeline: &RenderPipeline,
+        bind_group: &BindGroup,
+        target: &TextureView,
+    ) {
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Post Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    fn create_view(device: &Device, width: u32, height: u32, format: TextureFormat) -> TextureView {
+        device
+            .create_texture(&TextureDescriptor {
+                label: Some("Post Texture"),
+                size: Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&TextureViewDescriptor::default())
+    }
+
+    /// The tone mapping bind group samples `source` and `bloom`, the FXAA
+    /// one has no bloom.
+    fn create_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        (source, bloom): (&TextureView, Option<&TextureView>),
+        sampler: &Sampler,
+    ) -> BindGroup {
+        let mut entries = vec![
+            BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(source),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Sampler(sampler),
+            },
+        ];
+        if let Some(bloom) = bloom {
+            entries.push(BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::TextureView(bloom),
+            });
+        }
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Post Bind Group"),
+            layout,
+            entries: &entries,
+        })
+    }
+}
@@ -0,0 +1,158 @@
+// Resolves the HDR scene into the output format: bloom, exposure, tone
+// mapping and display encoding, then optionally FXAA. See world/post.rs.
+const REINHARD = 0u;
+const ACES = 1u;
+const AGX = 2u;
+
+struct PostUniforms {
+    // linear multiplier, 2^EV
+    exposure: f32,
+    // applied last, so the output ends up encoded for the display gamma
+    encode_power: f32,
+    tonemapper: u32,
+    bloom_intensity: f32,
+    // the tone mapped frame FXAA reads is sRGB, so its samples are linear
+    srgb_frame: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> post: PostUniforms;
+// the HDR scene when tone mapping, the tone mapped frame for FXAA
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(2)
+var source_sampler: sampler;
+@group(0) @binding(3)
+var bloom: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var result: VertexOutput;
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+    result.position = vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    result.uv = uv;
+    return result;
+}
+
+fn reinhard(color: vec3f) -> vec3f {
+    return color / (1.0 + color);
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+fn aces(color: vec3f) -> vec3f {
+    let input = mat3x3(
+        vec3(0.59719, 0.07600, 0.02840),
+        vec3(0.35458, 0.90834, 0.13383),
+        vec3(0.04823, 0.01566, 0.83777),
+    );
+    let output = mat3x3(
+        vec3(1.60475, -0.10208, -0.00327),
+        vec3(-0.53108, 1.10813, -0.07276),
+        vec3(-0.07367, -0.00605, 1.07602),
+    );
+    let v = input * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return saturate(output * (a / b));
+}
+
+// polynomial fit of the AgX base contrast curve, from Benjamin Wrensch
+fn agx_contrast(x: vec3f) -> vec3f {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+}
+
+fn agx(color: vec3f) -> vec3f {
+    let inset = mat3x3(
+        vec3(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3(
+        vec3(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+    var v = inset * max(color, vec3(1e-10));
+    v = clamp(log2(v), vec3(min_ev), vec3(max_ev));
+    v = (v - min_ev) / (max_ev - min_ev);
+    v = agx_contrast(v);
+    // the curve outputs display encoded values, decode back to linear
+    return pow(max(outset * v, vec3(0.0)), vec3(2.2));
+}
+
+@fragment
+fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    let scene = textureSample(source, source_sampler, vertex.uv).rgb;
+    let glow = textureSample(bloom, source_sampler, vertex.uv).rgb;
+    let color = (scene + glow * post.bloom_intensity) * post.exposure;
+    var mapped: vec3f;
+    switch post.tonemapper {
+        case REINHARD: {
+            mapped = reinhard(color);
+        }
+        case ACES: {
+            mapped = aces(color);
+        }
+        default: {
+            mapped = agx(color);
+        }
+    }
+    return vec4(pow(saturate(mapped), vec3(post.encode_power)), 1.0);
+}
+
+// FXAA 3.11 console variant by Timothy Lottes: blur along the edge
+// direction found from the luma of the four diagonal neighbours
+const FXAA_REDUCE_MIN = 1.0 / 128.0;
+const FXAA_REDUCE_MUL = 1.0 / 8.0;
+const FXAA_SPAN_MAX = 8.0;
+
+fn fxaa_sample(uv: vec2f) -> vec3f {
+    return textureSampleLevel(source, source_sampler, uv, 0.0).rgb;
+}
+
+fn luma(color: vec3f) -> f32 {
+    let y = dot(color, vec3(0.299, 0.587, 0.114));
+    // edges are judged perceptually
+    return select(y, sqrt(y), post.srgb_frame != 0u);
+}
+
+@fragment
+fn fs_fxaa(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2f(textureDimensions(source));
+    let uv = vertex.uv;
+    let center = fxaa_sample(uv);
+    let luma_m = luma(center);
+    let luma_nw = luma(fxaa_sample(uv + vec2(-1.0, -1.0) * texel));
+    let luma_ne = luma(fxaa_sample(uv + vec2(1.0, -1.0) * texel));
+    let luma_sw = luma(fxaa_sample(uv + vec2(-1.0, 1.0) * texel));
+    let luma_se = luma(fxaa_sample(uv + vec2(1.0, 1.0) * texel));
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+    var direction = vec2(
+        (luma_sw + luma_se) - (luma_nw + luma_ne),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2(-FXAA_SPAN_MAX), vec2(FXAA_SPAN_MAX)) * texel;
+    let inner = 0.5 * (fxaa_sample(uv + direction * (1.0 / 3.0 - 0.5))
+        + fxaa_sample(uv + direction * (2.0 / 3.0 - 0.5)));
+    let outer = inner * 0.5 + 0.25 * (fxaa_sample(uv - direction * 0.5)
+        + fxaa_sample(uv + direction * 0.5));
+    let luma_outer = luma(outer);
+    // the wider blur crossed another edge, keep the narrow one
+    if luma_outer < luma_min || luma_outer > luma_max {
+        return vec4(inner, 1.0);
+    }
+    return vec4(outer, 1.0);
+}
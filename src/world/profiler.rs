@@ -0,0 +1,452 @@
+use std::collections::VecDeque;
+use std::mem::size_of;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+use wgpu::{
+    Adapter, Buffer, BufferDescriptor, BufferUsages, CommandEncoder, ComputePassDescriptor,
+    ComputePassTimestampWrites, Device, DownlevelFlags, Features, MapMode, PollType, QuerySet,
+    QuerySetDescriptor, QueryType, Queue,
+};
+
+/// Frames the rolling averages and graphs cover
+pub const PROFILE_HISTORY: usize = 120;
+/// GPU scopes per frame, one timestamp is written where each begins and one
+/// at the end of the frame
+const MAX_TIMESTAMPS: u32 = 16;
+/// Frames of timestamps in flight, results are read back a few frames late
+/// so the CPU never waits on the GPU
+const READBACK_FRAMES: usize = 3;
+
+/// Durations of named scopes over the last frames, in milliseconds.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    scopes: Vec<(&'static str, VecDeque<f32>)>,
+    /// Sum of the scopes of each frame
+    pub totals: VecDeque<f32>,
+}
+
+impl Timings {
+    fn push_frame(&mut self, durations: &[(&'static str, f32)]) {
+        for &(name, duration) in durations {
+            let history = match self.scopes.iter().position(|(scope, _)| *scope == name) {
+                Some(i) => &mut self.scopes[i].1,
+                None => {
+                    self.scopes.push((name, VecDeque::new()));
+                    &mut self.scopes.last_mut().unwrap().1
+                }
+            };
+            Self::push(history, duration);
+        }
+        Self::push(&mut self.totals, durations.iter().map(|(_, d)| d).sum());
+    }
+
+    fn push(history: &mut VecDeque<f32>, duration: f32) {
+        if history.len() == PROFILE_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(duration);
+    }
+
+    /// Rolling average of each scope, in the order they first ran.
+    pub fn averages(&self) -> impl Iterator<Item = (&'static str, f32)> + '_ {
+        self.scopes.iter().map(|(name, history)| (*name, Self::average(history)))
+    }
+
+    pub fn average_total(&self) -> f32 {
+        Self::average(&self.totals)
+    }
+
+    fn average(history: &VecDeque<f32>) -> f32 {
+        history.iter().sum::<f32>() / history.len().max(1) as f32
+    }
+}
+
+/// What the last frame submitted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameCounts {
+    pub draw_calls: u32,
+    /// Of all instances drawn, those GPU culling kept as of a few frames
+    /// before
+    pub triangles: u64,
+    /// Writes of the frame's uniforms, lights and instances, and of the
+    /// GPU culling inputs
+    pub buffer_writes: u32,
+    pub instances: u32,
+    /// Entities outside the view frustum, left out of the main pass. Those
+    /// culled on the GPU are counted a few frames late
+    pub culled: u32,
+    /// Entities past the most instances the device holds, not drawn
+    pub dropped: u32,
+    /// Bytes of the frame's uniform, light and instance buffers, and of
+    /// those culling on the GPU
+    pub frame_memory: u64,
+}
+
+/// Copy of the profiler's results, for showing them while the renderer is
+/// busy drawing.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileReport {
+    pub cpu: Timings,
+    /// None when the adapter has no timestamp queries
+    pub gpu: Option<Timings>,
+    pub counts: FrameCounts,
+}
+
+struct Readback {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    read_buffer: Buffer,
+    /// Scopes the timestamps of this frame begin
+    names: Vec<&'static str>,
+    /// Submitted and waiting to be mapped
+    in_flight: bool,
+    mapped: Arc<AtomicBool>,
+    /// The mapping failed, the frame's timestamps are lost
+    failed: Arc<AtomicBool>,
+}
+
+/// Timestamps written between passes into a ring of query sets. Each scope
+/// starts with an empty compute pass that only writes a timestamp, so
+/// passes recorded elsewhere, like post processing, need no changes.
+struct GpuTimer {
+    readbacks: Vec<Readback>,
+    /// Readback of the frame being recorded, None while all are in flight
+    current: Option<usize>,
+    next: usize,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+}
+
+impl GpuTimer {
+    fn new(device: &Device, queue: &Queue) -> Self {
+        let size = MAX_TIMESTAMPS as u64 * size_of::<u64>() as u64;
+        let readbacks = (0..READBACK_FRAMES)
+            .map(|_| Readback {
+                query_set: device.create_query_set(&QuerySetDescriptor {
+                    label: Some("Profiler Query Set"),
+                    ty: QueryType::Timestamp,
+                    count: MAX_TIMESTAMPS,
+                }),
+                resolve_buffer: device.create_buffer(&BufferDescriptor {
+                    label: Some("Profiler Resolve Buffer"),
+                    size,
+                    usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                }),
+                read_buffer: device.create_buffer(&BufferDescriptor {
+                    label: Some("Profiler Read Buffer"),
+                    size,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                names: Vec::new(),
+                in_flight: false,
+                mapped: Arc::new(AtomicBool::new(false)),
+                failed: Arc::new(AtomicBool::new(false)),
+            })
+            .collect();
+        Self {
+            readbacks,
+            current: None,
+            next: 0,
+            period: queue.get_timestamp_period(),
+        }
+    }
+
+    /// Durations of the frames whose timestamps arrived since the last call.
+    fn collect(&mut self) -> Vec<Vec<(&'static str, f32)>> {
+        let mut frames = Vec::new();
+        for readback in &mut self.readbacks {
+            // frees the readback for a later frame, or no frame would use it
+            // again
+            if readback.failed.swap(false, Ordering::Acquire) {
+                readback.in_flight = false;
+                continue;
+            }
+            if !readback.in_flight || !readback.mapped.swap(false, Ordering::Acquire) {
+                continue;
+            }
+            {
+                let data = readback.read_buffer.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&data);
+                let durations = readback
+                    .names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &name)| {
+                        let ticks = timestamps[i + 1].saturating_sub(timestamps[i]);
+                        (name, ticks as f32 * self.period * 1e-6)
+                    })
+                    .collect();
+                frames.push(durations);
+            }
+            readback.read_buffer.unmap();
+            readback.in_flight = false;
+        }
+        frames
+    }
+
+    fn begin_frame(&mut self) {
+        let readback = &mut self.readbacks[self.next];
+        self.current = (!readback.in_flight).then(|| {
+            readback.names.clear();
+            self.next
+        });
+        self.next = (self.next + 1) % READBACK_FRAMES;
+    }
+
+    /// Write a timestamp, the end of the scope before and the start of
+    /// `name` if given.
+    fn mark(&mut self, encoder: &mut CommandEncoder, name: Option<&'static str>) {
+        let Some(readback) = self.current.map(|i| &mut self.readbacks[i]) else {
+            return;
+        };
+        // the last timestamp of the frame ends the last scope
+        let index = readback.names.len() as u32;
+        if index + 1 >= MAX_TIMESTAMPS && name.is_some() {
+            return;
+        }
+        encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Profiler Timestamp"),
+            timestamp_writes: Some(ComputePassTimestampWrites {
+                query_set: &readback.query_set,
+                beginning_of_pass_write_index: Some(index),
+                end_of_pass_write_index: None,
+            }),
+        });
+        readback.names.extend(name);
+    }
+
+    fn end_frame(&mut self, encoder: &mut CommandEncoder) {
+        let Some(i) = self.current else {
+            return;
+        };
+        if self.readbacks[i].names.is_empty() {
+            self.current = None;
+            return;
+        }
+        self.mark(encoder, None);
+        let readback = &self.readbacks[i];
+        let count = readback.names.len() as u32 + 1;
+        encoder.resolve_query_set(&readback.query_set, 0..count, &readback.resolve_buffer, 0);
+        let size = count as u64 * size_of::<u64>() as u64;
+        encoder.copy_buffer_to_buffer(&readback.resolve_buffer, 0, &readback.read_buffer, 0, size);
+    }
+
+    /// Map the timestamps of the frame just submitted.
+    fn submitted(&mut self) {
+        let Some(i) = self.current.take() else {
+            return;
+        };
+        let readback = &mut self.readbacks[i];
+        readback.in_flight = true;
+        let mapped = readback.mapped.clone();
+        let failed = readback.failed.clone();
+        readback.read_buffer.slice(..).map_async(MapMode::Read, move |result| {
+            match result {
+                Ok(()) => mapped.store(true, Ordering::Release),
+                Err(err) => {
+                    log::warn!("could not read back the GPU timestamps: {err}");
+                    failed.store(true, Ordering::Release);
+                }
+            }
+        });
+    }
+}
+
+/// Times the CPU work of each frame by scope, and where the adapter has
+/// timestamp queries the GPU work between passes, keeping rolling averages
+/// of both along with counts of what was drawn.
+pub struct Profiler {
+    pub enabled: bool,
+    cpu: Timings,
+    gpu: Option<Timings>,
+    gpu_timer: Option<GpuTimer>,
+    /// Scopes of the frame being recorded
+    frame: Vec<(&'static str, f32)>,
+    scope: Option<(&'static str, Instant)>,
+    counts: FrameCounts,
+    last_counts: FrameCounts,
+}
+
+impl Profiler {
+    /// Features the device needs for GPU timings, if the adapter has them.
+    pub fn features(adapter: &Adapter) -> Features {
+        let compute = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(DownlevelFlags::COMPUTE_SHADERS);
+        if compute {
+            adapter.features() & Features::TIMESTAMP_QUERY
+        } else {
+            Features::empty()
+        }
+    }
+
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let gpu_timer = device
+            .features()
+            .contains(Features::TIMESTAMP_QUERY)
+            .then(|| GpuTimer::new(device, queue));
+        if gpu_timer.is_none() {
+            log::info!("timestamp queries are not supported, only the CPU is profiled");
+        }
+        Self {
+            enabled: true,
+            cpu: Timings::default(),
+            gpu: gpu_timer.as_ref().map(|_| Timings::default()),
+            gpu_timer,
+            frame: Vec::new(),
+            scope: None,
+            counts: FrameCounts::default(),
+            last_counts: FrameCounts::default(),
+        }
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        ProfileReport {
+            cpu: self.cpu.clone(),
+            gpu: self.gpu.clone(),
+            counts: self.last_counts,
+        }
+    }
+
+    /// Start timing a frame, and take in GPU timings of earlier frames.
+    pub fn begin_frame(&mut self, device: &Device) {
+        self.frame.clear();
+        self.scope = None;
+        self.counts = FrameCounts::default();
+        let (Some(timer), Some(gpu)) = (&mut self.gpu_timer, &mut self.gpu) else {
+            return;
+        };
+        // lets the mapping of earlier frames finish, without waiting
+        let _ = device.poll(PollType::Poll);
+        for frame in timer.collect() {
+            gpu.push_frame(&frame);
+        }
+        if self.enabled {
+            timer.begin_frame();
+        }
+    }
+
+    /// End the current CPU scope and start `name`.
+    pub fn cpu(&mut self, name: &'static str) {
+        self.end_cpu_scope();
+        if self.enabled {
+            self.scope = Some((name, Instant::now()));
+        }
+    }
+
+    /// End the current GPU scope and start `name`, commands recorded into
+    /// `encoder` from here on count towards it.
+    pub fn gpu(&mut self, encoder: &mut CommandEncoder, name: &'static str) {
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.mark(encoder, Some(name));
+        }
+    }
+
+    pub fn count_draw(&mut self, triangles: u64) {
+        self.counts.draw_calls += 1;
+        self.counts.triangles += triangles;
+    }
+
+    /// Triangles of draws counted without them, like the indirect ones.
+    pub fn count_triangles(&mut self, triangles: u64) {
+        self.counts.triangles += triangles;
+    }
+
+    pub fn count_draws(&mut self, draw_calls: u32) {
+        self.counts.draw_calls += draw_calls;
+    }
+
+    pub fn count_buffer_writes(&mut self, writes: u32) {
+        self.counts.buffer_writes += writes;
+    }
+
+    pub fn count_culled(&mut self, culled: u32) {
+        self.counts.culled += culled;
+    }
+
+    pub fn count_dropped(&mut self, dropped: u32) {
+        self.counts.dropped += dropped;
+    }
+
+    pub fn count_instances(&mut self, instances: u32, frame_memory: u64) {
+        self.counts.instances += instances;
+        self.counts.frame_memory = frame_memory;
+    }
+
+    /// Close the last GPU scope, the frame's commands end here.
+    pub fn end_encoder(&mut self, encoder: &mut CommandEncoder) {
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.end_frame(encoder);
+        }
+    }
+
+    /// Close the last CPU scope after submitting the frame.
+    pub fn end_frame(&mut self) {
+        self.end_cpu_scope();
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.submitted();
+        }
+        self.last_counts = self.counts;
+        if self.enabled && !self.frame.is_empty() {
+            self.cpu.push_frame(&self.frame);
+        }
+    }
+
+    fn end_cpu_scope(&mut self) {
+        if let Some((name, start)) = self.scope.take() {
+            self.frame.push((name, start.elapsed().as_secs_f32() * 1000.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_keep_the_order_they_first_ran_in() {
+        let mut timings = Timings::default();
+        timings.push_frame(&[("Traversal", 1.0), ("Shadows", 2.0)]);
+        timings.push_frame(&[("Post", 4.0), ("Traversal", 3.0)]);
+        let names: Vec<_> = timings.averages().map(|(name, _)| name).collect();
+        assert_eq!(names, ["Traversal", "Shadows", "Post"]);
+    }
+
+    #[test]
+    fn averages_cover_the_frames_each_scope_ran_in() {
+        let mut timings = Timings::default();
+        timings.push_frame(&[("Traversal", 1.0), ("Shadows", 2.0)]);
+        timings.push_frame(&[("Traversal", 3.0)]);
+        let averages: Vec<_> = timings.averages().collect();
+        assert_eq!(averages, [("Traversal", 2.0), ("Shadows", 2.0)]);
+        assert_eq!(timings.totals, [3.0, 3.0]);
+        assert_eq!(timings.average_total(), 3.0);
+    }
+
+    #[test]
+    fn history_keeps_the_latest_frames() {
+        let mut timings = Timings::default();
+        for i in 0..PROFILE_HISTORY + 10 {
+            timings.push_frame(&[("Main pass", i as f32)]);
+        }
+        assert_eq!(timings.totals.len(), PROFILE_HISTORY);
+        assert_eq!(timings.totals.front(), Some(&10.0));
+        assert_eq!(timings.totals.back(), Some(&(PROFILE_HISTORY as f32 + 9.0)));
+        let expected = (10 + PROFILE_HISTORY + 9) as f32 / 2.0;
+        assert_eq!(timings.averages().next(), Some(("Main pass", expected)));
+    }
+
+    #[test]
+    fn empty_timings_average_zero() {
+        let timings = Timings::default();
+        assert_eq!(timings.averages().count(), 0);
+        assert_eq!(timings.average_total(), 0.0);
+    }
+}
@@ -1,47 +1,227 @@
-use crate::world::{node, Camera, Light, Node, NodeRef};
-use glam::{Mat4, Vec4, Vec4Swizzles};
+use crate::geometry::{Aabb, BoundingSphere, Frustum, Mesh};
+use crate::material::{BlendMode, Material, Shader, TextureCache, TextureSet};
+use crate::world::{
+    node, AntiAliasing, BloomSettings, Camera, CullBatch, DRAW_ARGS_SIZE, DebugDraw, DebugSettings,
+    EmitterRef, Environment, Fog, FogSettings, Frame, FrameUniforms, GpuCulling, HDR_FORMAT, Light,
+    MAX_SHADOW_LIGHTS, Node, NodeInstance, NodeRef, ParticleSystem, PostProcess, Profiler, Shadows,
+    Sky, Sun, ToneMapping, TrailRef, Trails,
+};
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
 use std::cmp::max;
-use std::mem::size_of;
+use std::ops::Range;
+use std::ptr;
+use std::rc::Rc;
 use std::sync::Arc;
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::Instant;
 #[cfg(target_arch = "wasm32")]
 use web_time::Instant;
-use wgpu::util::{BufferInitDescriptor, DeviceExt, align_to};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::{
-    BackendOptions, Backends, Buffer, BufferAddress, BufferDescriptor, BufferUsages, Color, CommandEncoderDescriptor, Device, DeviceDescriptor, Extent3d, IndexFormat, Instance, InstanceDescriptor, InstanceFlags, Limits, LoadOp, MemoryBudgetThresholds, Operations, Queue, RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor, RequestAdapterOptions, StoreOp, Surface, SurfaceConfiguration, SurfaceError, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor
+    BackendOptions, Backends, BindGroup, BindGroupDescriptor, BindGroupLayout, BindGroupLayoutDescriptor, Buffer, BufferAddress, BufferDescriptor, BufferSlice, BufferUsages, Color, CommandEncoderDescriptor, CompareFunction, Device, DeviceDescriptor, Extent3d, Features, IndexFormat, Instance, InstanceDescriptor, InstanceFlags, Limits, LoadOp, MemoryBudgetThresholds, Operations, Queue, RenderPass, RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor, RequestAdapterOptions, StoreOp, Surface, SurfaceConfiguration, SurfaceError, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor
 };
 use winit::window::Window;
 use egui_wgpu::{Renderer as EguiRenderer, RendererOptions};
 use egui_winit::State as EguiState;
 use egui::{Context};
 
-pub const MAX_ENTITY: u64 = 100000;
 pub const MAX_LIGHT: u64 = 10;
-const CLEAR_COLOR: Color = Color {
-    r: 0.01233333333,
-    g: 0.01233333333,
-    b: 0.02388235294,
-    a: 1.0,
-};
+/// Depth is reversed, 1 at the near plane and 0 at the far plane, which
+/// spreads float precision evenly over large distances.
+pub const DEPTH_COMPARE: CompareFunction = CompareFunction::Greater;
+const DEPTH_CLEAR: f32 = 0.0;
+
+/// An entity gathered from the scene graph for this frame.
+struct DrawNode {
+    geometry: Rc<Mesh>,
+    shader: Rc<dyn Shader>,
+    transform: Mat4,
+    rotation: Mat4,
+    material: Material,
+    textures: Option<Rc<TextureSet>>,
+    cast_shadow: bool,
+    blend: BlendMode,
+    /// Its bounds are at least partly inside the view frustum, or it is
+    /// opaque and culled on the GPU instead
+    visible: bool,
+}
+
+/// Which instances a batch draws.
+enum DrawInstances<'a> {
+    /// A range of the frame's instance buffer, which the pass must have in
+    /// vertex buffer slot 1
+    Range(Range<u32>),
+    /// The ones GPU culling kept, as many as the indirect arguments at
+    /// `offset` of `args` count
+    Indirect {
+        instances: BufferSlice<'a>,
+        args: &'a Buffer,
+        offset: BufferAddress,
+    },
+}
+
+impl DrawNode {
+    fn instance(&self) -> NodeInstance {
+        NodeInstance::new(self.transform, self.rotation, self.material)
+    }
+
+    /// Entities that sort next to each other by this are drawn together
+    /// where they also share their blend mode.
+    fn batch_key(&self) -> (*const Mesh, *const (), *const TextureSet, bool) {
+        (
+            Rc::as_ptr(&self.geometry),
+            Rc::as_ptr(&self.shader) as *const (),
+            self.textures.as_ref().map_or(ptr::null(), Rc::as_ptr),
+            self.cast_shadow,
+        )
+    }
+
+    /// Whether both differ only in their instance.
+    fn batches_with(&self, other: &DrawNode) -> bool {
+        self.batch_key() == other.batch_key() && self.blend == other.blend
+    }
+
+    /// The index buffer the main pass draws with and its length, the mesh
+    /// edges for the wireframe.
+    fn indices(&self, wireframe: bool) -> (&Buffer, u32) {
+        let geometry = &self.geometry;
+        if wireframe {
+            (&geometry.edge_index_buffer, geometry.edge_index_count)
+        } else {
+            (&geometry.index_buffer, geometry.indices.len() as u32)
+        }
+    }
+
+    /// Draw `instances` with this entity's mesh and shader. The shader must
+    /// have been updated with the same `wireframe` setting, its pipeline
+    /// draws lines then. Returns the triangles of one instance.
+    fn draw<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        instances: DrawInstances<'a>,
+        default_textures: &'a TextureSet,
+        wireframe: bool,
+    ) -> u64 {
+        let textures = self.textures.as_deref().unwrap_or(default_textures);
+        pass.set_bind_group(3, &textures.bind_group, &[]);
+        self.shader.set_pipeline(pass, self.blend);
+        let (index_buffer, count) = self.indices(wireframe);
+        pass.set_index_buffer(index_buffer.slice(..), IndexFormat::Uint32);
+        pass.set_vertex_buffer(0, self.geometry.vertex_buffer.slice(..));
+        match instances {
+            DrawInstances::Range(instances) => pass.draw_indexed(0..count, 0, instances),
+            DrawInstances::Indirect {
+                instances,
+                args,
+                offset,
+            } => {
+                pass.set_vertex_buffer(1, instances);
+                pass.draw_indexed_indirect(args, offset);
+            }
+        }
+        self.geometry.indices.len() as u64 / 3
+    }
+}
+
+/// A run of entities drawn with a single instanced draw, those of `node`
+/// and the ones following it in the instance buffer.
+struct Batch {
+    node: usize,
+    instances: Range<u32>,
+}
+
+impl Batch {
+    /// Merge the `include`d neighbours of `order`, the order the instances
+    /// were written in, that `same` tells only differ in their instance.
+    fn gather<T>(
+        nodes: &[T],
+        order: &[usize],
+        include: impl Fn(&T) -> bool,
+        same: impl Fn(&T, &T) -> bool,
+    ) -> Vec<Batch> {
+        let mut batches: Vec<Batch> = Vec::new();
+        for (instance, &i) in order.iter().enumerate() {
+            if !include(&nodes[i]) {
+                continue;
+            }
+            let instance = instance as u32;
+            match batches.last_mut() {
+                Some(batch)
+                    if batch.instances.end == instance && same(&nodes[batch.node], &nodes[i]) =>
+                {
+                    batch.instances.end += 1;
+                }
+                _ => batches.push(Batch {
+                    node: i,
+                    instances: instance..instance + 1,
+                }),
+            }
+        }
+        batches
+    }
+}
 
 pub struct Renderer {
     pub camera: Camera,
     pub root: NodeRef,
     pub time: f32,
+    /// Simulated seconds since the last frame, particles and trails advance
+    /// by it when the next frame is drawn
+    pub delta_time: f32,
     pub config: SurfaceConfiguration,
     pub surface: Surface<'static>,
     pub device: Device,
     pub queue: Queue,
     depth_texture_view: TextureView,
+    /// Multisampled scene target, resolved into the HDR view of `post`
+    msaa_texture_view: Option<TextureView>,
+    sample_count: u32,
     pub egui_state: EguiState,
     pub egui_renderer: EguiRenderer,
     pub egui_context: Context,
     pub regenerate_path: bool,
+    /// Camera, lights and time in group 1 of scene pipelines, and the
+    /// instances of every entity
+    pub frame: Frame,
+    /// Layout of group 2 of scene pipelines, the shadow maps followed by the
+    /// environment and the fog
+    pub scene_bind_group_layout: BindGroupLayout,
+    scene_bind_group: BindGroup,
+    pub shadows: Shadows,
+    pub sun: Sun,
+    pub environment: Environment,
+    pub sky: Sky,
+    fog_uniforms: Fog,
+    pub fog: FogSettings,
+    /// None when the adapter can't run compute shaders, emitters are ignored
+    pub particles: Option<ParticleSystem>,
+    /// None without compute shaders or indirect draws, entities are culled
+    /// on the CPU then
+    gpu_culling: Option<GpuCulling>,
+    /// Cull opaque entities in a compute pass and draw them indirectly,
+    /// where supported
+    pub cull_on_gpu: bool,
+    /// Set once the scene outgrew the instance buffer and that was logged,
+    /// cleared when it fits again
+    too_many_entities: bool,
+    trails: Trails,
+    debug_draw: DebugDraw,
+    pub debug: DebugSettings,
+    pub profiler: Profiler,
+    pub textures: TextureCache,
+    pub post: PostProcess,
+    pub tone_mapping: ToneMapping,
+    pub bloom: BloomSettings,
+    /// Applied on the next frame, must be one of `anti_aliasing_modes`
+    pub anti_aliasing: AntiAliasing,
+    pub anti_aliasing_modes: Vec<AntiAliasing>,
     window: Arc<Window>,
 }
 
 impl Renderer {
+    /// Format the tone mapping pass writes to. Natively an sRGB surface
+    /// encodes in hardware; WebGPU canvases don't support sRGB formats, so
+    /// there the tone mapping shader applies the encoding itself.
     fn adapt_texture_format(format: TextureFormat) -> TextureFormat {
         #[cfg(not(target_arch = "wasm32"))]
         {
@@ -73,16 +253,26 @@ impl Renderer {
             })
             .await
             .expect("An appropriate adapter must exist!");
-        let (device, queue) = adapter
-            .request_device(&DeviceDescriptor::default())
-            .await
-            .or(adapter
+        // needed for MSAA sample counts other than 4, and for GPU timings
+        let required_features = adapter.features()
+            & Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+            | Profiler::features(&adapter);
+        let descriptor = DeviceDescriptor {
+            required_features,
+            ..Default::default()
+        };
+        // only fall back once the default request failed, the second request
+        // must not run eagerly, on GL it would share and clobber the context
+        let (device, queue) = match adapter.request_device(&descriptor).await {
+            Ok(device) => device,
+            Err(_) => adapter
                 .request_device(&DeviceDescriptor {
                     required_limits: Limits::downlevel_webgl2_defaults(),
-                    ..Default::default()
+                    ..descriptor
                 })
-                .await)
-            .expect("A device must be present");
+                .await
+                .expect("A device must be present"),
+        };
         log::info!(
             "requested device in {:?}",
             device_request_timestamp.elapsed()
@@ -94,21 +284,14 @@ impl Renderer {
         config.format = Self::adapt_texture_format(format);
         config.view_formats.push(config.format);
         surface.configure(&device, &config);
-        let depth_texture = device.create_texture(&TextureDescriptor {
-            size: Extent3d {
-                width: config.width,
-                height: config.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Depth32Float,
-            usage: TextureUsages::RENDER_ATTACHMENT,
-            label: Some("Depth Texture"),
-            view_formats: &[],
-        });
-        let depth_texture_view = depth_texture.create_view(&TextureViewDescriptor::default());
+        let anti_aliasing_modes = AntiAliasing::supported(&adapter, device.features());
+        let anti_aliasing = [AntiAliasing::Msaa(4), AntiAliasing::Fxaa]
+            .into_iter()
+            .find(|mode| anti_aliasing_modes.contains(mode))
+            .unwrap_or(AntiAliasing::Off);
+        let sample_count = anti_aliasing.sample_count();
+        let (depth_texture_view, msaa_texture_view) =
+            Self::create_scene_targets(&device, &config, sample_count);
         log::info!(
             "in total, created new renderer in {:?}",
             new_renderer_timestamp.elapsed()
@@ -125,6 +308,49 @@ impl Renderer {
             None,
         );
         let egui_renderer = EguiRenderer::new(&device, config.format, RendererOptions::default());
+        let shadows = Shadows::new(&device);
+        let environment = Environment::new(&device, &queue, sample_count);
+        let fog_uniforms = Fog::new(&device);
+        let frame = Frame::new(&device);
+        let scene_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Scene Bind Group Layout"),
+            entries: &[
+                Shadows::layout_entries().as_slice(),
+                Environment::layout_entries().as_slice(),
+                Fog::layout_entries().as_slice(),
+            ]
+            .concat(),
+        });
+        let scene_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Scene Bind Group"),
+            layout: &scene_bind_group_layout,
+            entries: &[
+                shadows.bind_group_entries().as_slice(),
+                environment.bind_group_entries().as_slice(),
+                fog_uniforms.bind_group_entries().as_slice(),
+            ]
+            .concat(),
+        });
+        let particles = if ParticleSystem::supported(&adapter) {
+            Some(ParticleSystem::new(
+                &device,
+                &frame.layout,
+                &scene_bind_group_layout,
+                sample_count,
+            ))
+        } else {
+            log::warn!("compute shaders are not supported, particles are disabled");
+            None
+        };
+        let gpu_culling = GpuCulling::supported(&adapter).then(|| GpuCulling::new(&device));
+        if gpu_culling.is_none() {
+            log::info!("indirect draws are not supported, entities are culled on the CPU");
+        }
+        let trails = Trails::new(&device, &frame.layout, &scene_bind_group_layout, sample_count);
+        let debug_draw = DebugDraw::new(&device, sample_count);
+        let profiler = Profiler::new(&device, &queue);
+        let textures = TextureCache::new(&device, &queue);
+        let post = PostProcess::new(&device, config.width, config.height, config.format);
 
         Self {
             camera: Camera::new(),
@@ -134,11 +360,37 @@ impl Renderer {
             device,
             queue,
             time: 0.0,
+            delta_time: 0.0,
             depth_texture_view,
+            msaa_texture_view,
+            sample_count,
             egui_state,
             egui_renderer,
             egui_context,
             regenerate_path: false,
+            frame,
+            scene_bind_group_layout,
+            scene_bind_group,
+            shadows,
+            sun: Sun::default(),
+            environment,
+            sky: Sky::default(),
+            fog_uniforms,
+            fog: FogSettings::default(),
+            particles,
+            gpu_culling,
+            cull_on_gpu: true,
+            too_many_entities: false,
+            trails,
+            debug_draw,
+            debug: DebugSettings::default(),
+            profiler,
+            textures,
+            post,
+            tone_mapping: ToneMapping::default(),
+            bloom: BloomSettings::default(),
+            anti_aliasing,
+            anti_aliasing_modes,
             window,
         }
     }
@@ -147,21 +399,57 @@ impl Renderer {
         self.config.width = max(1, width);
         self.config.height = max(1, height);
         self.surface.configure(&self.device, &self.config);
-        let depth_texture = self.device.create_texture(&TextureDescriptor {
-            size: Extent3d {
-                width: self.config.width,
-                height: self.config.height,
-                depth_or_array_layers: 1,
-            },
+        (self.depth_texture_view, self.msaa_texture_view) =
+            Self::create_scene_targets(&self.device, &self.config, self.sample_count);
+        self.post.resize(&self.device, self.config.width, self.config.height);
+    }
+
+    /// Samples per pixel of the main pass, pipelines drawing into it must match.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Depth and, when multisampling, color targets of the main pass.
+    fn create_scene_targets(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        sample_count: u32,
+    ) -> (TextureView, Option<TextureView>) {
+        let size = Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        let depth_texture = device.create_texture(&TextureDescriptor {
+            size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: TextureDimension::D2,
             format: TextureFormat::Depth32Float,
             usage: TextureUsages::RENDER_ATTACHMENT,
-            label: Some("Depth Texture (Resized)"),
+            label: Some("Depth Texture"),
             view_formats: &[],
         });
-        self.depth_texture_view = depth_texture.create_view(&TextureViewDescriptor::default());
+        let msaa_texture = (sample_count > 1).then(|| {
+            device.create_texture(&TextureDescriptor {
+                size,
+                mip_level_count: 1,
+                sample_count,
+                dimension: TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: TextureUsages::RENDER_ATTACHMENT,
+                label: Some("Multisampled Scene Texture"),
+                view_formats: &[],
+            })
+        });
+        (
+            depth_texture.create_view(&TextureViewDescriptor::default()),
+            msaa_texture.map(|texture| texture.create_view(&TextureViewDescriptor::default())),
+        )
+    }
+
+    pub fn gpu_culling_supported(&self) -> bool {
+        self.gpu_culling.is_some()
     }
 
     pub fn handle_input(&mut self, event: &winit::event::WindowEvent) -> bool {
@@ -169,6 +457,18 @@ impl Renderer {
     }
 
     pub fn draw(&mut self, mut run_ui: impl FnMut(&egui::Context, &mut bool)) {
+        let sample_count = self.anti_aliasing.sample_count();
+        if sample_count != self.sample_count {
+            self.sample_count = sample_count;
+            (self.depth_texture_view, self.msaa_texture_view) =
+                Self::create_scene_targets(&self.device, &self.config, sample_count);
+            self.environment.update_pipeline(&self.device, sample_count);
+            if let Some(particles) = &mut self.particles {
+                particles.update_pipelines(&self.device, sample_count);
+            }
+            self.trails.update_pipeline(&self.device, sample_count);
+            self.debug_draw.update_pipelines(&self.device, sample_count);
+        }
         let frame = match self.surface.get_current_texture() {
             Ok(frame) => frame,
             Err(SurfaceError::Timeout) => {
@@ -187,60 +487,94 @@ impl Renderer {
         let mut encoder = self
             .device
             .create_command_encoder(&CommandEncoderDescriptor::default());
+        self.profiler.begin_frame(&self.device);
+        self.profiler.cpu("Traversal");
         let mut nodes = Vec::new();
-        let mut lights: Vec<(Color, f32, Mat4)> = Vec::new();
-        nodes.clear();
-        lights.clear();
-        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
-            color_attachments: &[Some(RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
-                ops: Operations {
-                    load: LoadOp::Clear(CLEAR_COLOR),
-                    store: StoreOp::Store,
-                },
-                depth_slice: None,
-            })],
-            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
-                view: &self.depth_texture_view,
-                depth_ops: Some(Operations {
-                    load: LoadOp::Clear(1.0),
-                    store: StoreOp::Discard,
-                }),
-                stencil_ops: None,
-            }),
-            ..Default::default()
-        });
+        let mut lights: Vec<(Color, f32, f32, Mat4, bool)> = Vec::new();
+        let mut emitters: Vec<(EmitterRef, Mat4)> = Vec::new();
+        let mut trails: Vec<(TrailRef, Mat4)> = Vec::new();
+        // the entities out of view are still gathered, they may cast
+        // shadows into it, along with the lights, emitters and trails
         let mut q = Vec::new();
-        q.push((self.root.clone(), Mat4::IDENTITY));
-        let vp_matrix = self.camera.make_vp_matrix(
-            self.config.width as f32 / self.config.height as f32,
-        );
-        while let Some((node, transform_mx)) = q.pop() {
-            match &node.borrow().variant {
+        q.push((self.root.clone(), Mat4::IDENTITY, false));
+        let aspect_ratio = self.config.width as f32 / self.config.height as f32;
+        let vp_matrix = self.camera.make_vp_matrix(aspect_ratio);
+        let frustum = Frustum::from_view_proj(&vp_matrix);
+        let in_view = |(aabb, sphere): &(Aabb, BoundingSphere)| {
+            frustum.intersects_sphere(sphere) && frustum.intersects_aabb(aabb)
+        };
+        let cull_on_gpu = self.cull_on_gpu && self.gpu_culling.is_some();
+        Node::update_bounds(&self.root, &Mat4::IDENTITY);
+        while let Some((node, transform_mx, parent_culled)) = q.pop() {
+            let node_ref = node.borrow();
+            // all below a node out of view are too, without testing each
+            let culled = parent_culled || node_ref.bounds.as_ref().is_some_and(|b| !in_view(b));
+            match &node_ref.variant {
                 node::Variant::Entity(geometry, shader) => {
                     let (_scale, rotation, _translation) =
                         transform_mx.to_scale_rotation_translation();
                     let rotation = Mat4::from_quat(rotation);
-                    nodes.push((geometry.clone(), shader.clone(), transform_mx, rotation));
+                    // blended entities keep their order, so only opaque
+                    // ones are left to the GPU. The bounds of a leaf are its
+                    // own, those of a parent also hold its children.
+                    let visible = (cull_on_gpu && node_ref.blend.is_opaque())
+                        || (!culled
+                            && (node_ref.children.is_empty()
+                                || node_ref
+                                    .world_bounds(&transform_mx)
+                                    .is_some_and(|bounds| in_view(&bounds))));
+                    nodes.push(DrawNode {
+                        geometry: geometry.clone(),
+                        shader: shader.clone(),
+                        transform: transform_mx,
+                        rotation,
+                        material: node_ref.material,
+                        textures: node_ref.textures.clone(),
+                        cast_shadow: node_ref.cast_shadow,
+                        blend: node_ref.blend,
+                        visible,
+                    });
+                }
+                node::Variant::Light(color, radius, intensity) => {
+                    lights.push((*color, *radius, *intensity, transform_mx, node_ref.cast_shadow));
+                }
+                node::Variant::Emitter(emitter) => {
+                    emitters.push((emitter.clone(), transform_mx));
                 }
-                node::Variant::Light(color, radius) => {
-                    lights.push((*color, *radius, transform_mx));
+                node::Variant::Trail(trail) => {
+                    trails.push((trail.clone(), transform_mx));
                 }
                 _ => {}
             }
             for child in node.borrow().children.iter() {
                 let transform_mx = transform_mx * child.borrow().calculate_transform();
-                q.push((child.clone(), transform_mx));
+                q.push((child.clone(), transform_mx, culled));
             }
         }
+        let max_instances = self.frame.max_instances();
+        let dropped = nodes.len().saturating_sub(max_instances);
+        if dropped > 0 && !self.too_many_entities {
+            log::error!(
+                "the scene has {} entities but the device holds at most {max_instances} \
+                 instances, the rest are not drawn",
+                nodes.len()
+            );
+        }
+        self.too_many_entities = dropped > 0;
+        self.profiler.count_dropped(dropped as u32);
+        nodes.truncate(max_instances);
+        let mut shadow_count = 0;
         let lights: Vec<Light> = lights
             .into_iter()
-            .map(|(color, radius, transform)| {
-                let mut position = transform * Vec4::W;
-                position.w = radius;
+            .map(|(color, radius, intensity, transform, cast_shadow)| {
+                let shadow = if cast_shadow && shadow_count < MAX_SHADOW_LIGHTS {
+                    shadow_count += 1;
+                    shadow_count as i32 - 1
+                } else {
+                    -1
+                };
                 Light {
-                    position: position.xyz(),
+                    position: (transform * Vec4::W).xyz(),
                     radius,
                     color: Vec4::new(
                         color.r as f32,
@@ -248,31 +582,209 @@ impl Renderer {
                         color.b as f32,
                         color.a as f32,
                     ),
+                    shadow,
+                    intensity,
+                    _padding: [0; 2],
                 }
             })
             .collect();
-        let node_uniform_aligned = {
-            let node_uniform_size = size_of::<Mat4>() as BufferAddress;
-            let alignment =
-                self.device.limits().min_uniform_buffer_offset_alignment as BufferAddress;
-            align_to(node_uniform_size, alignment)
+        self.profiler.cpu("Uniforms");
+        let view_matrix = self.camera.get_view_matrix();
+        let eye_position = self.camera.get_eye_position();
+        let frame_uniforms = FrameUniforms {
+            view_proj: vp_matrix,
+            view: view_matrix,
+            eye_position: eye_position.extend(1.0),
+            time: self.time,
+            light_count: lights.len() as u32,
+            _padding: [0; 2],
         };
-        for (i, (geometry, shader, transform, rotation)) in nodes.iter().enumerate() {
-            let offset = (node_uniform_aligned * i as u64) as BufferAddress;
-            shader.set_pipeline(&mut rpass, offset);
-            shader.write_camera_data(&self.queue, vp_matrix.as_ref());
-            shader.write_light_data(&self.queue, &lights);
-            shader.write_time_data(&self.queue, self.time);
-            shader.write_transform_data(&self.queue, offset, transform.as_ref());
-            shader.write_rotation_data(&self.queue, offset, rotation.as_ref());
-            rpass.set_index_buffer(geometry.index_buffer.slice(..), IndexFormat::Uint32);
-            rpass.set_vertex_buffer(0, geometry.vertex_buffer.slice(..));
-            let n = geometry.indices.len() as u32;
-            rpass.draw_indexed(0..n, 0, 0..1);
+        let mut buffer_writes = self.frame.prepare(&self.queue, frame_uniforms, &lights);
+        // blended entities go over everything behind them, so they are drawn
+        // after the opaque ones, the furthest first
+        let (mut opaque, mut blended): (Vec<usize>, Vec<usize>) =
+            (0..nodes.len()).partition(|&i| nodes[i].blend.is_opaque());
+        // the visible ones of a batch stay next to each other, so culling
+        // leaves a single run of them to draw
+        opaque.sort_by_key(|&i| (nodes[i].batch_key(), !nodes[i].visible));
+        // the view looks down -z, the furthest are the most negative
+        let view_depth = |i: usize| view_matrix.transform_point3(nodes[i].transform.w_axis.xyz()).z;
+        blended.sort_by(|&a, &b| view_depth(a).total_cmp(&view_depth(b)));
+        let order: Vec<usize> = opaque.into_iter().chain(blended).collect();
+        let instances = order.iter().map(|&i| nodes[i].instance());
+        buffer_writes += self.frame.write_instances(&self.device, &self.queue, instances);
+        let batches = Batch::gather(&nodes, &order, |node| node.visible, DrawNode::batches_with);
+        // entities out of view may still cast shadows into it
+        let shadow_batches =
+            Batch::gather(&nodes, &order, |node| node.cast_shadow, DrawNode::batches_with);
+        self.profiler.count_culled(nodes.iter().filter(|node| !node.visible).count() as u32);
+        let opaque_batches = batches.partition_point(|batch| nodes[batch.node].blend.is_opaque());
+        let wireframe = self.debug.wireframe;
+        let mut memory = self.frame.memory_usage();
+        let gpu_culling = self.gpu_culling.as_mut().filter(|_| cull_on_gpu);
+        if let Some(culling) = gpu_culling {
+            let cull_batches: Vec<CullBatch> = batches[..opaque_batches]
+                .iter()
+                .map(|batch| {
+                    let node = &nodes[batch.node];
+                    let (_aabb, sphere) = Node::entity_bounds(&node.geometry, node.shader.as_ref());
+                    CullBatch {
+                        sphere,
+                        instances: batch.instances.clone(),
+                        index_count: node.indices(wireframe).1,
+                        triangles: node.geometry.indices.len() as u64 / 3,
+                    }
+                })
+                .collect();
+            let instances = self.frame.instance_buffer();
+            buffer_writes +=
+                culling.prepare(&self.device, &self.queue, &frustum, instances, &cull_batches);
+            memory += culling.memory_usage();
+            // as of a few frames before
+            let stats = culling.stats();
+            self.profiler.count_culled(stats.culled);
+            self.profiler.count_triangles(stats.triangles);
+        }
+        self.profiler.count_buffer_writes(buffer_writes);
+        self.profiler.count_instances(nodes.len() as u32, memory);
+        self.profiler.cpu("Shadows");
+        self.profiler.gpu(&mut encoder, "Shadows");
+        let shadow_passes = self.shadows.prepare(
+            &self.queue,
+            &self.camera,
+            aspect_ratio,
+            &self.sun,
+            &lights,
+            frame_uniforms,
+        );
+        for shadow_pass in shadow_passes {
+            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: shadow_pass.view,
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(1.0),
+                        store: StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                ..Default::default()
+            });
+            rpass.set_bind_group(1, &self.shadows.pass_bind_group, &[shadow_pass.offset]);
+            rpass.set_vertex_buffer(1, self.frame.instances());
+            for batch in &shadow_batches {
+                let node = &nodes[batch.node];
+                if !node.shader.set_shadow_pipeline(&mut rpass) {
+                    continue;
+                }
+                let geometry = &node.geometry;
+                rpass.set_index_buffer(geometry.index_buffer.slice(..), IndexFormat::Uint32);
+                rpass.set_vertex_buffer(0, geometry.vertex_buffer.slice(..));
+                let n = geometry.indices.len() as u32;
+                rpass.draw_indexed(0..n, 0, batch.instances.clone());
+                self.profiler.count_draw(n as u64 / 3 * batch.instances.len() as u64);
+            }
+        }
+        self.profiler.cpu("Prepare");
+        self.profiler.gpu(&mut encoder, "Prepare");
+        self.environment.prepare(
+            &self.queue,
+            &mut encoder,
+            &self.sky,
+            &self.sun,
+            vp_matrix.inverse(),
+        );
+        self.fog_uniforms.prepare(&self.queue, &self.fog, eye_position);
+        if let Some(culling) = self.gpu_culling.as_mut().filter(|_| cull_on_gpu) {
+            culling.cull(&mut encoder);
+        }
+        let delta_time = std::mem::take(&mut self.delta_time);
+        self.trails.prepare(&self.device, &self.queue, &trails, delta_time);
+        if let Some(particles) = &mut self.particles {
+            particles.prepare(&self.device, &self.queue, &mut encoder, &emitters, delta_time);
+        }
+        self.gather_debug_lines(&nodes, &lights);
+        self.debug_draw.prepare(&self.device, &self.queue, vp_matrix);
+        for node in &nodes {
+            node.shader.update_pipelines(self);
+        }
+        // when multisampling, only the resolved samples are kept
+        self.profiler.cpu("Main pass");
+        self.profiler.gpu(&mut encoder, "Main pass");
+        let (color_view, resolve_target, color_store) = match &self.msaa_texture_view {
+            Some(msaa_view) => (msaa_view, Some(&self.post.hdr_view), StoreOp::Discard),
+            None => (&self.post.hdr_view, None, StoreOp::Store),
+        };
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: color_view,
+                resolve_target,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: color_store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.depth_texture_view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(DEPTH_CLEAR),
+                    store: StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            ..Default::default()
+        });
+        rpass.set_bind_group(1, &self.frame.bind_group, &[]);
+        rpass.set_bind_group(2, &self.scene_bind_group, &[]);
+        rpass.set_vertex_buffer(1, self.frame.instances());
+        let default_textures = &self.textures.default_set;
+        let gpu_culling = self.gpu_culling.as_ref().filter(|_| cull_on_gpu);
+        let (opaque_batches, blended_batches) = batches.split_at(opaque_batches);
+        for (i, batch) in opaque_batches.iter().enumerate() {
+            let node = &nodes[batch.node];
+            let instances = match gpu_culling {
+                Some(culling) => DrawInstances::Indirect {
+                    instances: culling.instances(batch.instances.clone()),
+                    args: culling.draw_args(),
+                    offset: i as BufferAddress * DRAW_ARGS_SIZE,
+                },
+                None => DrawInstances::Range(batch.instances.clone()),
+            };
+            let triangles = node.draw(&mut rpass, instances, default_textures, wireframe);
+            // those the GPU kept are counted from its culling stats
+            let instance_count = if gpu_culling.is_some() { 0 } else { batch.instances.len() };
+            self.profiler.count_draw(triangles * instance_count as u64);
+        }
+        // the background only fills what the opaque entities left empty,
+        // blended surfaces must go over it
+        self.environment.draw_background(&mut rpass);
+        self.profiler.count_draw(1);
+        rpass.set_bind_group(1, &self.frame.bind_group, &[]);
+        rpass.set_bind_group(2, &self.scene_bind_group, &[]);
+        rpass.set_vertex_buffer(1, self.frame.instances());
+        for batch in blended_batches {
+            let node = &nodes[batch.node];
+            let instances = DrawInstances::Range(batch.instances.clone());
+            let triangles = node.draw(&mut rpass, instances, default_textures, wireframe);
+            self.profiler.count_draw(triangles * batch.instances.len() as u64);
         }
+        let mut draw_calls = self.trails.draw(&mut rpass, &trails);
+        if let Some(particles) = &self.particles {
+            draw_calls += particles.draw(&mut rpass, &emitters);
+        }
+        draw_calls += self.debug_draw.draw(&mut rpass, self.debug.on_top);
+        self.profiler.count_draws(draw_calls);
         drop(rpass);
+        self.profiler.cpu("Post");
+        self.profiler.gpu(&mut encoder, "Post");
+        let fxaa = self.anti_aliasing == AntiAliasing::Fxaa;
+        self.post.resolve(&self.queue, &mut encoder, &view, &self.tone_mapping, &self.bloom, fxaa);
 
         // Run egui
+        self.profiler.cpu("Egui");
+        self.profiler.gpu(&mut encoder, "Egui");
         let raw_input = self.egui_state.take_egui_input(&self.window);
         let full_output = self.egui_context.run(raw_input, |ctx| {
             run_ui(ctx, &mut self.regenerate_path);
@@ -314,9 +826,61 @@ impl Renderer {
             let mut rpass = rpass.forget_lifetime();
             self.egui_renderer.render(&mut rpass, &paint_jobs, &screen_descriptor);
         }
+        self.profiler.count_draws(paint_jobs.len() as u32);
+        self.profiler.end_encoder(&mut encoder);
 
+        self.profiler.cpu("Submit");
         self.queue.submit(Some(encoder.finish()));
         frame.present();
+        if let Some(culling) = self.gpu_culling.as_mut().filter(|_| cull_on_gpu) {
+            culling.submitted();
+        }
+        self.profiler.end_frame();
+    }
+    /// Lines of the debug overlay for this frame, following its settings.
+    fn gather_debug_lines(&mut self, nodes: &[DrawNode], lights: &[Light]) {
+        let debug = self.debug;
+        let lines = &mut self.debug_draw;
+        if debug.grid {
+            lines.grid(debug.grid_size, debug.grid_spacing);
+        }
+        if debug.axes {
+            lines.axes(Mat4::IDENTITY, debug.axis_length);
+        }
+        if debug.light_radius {
+            for light in lights {
+                lines.sphere(light.position, light.radius, light.color.xyz().extend(1.0));
+            }
+        }
+        for node in nodes {
+            if debug.normals {
+                for vertex in &node.geometry.vertices {
+                    let position = Vec3::from_slice(&vertex.position[..3]);
+                    let normal = Vec3::from_slice(&vertex.normal[..3]);
+                    let (position, normal) = node.shader.deform_vertex(position, normal, self.time);
+                    let position = node.transform.transform_point3(position);
+                    let normal = node.rotation.transform_vector3(normal).normalize_or_zero();
+                    let color = (normal * 0.5 + 0.5).extend(1.0);
+                    lines.line(position, position + normal * debug.normal_length, color);
+                }
+            }
+            if !debug.path && !debug.frames {
+                continue;
+            }
+            let Some(path) = node.shader.path() else {
+                continue;
+            };
+            let frames: Vec<Mat4> = path.iter().map(|&frame| node.transform * frame).collect();
+            if debug.path {
+                let points: Vec<Vec3> = frames.iter().map(|frame| frame.w_axis.xyz()).collect();
+                lines.polyline(&points, Vec4::new(1.0, 0.8, 0.1, 1.0));
+            }
+            if debug.frames {
+                for &frame in frames.iter().step_by(debug.frame_step.max(1)) {
+                    lines.axes(frame, debug.frame_size);
+                }
+            }
+        }
     }
     pub fn add(&mut self, node: NodeRef) {
         self.root.borrow_mut().add_child(node);
@@ -337,3 +901,47 @@ impl Renderer {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for a `DrawNode`, the mesh it draws and whether it is
+    /// included.
+    type Node = (char, bool);
+
+    fn gather(nodes: &[Node], order: &[usize]) -> Vec<(usize, Range<u32>)> {
+        Batch::gather(nodes, order, |node| node.1, |a, b| a.0 == b.0)
+            .into_iter()
+            .map(|batch| (batch.node, batch.instances))
+            .collect()
+    }
+
+    #[test]
+    fn neighbours_of_the_same_mesh_merge() {
+        let nodes = [('a', true), ('a', true), ('b', true), ('b', true), ('a', true)];
+        let batches = gather(&nodes, &[0, 1, 2, 3, 4]);
+        assert_eq!(batches, [(0, 0..2), (2, 2..4), (4, 4..5)]);
+    }
+
+    #[test]
+    fn instances_follow_the_order() {
+        let nodes = [('a', true), ('b', true), ('a', true)];
+        let batches = gather(&nodes, &[1, 0, 2]);
+        assert_eq!(batches, [(1, 0..1), (0, 1..3)]);
+    }
+
+    #[test]
+    fn left_out_instances_split_a_batch() {
+        let nodes = [('a', true), ('a', false), ('a', true), ('a', true)];
+        let batches = gather(&nodes, &[0, 1, 2, 3]);
+        assert_eq!(batches, [(0, 0..1), (2, 2..4)]);
+    }
+
+    #[test]
+    fn nothing_included_gathers_nothing() {
+        let nodes = [('a', false), ('b', false)];
+        assert!(gather(&nodes, &[0, 1]).is_empty());
+        assert!(gather(&[], &[]).is_empty());
+    }
+}
@@ -0,0 +1,513 @@
+use crate::world::{Camera, FrameUniforms, Light, Projection};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+use std::f32::consts::FRAC_PI_2;
+use std::mem::size_of;
+use wgpu::util::align_to;
+use wgpu::{
+    AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer,
+    BufferAddress, BufferBinding, BufferBindingType, BufferDescriptor, BufferSize, BufferUsages,
+    CompareFunction, DepthBiasState, DepthStencilState, Device, DynamicOffset, Extent3d,
+    FilterMode, Queue, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages, StencilState,
+    TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType,
+    TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
+};
+
+pub const CASCADE_COUNT: usize = 3;
+pub const MAX_SHADOW_LIGHTS: usize = 4;
+const SHADOW_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+const SUN_SHADOW_SIZE: u32 = 2048;
+const POINT_SHADOW_SIZE: u32 = 512;
+/// Cascades cover the view frustum up to this distance from the eye
+const SUN_SHADOW_DISTANCE: f32 = 400.0;
+const POINT_SHADOW_NEAR: f32 = 0.5;
+const POINT_SHADOW_FAR: f32 = 400.0;
+/// Blend between uniform (0) and logarithmic (1) cascade splits
+const CASCADE_SPLIT_LAMBDA: f32 = 0.7;
+const PASS_COUNT: usize = CASCADE_COUNT + MAX_SHADOW_LIGHTS * 6;
+
+/// Directional light, shining along `direction` from infinitely far away.
+#[derive(Debug, Clone, Copy)]
+pub struct Sun {
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub enabled: bool,
+    pub cast_shadow: bool,
+}
+
+impl Default for Sun {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::new(0.4, 0.3, -1.0).normalize(),
+            color: Vec3::new(1.0, 0.95, 0.85),
+            intensity: 2.0,
+            enabled: true,
+            cast_shadow: true,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct ShadowUniforms {
+    // xyz: direction, w: 1 if the sun is on
+    sun_direction: Vec4,
+    // rgb: color times intensity, w: 1 if the sun casts shadows
+    sun_color: Vec4,
+    eye_position: Vec4,
+    eye_forward: Vec4,
+    // far end of each cascade along the view direction
+    cascade_splits: Vec4,
+    cascade_view_proj: [Mat4; CASCADE_COUNT],
+    // 6 cube faces per light, in +x -x +y -y +z -z order
+    point_view_proj: [Mat4; MAX_SHADOW_LIGHTS * 6],
+}
+
+/// Depth only render target for one shadow map layer, with the dynamic
+/// offset of its frame uniforms in the pass bind group.
+pub struct ShadowPass<'a> {
+    pub view: &'a TextureView,
+    pub offset: DynamicOffset,
+}
+
+/// Cascaded shadow maps for the sun and cube shadow maps for point lights.
+///
+/// Lit pipelines sample the maps in group 2, at the bindings of
+/// [`Shadows::layout_entries`].
+/// Shadow casting pipelines bind `pass_bind_group_layout` as group 1 in
+/// place of the frame, its only binding being the frame uniforms with the
+/// light's view projection.
+pub struct Shadows {
+    pub pass_bind_group_layout: BindGroupLayout,
+    pub pass_bind_group: BindGroup,
+    uniform_buffer: Buffer,
+    pass_buffer: Buffer,
+    pass_stride: BufferAddress,
+    sun_map_view: TextureView,
+    point_map_view: TextureView,
+    sampler: Sampler,
+    sun_views: Vec<TextureView>,
+    point_views: Vec<TextureView>,
+}
+
+impl Shadows {
+    pub fn new(device: &Device) -> Self {
+        let create_map = |label, size, layers| {
+            device.create_texture(&TextureDescriptor {
+                label: Some(label),
+                size: Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: layers,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: SHADOW_FORMAT,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+        };
+        let sun_map = create_map("Sun Shadow Map", SUN_SHADOW_SIZE, CASCADE_COUNT as u32);
+        // one spare layer, GL backends treat square textures with a multiple
+        // of 6 layers as cube arrays and refuse to view them as 2d arrays
+        let point_map = create_map(
+            "Point Shadow Map",
+            POINT_SHADOW_SIZE,
+            (MAX_SHADOW_LIGHTS * 6 + 1) as u32,
+        );
+        let layer_views = |texture: &wgpu::Texture, layers: usize| {
+            (0..layers as u32)
+                .map(|layer| {
+                    texture.create_view(&TextureViewDescriptor {
+                        dimension: Some(TextureViewDimension::D2),
+                        base_array_layer: layer,
+                        array_layer_count: Some(1),
+                        ..Default::default()
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let sun_views = layer_views(&sun_map, CASCADE_COUNT);
+        let point_views = layer_views(&point_map, MAX_SHADOW_LIGHTS * 6);
+        let array_view = |texture: &wgpu::Texture| {
+            texture.create_view(&TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::D2Array),
+                aspect: TextureAspect::DepthOnly,
+                ..Default::default()
+            })
+        };
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            compare: Some(CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Shadow Uniforms"),
+            size: size_of::<ShadowUniforms>() as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pass_stride = align_to(
+            size_of::<FrameUniforms>() as BufferAddress,
+            device.limits().min_uniform_buffer_offset_alignment as BufferAddress,
+        );
+        let pass_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Shadow Pass Frame Uniforms"),
+            size: pass_stride * PASS_COUNT as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pass_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Shadow Pass Bind Group Layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0, // frame uniforms
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: BufferSize::new(size_of::<FrameUniforms>() as u64),
+                },
+                count: None,
+            }],
+        });
+        let pass_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Shadow Pass Bind Group"),
+            layout: &pass_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &pass_buffer,
+                    offset: 0,
+                    size: BufferSize::new(size_of::<FrameUniforms>() as u64),
+                }),
+            }],
+        });
+        Self {
+            sun_map_view: array_view(&sun_map),
+            point_map_view: array_view(&point_map),
+            sampler,
+            uniform_buffer,
+            pass_bind_group_layout,
+            pass_bind_group,
+            pass_buffer,
+            pass_stride,
+            sun_views,
+            point_views,
+        }
+    }
+
+    /// Entries 0 to 3 of the scene bind group layout: the shadow uniforms,
+    /// both shadow maps and their comparison sampler.
+    pub fn layout_entries() -> [BindGroupLayoutEntry; 4] {
+        let depth_array_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Depth,
+                view_dimension: TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+            count: None,
+        };
+        [
+            BindGroupLayoutEntry {
+                binding: 0, // shadow uniforms
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(size_of::<ShadowUniforms>() as u64),
+                },
+                count: None,
+            },
+            depth_array_entry(1), // sun shadow map
+            depth_array_entry(2), // point shadow map
+            BindGroupLayoutEntry {
+                binding: 3, // comparison sampler
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Comparison),
+                count: None,
+            },
+        ]
+    }
+
+    pub fn bind_group_entries(&self) -> [BindGroupEntry<'_>; 4] {
+        [
+            BindGroupEntry {
+                binding: 0,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&self.sun_map_view),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(&self.point_map_view),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+
+    /// Depth state for shadow casting pipelines. Shadow maps use regular
+    /// depth, unlike the reversed depth of the main pass.
+    pub fn depth_stencil_state() -> DepthStencilState {
+        DepthStencilState {
+            format: SHADOW_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::Less,
+            stencil: StencilState::default(),
+            bias: DepthBiasState {
+                constant: 2,
+                slope_scale: 2.0,
+                clamp: 0.0,
+            },
+        }
+    }
+
+    /// Fit the shadow maps to this frame's camera and lights, upload the
+    /// matrices and return the passes that need rendering. Lights with a
+    /// non negative `shadow` index get a cube shadow map. Each pass gets a
+    /// copy of `frame` seen from its light.
+    pub fn prepare(
+        &self,
+        queue: &Queue,
+        camera: &Camera,
+        aspect_ratio: f32,
+        sun: &Sun,
+        lights: &[Light],
+        frame: FrameUniforms,
+    ) -> Vec<ShadowPass<'_>> {
+        let mut passes = Vec::new();
+        let mut pass_matrices = [Mat4::IDENTITY; PASS_COUNT];
+        let mut uniforms = ShadowUniforms::zeroed();
+        let view = camera.get_view_matrix().inverse();
+        let eye = view.w_axis.xyz();
+        let forward = -view.z_axis.xyz();
+        uniforms.eye_position = eye.extend(1.0);
+        uniforms.eye_forward = forward.extend(0.0);
+        let sun_shadow = sun.enabled && sun.cast_shadow;
+        uniforms.sun_direction = sun.direction.normalize_or(-Vec3::Z).extend(sun.enabled as u32 as f32);
+        uniforms.sun_color = (sun.color * sun.intensity).extend(sun_shadow as u32 as f32);
+        let shadow_distance = camera.far.min(SUN_SHADOW_DISTANCE);
+        let splits = Self::cascade_splits(camera.near, shadow_distance);
+        let mut cascade_splits = [shadow_distance; 4];
+        cascade_splits[..CASCADE_COUNT].copy_from_slice(&splits[1..]);
+        uniforms.cascade_splits = Vec4::from_array(cascade_splits);
+        if sun_shadow {
+            for i in 0..CASCADE_COUNT {
+                let (near, far) = (splits[i], splits[i + 1]);
+                let view_proj =
+                    Self::fit_cascade(camera, &view, aspect_ratio, sun.direction, near, far);
+                uniforms.cascade_view_proj[i] = view_proj;
+                pass_matrices[i] = view_proj;
+                passes.push(ShadowPass {
+                    view: &self.sun_views[i],
+                    offset: (self.pass_stride * i as BufferAddress) as DynamicOffset,
+                });
+            }
+        }
+        const FACES: [(Vec3, Vec3); 6] = [
+            (Vec3::X, Vec3::NEG_Y),
+            (Vec3::NEG_X, Vec3::NEG_Y),
+            (Vec3::Y, Vec3::Z),
+            (Vec3::NEG_Y, Vec3::NEG_Z),
+            (Vec3::Z, Vec3::NEG_Y),
+            (Vec3::NEG_Z, Vec3::NEG_Y),
+        ];
+        for light in lights.iter().filter(|light| light.shadow >= 0) {
+            let far = light.radius.clamp(POINT_SHADOW_NEAR * 2.0, POINT_SHADOW_FAR);
+            let projection = Mat4::perspective_rh(FRAC_PI_2, 1.0, POINT_SHADOW_NEAR, far);
+            for (face, (direction, up)) in FACES.iter().enumerate() {
+                let layer = light.shadow as usize * 6 + face;
+                let view_proj = projection * Mat4::look_to_rh(light.position, *direction, *up);
+                uniforms.point_view_proj[layer] = view_proj;
+                let pass = CASCADE_COUNT + layer;
+                pass_matrices[pass] = view_proj;
+                passes.push(ShadowPass {
+                    view: &self.point_views[layer],
+                    offset: (self.pass_stride * pass as BufferAddress) as DynamicOffset,
+                });
+            }
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        // every pass staged together, `pass_stride` apart, for a single write
+        let stride = self.pass_stride as usize;
+        let mut staging = vec![0; PASS_COUNT * stride];
+        for (view_proj, chunk) in pass_matrices.iter().zip(staging.chunks_exact_mut(stride)) {
+            let frame = FrameUniforms {
+                view_proj: *view_proj,
+                ..frame
+            };
+            chunk[..size_of::<FrameUniforms>()].copy_from_slice(bytemuck::bytes_of(&frame));
+        }
+        queue.write_buffer(&self.pass_buffer, 0, &staging);
+        passes
+    }
+
+    /// View depths bounding the cascades, from `near` to `far`, blending
+    /// uniform and logarithmic splits.
+    fn cascade_splits(near: f32, far: f32) -> [f32; CASCADE_COUNT + 1] {
+        let mut splits = [near; CASCADE_COUNT + 1];
+        for (i, split) in splits.iter_mut().enumerate().skip(1) {
+            let k = i as f32 / CASCADE_COUNT as f32;
+            let uniform = near + (far - near) * k;
+            let logarithmic = near * (far / near).powf(k);
+            *split = uniform + (logarithmic - uniform) * CASCADE_SPLIT_LAMBDA;
+        }
+        splits
+    }
+
+    /// Orthographic light projection enclosing the slice of the view
+    /// frustum between `near` and `far`. The enclosing sphere keeps the
+    /// size constant as the camera turns and the center snaps to whole
+    /// texels, so shadow edges do not shimmer.
+    fn fit_cascade(
+        camera: &Camera,
+        view: &Mat4,
+        aspect_ratio: f32,
+        direction: Vec3,
+        near: f32,
+        far: f32,
+    ) -> Mat4 {
+        let half_height = |depth: f32| match camera.projection {
+            Projection::Perspective => depth * (camera.fov * 0.5).tan(),
+            Projection::Orthographic => camera.distance * (camera.fov * 0.5).tan(),
+        };
+        let corners = [near, far].into_iter().flat_map(|depth| {
+            let h = half_height(depth);
+            let w = h * aspect_ratio;
+            [(-w, -h), (w, -h), (-w, h), (w, h)]
+                .map(|(x, y)| view.transform_point3(Vec3::new(x, y, -depth)))
+        });
+        let corners = corners.collect::<Vec<_>>();
+        let center = corners.iter().copied().sum::<Vec3>() / corners.len() as f32;
+        let radius = corners
+            .iter()
+            .map(|corner| corner.distance(center))
+            .fold(0.0, f32::max)
+            .ceil();
+        let direction = direction.normalize_or(-Vec3::Z);
+        let up = if direction.z.abs() > 0.99 { Vec3::Y } else { Vec3::Z };
+        // pull the eye back so casters outside the slice still land in the map
+        let back = radius + SUN_SHADOW_DISTANCE;
+        let light_view = Mat4::look_to_rh(center - direction * back, direction, up);
+        let mut projection =
+            Mat4::orthographic_rh(-radius, radius, -radius, radius, 0.0, back + radius);
+        let half_size = SUN_SHADOW_SIZE as f32 * 0.5;
+        let origin = (projection * light_view).project_point3(Vec3::ZERO).truncate() * half_size;
+        let snap = (origin.round() - origin) / half_size;
+        projection.w_axis.x += snap.x;
+        projection.w_axis.y += snap.y;
+        projection * light_view
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASPECT_RATIO: f32 = 1.5;
+
+    fn sun_direction() -> Vec3 {
+        Sun::default().direction
+    }
+
+    /// Corners of the view frustum slice between `near` and `far`.
+    fn slice_corners(camera: &Camera, near: f32, far: f32) -> Vec<Vec3> {
+        let view = camera.get_view_matrix().inverse();
+        [near, far]
+            .into_iter()
+            .flat_map(|depth| {
+                let h = depth * (camera.fov * 0.5).tan();
+                let w = h * ASPECT_RATIO;
+                [(-w, -h), (w, -h), (-w, h), (w, h)]
+                    .map(|(x, y)| view.transform_point3(Vec3::new(x, y, -depth)))
+            })
+            .collect()
+    }
+
+    fn fit(camera: &Camera, near: f32, far: f32) -> Mat4 {
+        let view = camera.get_view_matrix().inverse();
+        Shadows::fit_cascade(camera, &view, ASPECT_RATIO, sun_direction(), near, far)
+    }
+
+    #[test]
+    fn splits_cover_near_to_far() {
+        let splits = Shadows::cascade_splits(1.0, 400.0);
+        assert_eq!(splits[0], 1.0);
+        assert!((splits[CASCADE_COUNT] - 400.0).abs() < 1e-3);
+        for i in 1..=CASCADE_COUNT {
+            let k = i as f32 / CASCADE_COUNT as f32;
+            let uniform = 1.0 + 399.0 * k;
+            let logarithmic = 400.0f32.powf(k);
+            assert!(splits[i] > splits[i - 1]);
+            // closer cascades are smaller than uniform splits would make them
+            assert!(splits[i] <= uniform + 1e-3 && splits[i] >= logarithmic - 1e-3);
+        }
+    }
+
+    #[test]
+    fn cascade_encloses_its_slice() {
+        let camera = Camera::new();
+        let splits = Shadows::cascade_splits(camera.near, SUN_SHADOW_DISTANCE);
+        for i in 0..CASCADE_COUNT {
+            let (near, far) = (splits[i], splits[i + 1]);
+            let view_proj = fit(&camera, near, far);
+            for corner in slice_corners(&camera, near, far) {
+                let p = view_proj.project_point3(corner);
+                assert!(p.x.abs() <= 1.0 && p.y.abs() <= 1.0, "{corner} -> {p}");
+                assert!((0.0..=1.0).contains(&p.z), "{corner} -> {p}");
+            }
+        }
+    }
+
+    #[test]
+    fn cascade_size_does_not_change_as_camera_turns() {
+        let mut camera = Camera::new();
+        let before = fit(&camera, 1.0, 50.0);
+        camera.rotate(1.3, -0.4);
+        let after = fit(&camera, 1.0, 50.0);
+        // the scale of the orthographic projection is 1 / radius
+        assert_eq!(before.x_axis.truncate().length(), after.x_axis.truncate().length());
+    }
+
+    #[test]
+    fn cascade_snaps_to_whole_texels() {
+        let half_size = SUN_SHADOW_SIZE as f32 * 0.5;
+        let mut camera = Camera::new();
+        for step in 0..8 {
+            camera.target = Vec3::new(0.37, -1.21, 0.5) * step as f32;
+            camera.rotate(0.21, 0.0);
+            let view_proj = fit(&camera, 1.0, 50.0);
+            let origin = view_proj.project_point3(Vec3::ZERO).truncate() * half_size;
+            assert!((origin - origin.round()).abs().max_element() < 1e-2, "{origin}");
+        }
+    }
+
+    #[test]
+    fn moving_less_than_a_texel_keeps_the_grid() {
+        let half_size = SUN_SHADOW_SIZE as f32 * 0.5;
+        let mut camera = Camera::new();
+        let before = fit(&camera, 1.0, 50.0);
+        camera.target += Vec3::new(0.01, 0.0, 0.0);
+        let after = fit(&camera, 1.0, 50.0);
+        // any world point still lands on the same texel grid, offset by whole texels
+        for point in [Vec3::ZERO, Vec3::new(10.0, -4.0, 2.0), Vec3::new(-7.0, 3.0, 0.0)] {
+            let a = before.project_point3(point).truncate() * half_size;
+            let b = after.project_point3(point).truncate() * half_size;
+            let shift = b - a;
+            assert!((shift - shift.round()).abs().max_element() < 1e-2, "{shift}");
+        }
+    }
+}
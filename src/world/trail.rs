@@ -0,0 +1,354 @@
+use crate::world::{DEPTH_COMPARE, HDR_FORMAT};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3, Vec4};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::mem::size_of;
+use std::rc::Rc;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferAddress, BufferBindingType,
+    BufferDescriptor, BufferSize, BufferUsages, ColorTargetState, ColorWrites, DepthBiasState,
+    DepthStencilState, Device, FragmentState, MultisampleState, PipelineCompilationOptions,
+    PipelineLayout, PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology, Queue,
+    RenderPass, RenderPipeline, RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, StencilState, TextureFormat, VertexAttribute,
+    VertexBufferLayout, VertexState, VertexStepMode, vertex_attr_array,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrailSettings {
+    pub enabled: bool,
+    /// Seconds of history the points cover, for whoever samples them
+    pub duration: f32,
+    /// Width at the start, in units
+    pub width: f32,
+    /// Width at the end, relative to `width`
+    pub end_width: f32,
+    /// Linear color
+    pub color: Vec3,
+    /// Scales the color, above 1 the trail blooms
+    pub intensity: f32,
+    pub start_alpha: f32,
+    pub end_alpha: f32,
+    /// Streaks per unit of length
+    pub uv_scale: f32,
+    /// Streaks scrolling past per second, towards the end of the trail
+    pub scroll_speed: f32,
+    /// How much the streaks cut into the alpha, 0 is a plain ribbon
+    pub streaks: f32,
+}
+
+impl Default for TrailSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            duration: 1.0,
+            width: 4.0,
+            end_width: 0.2,
+            color: Vec3::ONE,
+            intensity: 1.0,
+            start_alpha: 0.8,
+            end_alpha: 0.0,
+            uv_scale: 0.05,
+            scroll_speed: 2.0,
+            streaks: 0.5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct TrailVertex {
+    // xyz: point, w: edge
+    position: Vec4,
+    // xyz: towards older points, w: 0 to 1 along the trail
+    tangent: Vec4,
+    distance: f32,
+    _padding: [f32; 3],
+}
+
+impl TrailVertex {
+    const ATTRIBUTES: [VertexAttribute; 3] =
+        vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32];
+
+    fn desc() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: size_of::<Self>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct TrailUniforms {
+    color: Vec4,
+    start_alpha: f32,
+    end_alpha: f32,
+    width: f32,
+    end_width: f32,
+    uv_scale: f32,
+    scroll: f32,
+    streaks: f32,
+    _padding: f32,
+}
+
+struct TrailBuffers {
+    vertex_buffer: Buffer,
+    uniform_buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+pub type TrailRef = Rc<RefCell<Trail>>;
+
+/// Ribbon through a list of points, newest first, widening and fading
+/// along it by its settings. Whoever owns it updates the points, usually
+/// every frame from the history of something moving.
+pub struct Trail {
+    pub settings: TrailSettings,
+    points: Vec<Vec3>,
+    capacity: usize,
+    /// How far the streaks have scrolled, in streaks
+    scroll: f32,
+    /// Created by the renderer the first time it draws the trail
+    buffers: Option<TrailBuffers>,
+}
+
+impl Trail {
+    pub fn new(settings: TrailSettings, capacity: usize) -> TrailRef {
+        Rc::new(RefCell::new(Self {
+            settings,
+            points: Vec::with_capacity(capacity),
+            capacity: capacity.max(2),
+            scroll: 0.0,
+            buffers: None,
+        }))
+    }
+
+    /// Points the trail can hold, the rest are dropped.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Replace the points, newest first, in the space of the trail's node.
+    pub fn set_points(&mut self, points: impl IntoIterator<Item = Vec3>) {
+        self.points.clear();
+        self.points.extend(points.into_iter().take(self.capacity));
+    }
+}
+
+/// Draws trails at the end of the main pass, blended over the opaque scene
+/// and tested against its depth without writing it.
+pub struct Trails {
+    trail_layout: BindGroupLayout,
+    layout: PipelineLayout,
+    module: ShaderModule,
+    pipeline: RenderPipeline,
+}
+
+impl Trails {
+    /// `frame_layout` and `scene_layout` are the renderer's groups 1 and 2,
+    /// for the camera and the fog.
+    pub fn new(
+        device: &Device,
+        frame_layout: &BindGroupLayout,
+        scene_layout: &BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let trail_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Trail Bind Group Layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX_FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(size_of::<TrailUniforms>() as u64),
+                },
+                count: None,
+            }],
+        });
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Trail Shader"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(concat!(
+                include_str!("../material/frame.wgsl"),
+                include_str!("../material/environment.wgsl"),
+                include_str!("../material/fog.wgsl"),
+                include_str!("trail.wgsl")
+            ))),
+        });
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&trail_layout, frame_layout, scene_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &layout, &module, sample_count);
+        Self {
+            trail_layout,
+            layout,
+            module,
+            pipeline,
+        }
+    }
+
+    /// Rebuild the pipeline for a new sample count of the main pass.
+    pub fn update_pipeline(&mut self, device: &Device, sample_count: u32) {
+        self.pipeline = Self::create_pipeline(device, &self.layout, &self.module, sample_count);
+    }
+
+    /// Upload the points of `trails`, each with the world transform of its
+    /// node, and scroll their streaks by `delta_time` seconds.
+    pub fn prepare(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        trails: &[(TrailRef, Mat4)],
+        delta_time: f32,
+    ) {
+        for (trail, transform) in trails {
+            let mut trail = trail.borrow_mut();
+            if trail.buffers.is_none() {
+                trail.buffers = Some(self.create_buffers(device, trail.capacity));
+            }
+            let settings = trail.settings;
+            trail.scroll = (trail.scroll + settings.scroll_speed * delta_time).fract();
+            let points: Vec<Vec3> =
+                trail.points.iter().map(|&p| transform.transform_point3(p)).collect();
+            let last = points.len().saturating_sub(1).max(1) as f32;
+            let mut distance = 0.0;
+            let mut vertices = Vec::with_capacity(points.len() * 2);
+            for (i, &point) in points.iter().enumerate() {
+                if i > 0 {
+                    distance += point.distance(points[i - 1]);
+                }
+                let newer = points[i.saturating_sub(1)];
+                let older = points[(i + 1).min(points.len() - 1)];
+                let tangent = (older - newer).normalize_or(Vec3::X);
+                for edge in [-1.0, 1.0] {
+                    vertices.push(TrailVertex {
+                        position: point.extend(edge),
+                        tangent: tangent.extend(i as f32 / last),
+                        distance,
+                        _padding: [0.0; 3],
+                    });
+                }
+            }
+            let uniforms = TrailUniforms {
+                color: (settings.color * settings.intensity).extend(1.0),
+                start_alpha: settings.start_alpha,
+                end_alpha: settings.end_alpha,
+                width: settings.width,
+                end_width: settings.end_width,
+                uv_scale: settings.uv_scale,
+                scroll: trail.scroll,
+                streaks: settings.streaks,
+                _padding: 0.0,
+            };
+            let Some(buffers) = &trail.buffers else {
+                continue;
+            };
+            queue.write_buffer(&buffers.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+            queue.write_buffer(&buffers.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        }
+    }
+
+    /// Draw `trails`, must come after the background in the main pass with
+    /// the frame and scene bind groups in groups 1 and 2. Returns the number
+    /// of draw calls.
+    pub fn draw(&self, pass: &mut RenderPass<'_>, trails: &[(TrailRef, Mat4)]) -> u32 {
+        pass.set_pipeline(&self.pipeline);
+        let mut draw_calls = 0;
+        for (trail, _) in trails {
+            let trail = trail.borrow();
+            let Some(buffers) = &trail.buffers else {
+                continue;
+            };
+            if !trail.settings.enabled || trail.points.len() < 2 {
+                continue;
+            }
+            pass.set_bind_group(0, &buffers.bind_group, &[]);
+            pass.set_vertex_buffer(0, buffers.vertex_buffer.slice(..));
+            pass.draw(0..trail.points.len() as u32 * 2, 0..1);
+            draw_calls += 1;
+        }
+        draw_calls
+    }
+
+    fn create_buffers(&self, device: &Device, capacity: usize) -> TrailBuffers {
+        let vertex_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Trail Vertex Buffer"),
+            size: (size_of::<TrailVertex>() * capacity * 2) as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Trail Uniform Buffer"),
+            size: size_of::<TrailUniforms>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Trail Bind Group"),
+            layout: &self.trail_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+        TrailBuffers {
+            vertex_buffer,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        layout: &PipelineLayout,
+        module: &ShaderModule,
+        sample_count: u32,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Trail Pipeline"),
+            layout: Some(layout),
+            vertex: VertexState {
+                module,
+                entry_point: Some("vs_trail"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[TrailVertex::desc()],
+            },
+            fragment: Some(FragmentState {
+                module,
+                entry_point: Some("fs_trail"),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            // seen from both sides, the strip turns with the camera anyway
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            // hidden behind the opaque scene, without hiding what is blended
+            // after it
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: DEPTH_COMPARE,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+}
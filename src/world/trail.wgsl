@@ -0,0 +1,65 @@
+// Prepended with material/frame.wgsl, material/environment.wgsl and
+// material/fog.wgsl, for the frame and scene bind groups in groups 1 and 2.
+// Draws a trail as a strip of two vertices per point, pushed apart sideways
+// to face the camera.
+const TAU = 6.28318531;
+
+struct VertexInput {
+    // xyz: point of the trail, w: -1 or 1 for either edge
+    @location(0) position: vec4f,
+    // xyz: direction towards the older points, w: 0 at the start, 1 at the end
+    @location(1) tangent: vec4f,
+    // distance along the trail from its start
+    @location(2) distance: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) world_position: vec3f,
+    @location(1) uv: vec2f,
+    @location(2) alpha: f32,
+};
+
+struct Trail {
+    // rgb: color times intensity
+    color: vec4f,
+    start_alpha: f32,
+    end_alpha: f32,
+    width: f32,
+    end_width: f32,
+    uv_scale: f32,
+    // distance the streaks have scrolled, in repeats
+    scroll: f32,
+    streaks: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> trail: Trail;
+
+@vertex
+fn vs_trail(input: VertexInput) -> VertexOutput {
+    var result: VertexOutput;
+    let along = input.tangent.w;
+    let to_eye = frame.eye_position.xyz - input.position.xyz;
+    var side = cross(input.tangent.xyz, to_eye);
+    if dot(side, side) < 1e-8 {
+        // looking straight down the trail, any side will do
+        side = cross(input.tangent.xyz, vec3(0.0, 0.0, 1.0));
+    }
+    let width = trail.width * mix(1.0, trail.end_width, along) * 0.5;
+    result.world_position = input.position.xyz + normalize(side) * input.position.w * width;
+    result.position = frame.view_proj * vec4(result.world_position, 1.0);
+    result.uv = vec2(input.distance * trail.uv_scale - trail.scroll, input.position.w);
+    result.alpha = mix(trail.start_alpha, trail.end_alpha, along);
+    return result;
+}
+
+// premultiplied alpha
+@fragment
+fn fs_trail(vertex: VertexOutput) -> @location(0) vec4f {
+    // soft edges across the ribbon, streaks scrolling along it
+    let edge = 1.0 - vertex.uv.y * vertex.uv.y;
+    let streak = mix(1.0, 0.5 + 0.5 * sin(vertex.uv.x * TAU), trail.streaks);
+    let alpha = saturate(vertex.alpha * edge * streak);
+    return vec4(apply_fog(trail.color.rgb, vertex.world_position) * alpha, alpha);
+}